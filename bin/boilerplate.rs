@@ -1,11 +1,11 @@
 use vangers::{
     config,
-    render::{ScreenTargets, COLOR_FORMAT, DEPTH_FORMAT},
+    render::{color_format, ScreenTargets, DEPTH_FORMAT},
 };
 
 use env_logger;
 use futures::executor::{LocalPool, LocalSpawner};
-use log::info;
+use log::{info, warn};
 use winit::{
     event,
     event_loop::{ControlFlow, EventLoop},
@@ -19,6 +19,13 @@ pub trait Application {
     fn on_mouse_button(&mut self, _state: event::ElementState, _button: event::MouseButton) {}
     fn resize(&mut self, _device: &wgpu::Device, _extent: wgpu::Extent3d) {}
     fn reload(&mut self, device: &wgpu::Device);
+    fn on_settings(
+        &mut self,
+        _device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+        _settings: &config::Settings,
+    ) {
+    }
     fn update(
         &mut self,
         device: &wgpu::Device,
@@ -33,6 +40,52 @@ pub trait Application {
     ) -> wgpu::CommandBuffer;
 }
 
+const SETTINGS_PATH: &str = "config/settings.ron";
+
+fn settings_modified() -> Option<std::time::SystemTime> {
+    std::fs::metadata(SETTINGS_PATH)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+#[cfg(feature = "reload")]
+struct ShaderWatcher {
+    _watcher: notify::RecommendedWatcher,
+    receiver: std::sync::mpsc::Receiver<notify::DebouncedEvent>,
+}
+
+#[cfg(feature = "reload")]
+impl ShaderWatcher {
+    fn new() -> Self {
+        use notify::Watcher as _;
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut watcher = notify::watcher(sender, std::time::Duration::from_millis(200))
+            .expect("Unable to create the shader watcher");
+        watcher
+            .watch("res/shader", notify::RecursiveMode::Recursive)
+            .expect("Unable to watch the shaders");
+        ShaderWatcher {
+            _watcher: watcher,
+            receiver,
+        }
+    }
+
+    // Drains the pending events, returning true if any shader source has changed.
+    // An include may feed any number of shaders, so the caller reloads all of them.
+    fn has_changes(&self) -> bool {
+        use notify::DebouncedEvent as De;
+
+        self.receiver.try_iter().fold(false, |changed, event| {
+            let path = match event {
+                De::Create(path) | De::Write(path) | De::Rename(_, path) => path,
+                _ => return changed,
+            };
+            changed || path.extension().map_or(false, |ext| ext == "glsl")
+        })
+    }
+}
+
 pub struct Harness {
     task_pool: LocalPool,
     event_loop: EventLoop<()>,
@@ -44,6 +97,8 @@ pub struct Harness {
     pub extent: wgpu::Extent3d,
     reload_on_focus: bool,
     depth_target: wgpu::TextureView,
+    present_mode: wgpu::PresentMode,
+    color_format: wgpu::TextureFormat,
 }
 
 impl Harness {
@@ -52,7 +107,20 @@ impl Harness {
         let mut task_pool = LocalPool::new();
 
         info!("Loading the settings");
-        let settings = config::Settings::load("config/settings.ron");
+        let settings = match config::Settings::load(SETTINGS_PATH) {
+            Ok(settings) => settings,
+            Err(config::settings::SettingsError::Io(ref e))
+                if e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                config::Settings::write_template(SETTINGS_PATH)
+                    .expect("Unable to write the settings template");
+                panic!(
+                    "Created `{}` from the template, please point `data_path` to the game resources",
+                    SETTINGS_PATH
+                );
+            }
+            Err(e) => panic!("{}", e),
+        };
         let extent = wgpu::Extent3d {
             width: settings.window.size[0],
             height: settings.window.size[1],
@@ -73,10 +141,15 @@ impl Harness {
         info!("Initializing the device");
         let adapter = task_pool
             .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::Default,
+                power_preference: settings.power_preference.to_wgpu(),
                 compatible_surface: Some(&surface),
             }))
             .expect("Unable to initialize GPU via the selected backend.");
+        let adapter_info = adapter.get_info();
+        info!(
+            "Using adapter {:?} ({:?}, {:?})",
+            adapter_info.name, adapter_info.backend, adapter_info.device_type
+        );
         let (device, queue) = task_pool
             .run_until(adapter.request_device(
                 &wgpu::DeviceDescriptor {
@@ -88,12 +161,24 @@ impl Harness {
             ))
             .unwrap();
 
+        // wgpu can't report the supported modes of a surface yet,
+        // and it silently falls back to Fifo for the unsupported ones
+        let present_mode = settings.render.present_mode.to_wgpu();
+        if present_mode == wgpu::PresentMode::Fifo {
+            info!("Using present mode {:?}", present_mode);
+        } else {
+            warn!(
+                "Requested present mode {:?}, unsupported modes fall back to Fifo",
+                present_mode
+            );
+        }
+        let color_format = color_format(settings.render.srgb);
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: COLOR_FORMAT,
+            format: color_format,
             width: extent.width,
             height: extent.height,
-            present_mode: wgpu::PresentMode::Mailbox,
+            present_mode,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
         let depth_target = device
@@ -119,6 +204,8 @@ impl Harness {
             extent,
             reload_on_focus: settings.window.reload_on_focus,
             depth_target,
+            present_mode,
+            color_format,
         };
 
         (harness, settings)
@@ -129,6 +216,9 @@ impl Harness {
 
         let mut last_time = time::Instant::now();
         let mut needs_reload = false;
+        let mut last_settings_modified = settings_modified();
+        #[cfg(feature = "reload")]
+        let shader_watcher = ShaderWatcher::new();
         let Harness {
             mut task_pool,
             event_loop,
@@ -140,6 +230,8 @@ impl Harness {
             mut extent,
             reload_on_focus,
             mut depth_target,
+            present_mode,
+            color_format,
         } = self;
 
         event_loop.run(move |event, _, control_flow| {
@@ -160,10 +252,10 @@ impl Harness {
                     };
                     let sc_desc = wgpu::SwapChainDescriptor {
                         usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-                        format: COLOR_FORMAT,
+                        format: color_format,
                         width: size.width,
                         height: size.height,
-                        present_mode: wgpu::PresentMode::Mailbox,
+                        present_mode,
                     };
                     swap_chain = device.create_swap_chain(&surface, &sc_desc);
                     depth_target = device
@@ -187,6 +279,16 @@ impl Harness {
                         info!("Reloading shaders");
                         app.reload(&device);
                         needs_reload = false;
+
+                        let modified = settings_modified();
+                        if modified != last_settings_modified {
+                            last_settings_modified = modified;
+                            info!("Reloading settings");
+                            match config::Settings::load(SETTINGS_PATH) {
+                                Ok(settings) => app.on_settings(&device, &queue, &settings),
+                                Err(e) => log::warn!("Unable to reload settings: {}", e),
+                            }
+                        }
                     }
                     event::WindowEvent::CloseRequested => {
                         *control_flow = ControlFlow::Exit;
@@ -206,6 +308,13 @@ impl Harness {
                     _ => {}
                 },
                 event::Event::MainEventsCleared => {
+                    #[cfg(feature = "reload")]
+                    {
+                        if shader_watcher.has_changes() {
+                            info!("Reloading shaders");
+                            app.reload(&device);
+                        }
+                    }
                     let spawner = task_pool.spawner();
                     let duration = time::Instant::now() - last_time;
                     last_time += duration;
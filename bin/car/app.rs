@@ -1,4 +1,4 @@
-use crate::boilerplate::Application;
+use crate::{boilerplate::Application, model_obj};
 use m3d::Mesh;
 use vangers::{config, level, model, render, space};
 
@@ -6,105 +6,482 @@ use futures::executor::LocalSpawner;
 use log::info;
 use wgpu::util::DeviceExt as _;
 
-use std::mem;
+use std::{collections::HashMap, env, fs, mem, ops::Range, path::PathBuf, time};
 
 pub struct CarView {
+    car_reg: HashMap<String, config::car::CarInfo>,
+    car_ids: Vec<String>,
+    car_index: usize,
+    model_paths: HashMap<String, PathBuf>,
     model: model::VisualModel,
     transform: space::Transform,
-    physics: config::car::CarPhysics,
+    // missing for the models loaded outside of the registry
+    physics: Option<config::car::CarPhysics>,
     color: render::object::BodyColor,
     debug_render: render::debug::Context,
     global: render::global::Context,
     object: render::object::Context,
-    cam: space::Camera,
-    rotation: (cgmath::Rad<f32>, cgmath::Rad<f32>),
+    camera: space::CameraController,
+    camera_input: space::CameraInput,
+    screen_size: (u16, u16),
+    mouse_look: Option<(f64, f64)>,
+    turntable: bool,
+    turntable_speed: cgmath::Rad<f32>,
     light_config: config::settings::Light,
+    capture_requested: bool,
+    export_requested: bool,
+    show_info: bool,
+    info_text: String,
+    show_grid: bool,
+    grid_spacing: f32,
+    show_bounds: bool,
+    show_shape: bool,
+    wheel_time: f32,
+    pending_capture: Option<(render::capture::PendingCapture, PathBuf)>,
 }
 
 impl CarView {
-    pub fn new(settings: &config::Settings, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+    pub fn new(
+        settings: &config::Settings,
+        model_path: Option<PathBuf>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
         info!("Initializing the render");
         let pal_data = level::read_palette(settings.open_palette(), None);
         let store_init = render::body::GpuStoreInit::new_dummy(device);
-        let global = render::global::Context::new(device, queue, store_init.resource(), None);
+        let global = render::global::Context::new(
+            device,
+            queue,
+            store_init.resource(),
+            None,
+            None,
+            render::RenderFormats::new(settings.render.srgb),
+            1,
+        );
         let object = render::object::Context::new(device, queue, &pal_data, &global);
 
-        info!("Loading car registry");
-        let game_reg = config::game::Registry::load(settings);
-        let car_reg = config::car::load_registry(settings, &game_reg, device, &object);
-        let cinfo = match car_reg.get(&settings.car.id) {
-            Some(ci) => ci,
+        let (car_reg, car_ids, model_paths, model, scale, physics) = match model_path {
+            Some(path) => {
+                info!("Loading model {:?}", path);
+                let file = fs::File::open(&path)
+                    .unwrap_or_else(|e| panic!("Unable to open {:?}: {}", path, e));
+                let model =
+                    model::load_m3d(file, device, &object, settings.game.physics.shape_sampling);
+                let id = path
+                    .file_stem()
+                    .map_or("model".to_string(), |s| s.to_string_lossy().into_owned());
+                let mut model_paths = HashMap::new();
+                model_paths.insert(id.clone(), path);
+                (HashMap::new(), vec![id], model_paths, model, 1.0, None)
+            }
             None => {
-                let names = car_reg.keys().collect::<Vec<_>>();
-                panic!("Unable to find `{}` in {:?}", settings.car.id, names);
+                info!("Loading car registry");
+                let game_reg = config::game::Registry::load(settings);
+                let car_reg = config::car::load_registry(settings, &game_reg, device, &object);
+                let cinfo = match car_reg.get(&settings.car.id) {
+                    Some(ci) => ci,
+                    None => {
+                        let names = car_reg.keys().collect::<Vec<_>>();
+                        panic!("Unable to find `{}` in {:?}", settings.car.id, names);
+                    }
+                };
+                let mut model = cinfo.model.clone();
+                let scale = cinfo.scale;
+                let physics = cinfo.physics.clone();
+                let car_ids = config::car::car_ids(&car_reg)
+                    .map(|(id, _)| id.to_string())
+                    .collect::<Vec<_>>();
+                let model_paths = car_ids
+                    .iter()
+                    .map(|id| {
+                        let path = settings.data_path.join(&game_reg.model_infos[id].path);
+                        (id.clone(), path)
+                    })
+                    .collect();
+                for (ms, sid) in model.slots.iter_mut().zip(settings.car.slots.iter()) {
+                    let info = &game_reg.model_infos[sid];
+                    let raw = Mesh::load(&mut settings.open_relative(&info.path)).unwrap();
+                    ms.mesh = Some(model::load_c3d(raw, device));
+                    ms.scale = info.scale;
+                }
+                (car_reg, car_ids, model_paths, model, scale, Some(physics))
             }
         };
-        let mut model = cinfo.model.clone();
-        for (ms, sid) in model.slots.iter_mut().zip(settings.car.slots.iter()) {
-            let info = &game_reg.model_infos[sid];
-            let raw = Mesh::load(&mut settings.open_relative(&info.path));
-            ms.mesh = Some(model::load_c3d(raw, device));
-            ms.scale = info.scale;
-        }
+        let car_index = car_ids
+            .iter()
+            .position(|id| *id == settings.car.id)
+            .unwrap_or(0);
 
-        CarView {
+        let transform = cgmath::Decomposed {
+            scale,
+            disp: cgmath::Vector3::unit_z(),
+            rot: cgmath::One::one(),
+        };
+        let orbit = space::OrbitController {
+            target: transform.disp,
+            distance: 64.0,
+            yaw: cgmath::Rad(0.0),
+            pitch: cgmath::Angle::turn_div_6(),
+        };
+        let cam = space::Camera {
+            loc: cgmath::vec3(0.0, -64.0, 32.0),
+            rot: cgmath::One::one(),
+            proj: Self::perspective(
+                settings.window.size[0] as f32 / settings.window.size[1] as f32,
+                1.0..100.0,
+            ),
+            jitter: (0.0, 0.0),
+        };
+        let mut camera = space::CameraController::new(cam, orbit.target);
+        camera.mode = space::CameraMode::Orbit(orbit);
+        let mut view = CarView {
+            car_reg,
+            car_ids,
+            car_index,
+            model_paths,
             model,
-            transform: cgmath::Decomposed {
-                scale: cinfo.scale,
-                disp: cgmath::Vector3::unit_z(),
-                rot: cgmath::One::one(),
-            },
-            physics: cinfo.physics.clone(),
+            transform,
+            physics,
             color: settings.car.color,
+            // the collision shape is toggled at run time
             debug_render: render::debug::Context::new(
                 device,
-                &settings.render.debug,
+                &config::settings::DebugRender {
+                    collision_shapes: true,
+                    ..settings.render.debug.clone()
+                },
                 &global,
                 &object,
             ),
             global,
             object,
-            cam: space::Camera {
-                loc: cgmath::vec3(0.0, -64.0, 32.0),
-                rot: cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
-                proj: space::Projection::Perspective(cgmath::PerspectiveFov {
-                    fovy: cgmath::Deg(45.0).into(),
-                    aspect: settings.window.size[0] as f32 / settings.window.size[1] as f32,
-                    near: 1.0,
-                    far: 100.0,
-                }),
-            },
-            rotation: (cgmath::Rad(0.), cgmath::Rad(0.)),
+            camera,
+            camera_input: space::CameraInput::default(),
+            screen_size: (
+                settings.window.size[0] as u16,
+                settings.window.size[1] as u16,
+            ),
+            mouse_look: None,
+            turntable: false,
+            turntable_speed: cgmath::Rad(0.5),
             light_config: settings.render.light.clone(),
+            capture_requested: false,
+            export_requested: false,
+            show_info: true,
+            info_text: String::new(),
+            show_grid: true,
+            grid_spacing: settings.render.debug.grid_spacing,
+            show_bounds: false,
+            show_shape: false,
+            wheel_time: 0.0,
+            pending_capture: None,
+        };
+        view.frame_model();
+        view.update_info();
+        view
+    }
+
+    fn update_info(&mut self) {
+        let body = &self.model.body;
+        let phys = &body.physics;
+        self.info_text = format!(
+            "{}\nvolume: {:.1}\nrcm: {:.2} {:.2} {:.2}\njacobi: {:.1} {:.1} {:.1}\nradius: {:.2}\nwheels: {}",
+            self.car_ids[self.car_index],
+            phys.volume,
+            phys.rcm[0],
+            phys.rcm[1],
+            phys.rcm[2],
+            phys.jacobi[0][0],
+            phys.jacobi[1][1],
+            phys.jacobi[2][2],
+            body.bbox.radius,
+            self.model.wheels.len(),
+        );
+        if let Some(ref physics) = self.physics {
+            self.info_text += &format!(
+                "\nscale: {:.2} {:.2} {:.2}",
+                physics.scale_size, physics.scale_bound, physics.scale_box,
+            );
         }
     }
 
-    fn rotate_z(&mut self, angle: cgmath::Rad<f32>) {
-        use cgmath::Transform;
-        let other = cgmath::Decomposed {
-            scale: 1.0,
-            rot: cgmath::Rotation3::from_angle_z(angle),
-            disp: cgmath::Zero::zero(),
+    fn layout_info(&self) -> render::debug::LineBuffer {
+        use cgmath::EuclideanSpace as _;
+
+        let mut linebuf = render::debug::LineBuffer::new();
+        if !self.show_info {
+            return linebuf;
+        }
+        // place the text right behind the near plane at the top left corner
+        let cell = 0.005;
+        let aspect = self.screen_size.0 as f32 / self.screen_size.1 as f32;
+        let at = |x: f32, y: f32| {
+            let (origin, dir) = self.camera.cam.cast_ray((x, y));
+            (origin + dir * 0.01).to_vec()
         };
-        self.transform = other.concat(&self.transform);
+        let (x, y) = (-0.95, 0.85);
+        let origin = at(x, y);
+        linebuf.add_text(
+            &self.info_text,
+            origin.into(),
+            (at(x + cell, y) - origin).into(),
+            (at(x, y + cell * aspect) - origin).into(),
+            0xFFFFFFFF,
+        );
+        linebuf
+    }
+
+    // Ground grid on the z=0 plane, covering the model, with the R/G/B axes,
+    // and the bounding box of the model.
+    fn layout_lines(&self) -> render::debug::LineBuffer {
+        let mut linebuf = render::debug::LineBuffer::new();
+        let (min, max) = model::world_bounds(&self.model, &self.transform);
+        if self.show_bounds {
+            let corner = |i: usize| {
+                [
+                    if i & 1 != 0 { max.x } else { min.x },
+                    if i & 2 != 0 { max.y } else { min.y },
+                    if i & 4 != 0 { max.z } else { min.z },
+                ]
+            };
+            for i in 0..8 {
+                for &axis in &[1, 2, 4] {
+                    if i & axis == 0 {
+                        linebuf.add(corner(i), corner(i | axis), 0xFF00FFFF);
+                    }
+                }
+            }
+        }
+        if !self.show_grid || self.grid_spacing <= 0.0 {
+            return linebuf;
+        }
+        let reach = [min.x, min.y, max.x, max.y]
+            .iter()
+            .fold(0f32, |r, v| r.max(v.abs()));
+        let cells = (reach / self.grid_spacing).ceil() as i32 + 1;
+        let half = cells as f32 * self.grid_spacing;
+        for i in -cells..=cells {
+            let offset = i as f32 * self.grid_spacing;
+            linebuf.add([offset, -half, 0.0], [offset, half, 0.0], 0xFF808080);
+            linebuf.add([-half, offset, 0.0], [half, offset, 0.0], 0xFF808080);
+        }
+        linebuf.add([0.0; 3], [half, 0.0, 0.0], 0xFF0000FF);
+        linebuf.add([0.0; 3], [0.0, half, 0.0], 0xFF00FF00);
+        linebuf.add([0.0; 3], [0.0, 0.0, half], 0xFFFF0000);
+        linebuf
+    }
+
+    fn frame_model(&mut self) {
+        use cgmath::{EuclideanSpace as _, InnerSpace as _};
+
+        // frame the whole model, including the wheels and slots
+        let (min, max) = model::world_bounds(&self.model, &self.transform);
+        let center = min.midpoint(max).to_vec();
+        // the free flight is left alone
+        let camera = &mut self.camera;
+        if let space::CameraMode::Orbit(ref mut orbit) = camera.mode {
+            orbit.target = center;
+            orbit.apply(&mut camera.cam);
+            camera
+                .cam
+                .frame_bounds(center, 0.5 * (max - min).magnitude());
+            orbit.distance = (camera.cam.loc - orbit.target).magnitude();
+        }
     }
 
-    fn rotate_x(&mut self, angle: cgmath::Rad<f32>) {
-        use cgmath::Transform;
-        let other = cgmath::Decomposed {
-            scale: 1.0,
-            rot: cgmath::Rotation3::from_angle_x(angle),
-            disp: cgmath::Zero::zero(),
+    fn cycle_car(&mut self, offset: isize) {
+        let count = self.car_ids.len() as isize;
+        self.car_index = ((self.car_index as isize + offset).rem_euclid(count)) as usize;
+        let id = &self.car_ids[self.car_index];
+        let cinfo = match self.car_reg.get(id) {
+            Some(cinfo) => cinfo,
+            // a single model loaded from the command line
+            None => return,
+        };
+        info!("Switching to car {}", id);
+        self.model = cinfo.model.clone();
+        self.physics = Some(cinfo.physics.clone());
+        self.transform.scale = cinfo.scale;
+        self.frame_model();
+        self.update_info();
+    }
+
+    fn export_model(&self) {
+        let id = &self.car_ids[self.car_index];
+        let dir_path = env::current_exe()
+            .unwrap()
+            .with_file_name(format!("{}-obj", id));
+        fs::create_dir_all(&dir_path).unwrap();
+        let file = fs::File::open(&self.model_paths[id]).unwrap();
+        let raw = m3d::FullModel::load(file).unwrap();
+        let model_path = dir_path.join(id).with_extension("ron");
+        model_obj::export_m3d(raw, &model_path);
+        info!("Exported car {} to {:?}", id, model_path);
+    }
+
+    fn draw_scene(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        batcher: &render::Batcher,
+        overlay: &render::debug::LineBuffer,
+        color: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        pass.set_bind_group(0, &self.global.bind_group, &[]);
+        pass.set_bind_group(1, &self.object.bind_group, &[]);
+
+        batcher.draw(
+            &mut pass,
+            &self.object.pipelines,
+            render::PipelineKind::Main,
+        );
+        batcher.draw_debug_shapes(&mut pass, &self.debug_render);
+        drop(pass);
+
+        // the debug buffers are borrowed by each draw, so the lines
+        // and the text need their own passes
+        let lines = self.layout_lines();
+        if !lines.is_empty() {
+            let mut pass = begin_overlay_pass(encoder, color, depth);
+            pass.set_bind_group(0, &self.global.bind_group, &[]);
+            self.debug_render.draw_lines(&mut pass, device, &lines);
+        }
+        let mut pass = begin_overlay_pass(encoder, color, depth);
+        pass.set_bind_group(0, &self.global.bind_group, &[]);
+        self.debug_render.draw_text(&mut pass, device, overlay);
+    }
+
+    fn perspective(aspect: f32, depth: Range<f32>) -> space::Projection {
+        space::Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect,
+            near: depth.start,
+            far: depth.end,
+        })
+    }
+
+    fn toggle_fly(&mut self) {
+        self.camera.toggle_mode();
+        self.mouse_look = None;
+    }
+
+    // Movement keys rotate around the model when orbiting.
+    fn on_camera_key(&mut self, key: winit::event::VirtualKeyCode, pressed: bool) -> bool {
+        use winit::event::VirtualKeyCode as Key;
+
+        let input = &mut self.camera_input;
+        let value = if pressed { 1.0 } else { 0.0 };
+        match key {
+            Key::A => input.movement.x = -value,
+            Key::D => input.movement.x = value,
+            Key::S => input.movement.y = -value,
+            Key::W => input.movement.y = value,
+            Key::LControl => input.movement.z = -value,
+            Key::Space => input.movement.z = value,
+            Key::LShift => input.boosted = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    fn toggle_projection(&mut self) {
+        use cgmath::{Angle as _, InnerSpace as _};
+
+        let (w, h) = self.screen_size;
+        let distance = match self.camera.mode {
+            space::CameraMode::Orbit(ref orbit) => orbit.distance,
+            space::CameraMode::Fly(_) => (self.camera.cam.loc - self.transform.disp).magnitude(),
+        };
+        self.camera.cam.proj = match self.camera.cam.proj {
+            space::Projection::Perspective(pf) => {
+                // match the perspective footprint at the orbit target
+                let half_h = distance * (pf.fovy / 2.0).tan();
+                let half_w = half_h * pf.aspect;
+                space::Projection::Ortho {
+                    p: cgmath::Ortho {
+                        left: -half_w,
+                        right: half_w,
+                        bottom: -half_h,
+                        top: half_h,
+                        near: pf.near,
+                        far: pf.far,
+                    },
+                    original: (w, h),
+                }
+            }
+            space::Projection::Ortho { p, .. } => {
+                Self::perspective(w as f32 / h as f32, p.near..p.far)
+            }
         };
-        self.transform = self.transform.concat(&other);
     }
 }
 
+fn begin_overlay_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    color: &'a wgpu::TextureView,
+    depth: &'a wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+            attachment: color,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+            attachment: depth,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    })
+}
+
 impl Application for CarView {
     fn on_key(&mut self, input: winit::event::KeyboardInput) -> bool {
         use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
 
-        let angle = cgmath::Rad(2.0);
+        if let KeyboardInput {
+            state,
+            virtual_keycode: Some(key),
+            ..
+        } = input
+        {
+            if self.on_camera_key(key, state == ElementState::Pressed) {
+                return true;
+            }
+        }
+
         match input {
             KeyboardInput {
                 state: ElementState::Pressed,
@@ -112,19 +489,19 @@ impl Application for CarView {
                 ..
             } => match key {
                 Key::Escape => return false,
-                Key::A => self.rotation.0 = -angle,
-                Key::D => self.rotation.0 = angle,
-                Key::W => self.rotation.1 = -angle,
-                Key::S => self.rotation.1 = angle,
-                _ => (),
-            },
-            KeyboardInput {
-                state: ElementState::Released,
-                virtual_keycode: Some(key),
-                ..
-            } => match key {
-                Key::A | Key::D => self.rotation.0 = cgmath::Rad(0.),
-                Key::W | Key::S => self.rotation.1 = cgmath::Rad(0.),
+                Key::P => self.toggle_projection(),
+                Key::R => self.turntable = !self.turntable,
+                Key::Equals => self.turntable_speed = self.turntable_speed * 1.25,
+                Key::Minus => self.turntable_speed = self.turntable_speed / 1.25,
+                Key::PageUp => self.cycle_car(-1),
+                Key::PageDown => self.cycle_car(1),
+                Key::F12 => self.capture_requested = true,
+                Key::E => self.export_requested = true,
+                Key::I => self.show_info = !self.show_info,
+                Key::G => self.show_grid = !self.show_grid,
+                Key::B => self.show_bounds = !self.show_bounds,
+                Key::H => self.show_shape = !self.show_shape,
+                Key::F => self.toggle_fly(),
                 _ => (),
             },
             _ => {}
@@ -137,21 +514,74 @@ impl Application for CarView {
         &mut self,
         _device: &wgpu::Device,
         delta: f32,
-        _spawner: &LocalSpawner,
+        spawner: &LocalSpawner,
     ) -> Vec<wgpu::CommandBuffer> {
-        if self.rotation.0 != cgmath::Rad(0.) {
-            let rot = self.rotation.0 * delta;
-            self.rotate_z(rot);
+        if let Some((pending, path)) = self.pending_capture.take() {
+            pending.save(path, spawner);
         }
-        if self.rotation.1 != cgmath::Rad(0.) {
-            let rot = self.rotation.1 * delta;
-            self.rotate_x(rot);
+        if self.export_requested {
+            self.export_requested = false;
+            self.export_model();
         }
+        self.wheel_time += delta;
+        if self.turntable {
+            if let space::CameraMode::Orbit(ref mut orbit) = self.camera.mode {
+                orbit.auto_orbit(delta, self.turntable_speed);
+            }
+        }
+        self.camera.update(delta, &self.camera_input);
+        self.camera_input.look = (cgmath::Rad(0.0), cgmath::Rad(0.0));
+        self.camera_input.zoom = 0.0;
         Vec::new()
     }
 
+    fn on_mouse_button(
+        &mut self,
+        state: winit::event::ElementState,
+        button: winit::event::MouseButton,
+    ) {
+        // dragging with the left button orbits, the right one looks around in flight
+        let look_button = if self.camera.is_flying() {
+            winit::event::MouseButton::Right
+        } else {
+            winit::event::MouseButton::Left
+        };
+        if button == look_button {
+            self.mouse_look = None;
+            if state == winit::event::ElementState::Pressed {
+                self.mouse_look = Some((-1.0, -1.0));
+            }
+        }
+    }
+
+    fn on_cursor_move(&mut self, position: (f64, f64)) {
+        let last = match self.mouse_look.as_mut() {
+            Some(last) => last,
+            None => return,
+        };
+        if last.0 >= 0.0 {
+            let sensitivity = 0.005;
+            let dx = cgmath::Rad((last.0 - position.0) as f32 * sensitivity);
+            let dy = cgmath::Rad((position.1 - last.1) as f32 * sensitivity);
+            self.camera_input.look.0 += dx;
+            self.camera_input.look.1 += dy;
+        }
+        *last = position;
+    }
+
+    fn on_mouse_wheel(&mut self, delta: winit::event::MouseScrollDelta) {
+        // only used for orbiting
+        let amount = match delta {
+            winit::event::MouseScrollDelta::LineDelta(_, y) => 0.1 * y,
+            winit::event::MouseScrollDelta::PixelDelta(pos) => 0.005 * pos.y as f32,
+        };
+        self.camera_input.zoom += amount;
+    }
+
     fn resize(&mut self, _device: &wgpu::Device, extent: wgpu::Extent3d) {
-        self.cam
+        self.screen_size = (extent.width as u16, extent.height as u16);
+        self.camera
+            .cam
             .proj
             .update(extent.width as u16, extent.height as u16);
     }
@@ -160,26 +590,51 @@ impl Application for CarView {
         self.object.reload(device);
     }
 
+    fn on_settings(
+        &mut self,
+        _device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+        settings: &config::Settings,
+    ) {
+        self.light_config = settings.render.light.clone();
+        self.grid_spacing = settings.render.debug.grid_spacing;
+    }
+
     fn draw(
         &mut self,
         device: &wgpu::Device,
         targets: render::ScreenTargets,
         _spawner: &LocalSpawner,
     ) -> wgpu::CommandBuffer {
+        let wheel_pose = render::WheelPose {
+            spin: cgmath::Rad(self.wheel_time * 2.0),
+            steer: cgmath::Rad(0.3 * (self.wheel_time * 0.5).sin()),
+        };
         let mut batcher = render::Batcher::new();
-        batcher.add_model(
+        batcher.add_model_posed(
             &self.model,
             &self.transform,
-            Some(self.physics.scale_bound),
+            if self.show_shape {
+                Some(self.physics.as_ref().map_or(1.0, |p| p.scale_bound))
+            } else {
+                None
+            },
             &render::body::GpuBody::ZERO,
             self.color,
+            &wheel_pose,
         );
         batcher.prepare(device);
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
-        let global_data = render::global::Constants::new(&self.cam, &self.light_config, None);
+        let global_data = render::global::Constants::new(
+            &self.camera.cam,
+            &self.light_config,
+            None,
+            &render::global::Time::default(),
+            None,
+        );
         let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: bytemuck::bytes_of(&global_data),
@@ -193,46 +648,36 @@ impl Application for CarView {
             mem::size_of::<render::global::Constants>() as wgpu::BufferAddress,
         );
 
-        {
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: targets.color,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: targets.depth,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-
-            pass.set_pipeline(self.object.pipelines.select(render::PipelineKind::Main));
-            pass.set_bind_group(0, &self.global.bind_group, &[]);
-            pass.set_bind_group(1, &self.object.bind_group, &[]);
-
-            batcher.draw(&mut pass);
-
-            let _ = &self.debug_render;
-            /*TODO:
-            self.debug_render.draw_shape(
-                &mut pass,
-                &self.model.shape,
-                &self.instance_buf,
-                0,
-            );*/
+        let overlay = self.layout_info();
+        if self.capture_requested {
+            self.capture_requested = false;
+            let capture =
+                render::capture::Capture::new(device, targets.extent, self.global.color_format);
+            self.draw_scene(
+                &mut encoder,
+                device,
+                &batcher,
+                &overlay,
+                &capture.view,
+                targets.depth,
+            );
+            let stamp = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let path = env::current_exe()
+                .unwrap()
+                .with_file_name(format!("car-{}.png", stamp));
+            self.pending_capture = Some((capture.read(&mut encoder, device), path));
         }
+        self.draw_scene(
+            &mut encoder,
+            device,
+            &batcher,
+            &overlay,
+            targets.color,
+            targets.depth,
+        );
 
         encoder.finish()
     }
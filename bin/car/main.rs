@@ -1,6 +1,9 @@
 mod app;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+#[path = "../convert/model_obj.rs"]
+#[allow(dead_code)]
+mod model_obj;
 
 fn main() {
     use std::env;
@@ -14,7 +17,13 @@ fn main() {
     //TODO: render all vehicles, by mask
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
-        .optflag("h", "help", "print this help menu");
+        .optflag("h", "help", "print this help menu")
+        .optopt(
+            "m",
+            "model",
+            "view a single .m3d file, bypassing the registry",
+            "PATH",
+        );
 
     let matches = options.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || !matches.free.is_empty() {
@@ -24,7 +33,8 @@ fn main() {
         return;
     }
 
-    let app = app::CarView::new(&settings, &harness.device, &mut harness.queue);
+    let model_path = matches.opt_str("m").map(std::path::PathBuf::from);
+    let app = app::CarView::new(&settings, model_path, &harness.device, &mut harness.queue);
 
     harness.main_loop(app);
 }
@@ -1,5 +1,7 @@
 mod layers;
 mod level_png;
+#[cfg(feature = "gltf")]
+mod model_gltf;
 mod model_obj;
 
 use std::{
@@ -59,6 +61,7 @@ fn main() {
     let mut options = getopts::Options::new();
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
+        .optflag("c", "compact", "deduplicate vertices on OBJ import")
         .optflag("h", "help", "print this help menu");
 
     let matches = options.parse(&args[1..]).unwrap();
@@ -85,20 +88,28 @@ fn main() {
         ("m3d", "ron") => {
             let file = File::open(&src_path).unwrap();
             println!("\tLoading M3D...");
-            let raw = m3d::FullModel::load(file);
+            let raw = m3d::FullModel::load(file).unwrap();
             println!("\tExporting OBJ data...");
             model_obj::export_m3d(raw, &dst_path);
         }
+        #[cfg(feature = "gltf")]
+        ("m3d", "gltf") => {
+            let file = File::open(&src_path).unwrap();
+            println!("\tLoading M3D...");
+            let raw = m3d::FullModel::load(file).unwrap();
+            println!("\tExporting glTF data...");
+            model_gltf::export_m3d(raw, &dst_path).unwrap();
+        }
         ("ron", "md3") => {
             println!("\tImporting OBJ data...");
-            let model = model_obj::import_m3d(&src_path);
+            let model = model_obj::import_m3d(&src_path, matches.opt_present("c"));
             println!("\tSaving M3D...");
             model.save(File::create(&dst_path).unwrap());
         }
         ("a3d", "ron") => {
             let file = File::open(&src_path).unwrap();
             println!("\tLoading A3D...");
-            let raw = m3d::AnimatedMesh::load(file);
+            let raw = m3d::AnimatedMesh::load(file).unwrap();
             println!("\tExporting OBJ data...");
             model_obj::export_a3d(raw, &dst_path);
         }
@@ -131,19 +142,35 @@ fn main() {
             println!("\tSaving TIFF layers...");
             save_tiff(&dst_path, layers);
         }
+        ("ini", "png") => {
+            println!("\tLoading the level...");
+            let config = vangers::level::LevelConfig::load(&src_path);
+            let level = vangers::level::load(&config);
+            println!("\tSaving the dual layer view...");
+            let file = BufWriter::new(File::create(&dst_path).unwrap());
+            let mut encoder = png::Encoder::new(file, level.size.0 as u32, level.size.1 as u32);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(&level.export_dual())
+                .unwrap();
+        }
         ("ini", "vmp") => {
             println!("\tLoading the VMC...");
             let config = vangers::level::LevelConfig::load(&src_path);
             let level = vangers::level::load(&config);
             println!("\tSaving VMP...");
-            vangers::level::LevelData::from(level).save_vmp(&dst_path);
+            vangers::level::LevelData::from(level)
+                .save_vmp(&dst_path)
+                .unwrap();
         }
         ("ron", "vmp") => {
             println!("\tLoading multiple PNGs...");
             let layers = level_png::load(&src_path);
             println!("\tSaving VMP...");
             let level_data = layers.export();
-            level_data.save_vmp(&dst_path);
+            level_data.save_vmp(&dst_path).unwrap();
         }
         ("pal", "png") => {
             println!("Converting palette to PNG...");
@@ -0,0 +1,285 @@
+use m3d::{DrawTriangle, FullModel, Geometry, Mesh, NORMALIZER};
+
+use byteorder::{LittleEndian as E, WriteBytesExt};
+
+use std::{fs, io::Result as IoResult, path::PathBuf};
+
+const ARRAY_BUFFER: u32 = 34962;
+const FLOAT: u32 = 5126;
+const UNSIGNED_BYTE: u32 = 5121;
+// rotates the Z-up model space into the Y-up glTF space
+const Z_UP_TO_Y_UP: [f32; 4] = [-0.707_106_77, 0.0, 0.0, 0.707_106_77];
+const IDENTITY: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+fn encode_base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).cloned().unwrap_or(0),
+            chunk.get(2).cloned().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// Euler angles in degrees, applied in X, Y, Z order, like the slot angles.
+fn euler_to_quat(angles: [i32; 3]) -> [f32; 4] {
+    let half = |a: i32| (a as f32).to_radians() * 0.5;
+    let (sx, cx) = half(angles[0]).sin_cos();
+    let (sy, cy) = half(angles[1]).sin_cos();
+    let (sz, cz) = half(angles[2]).sin_cos();
+    [
+        sx * cy * cz - cx * sy * sz,
+        cx * sy * cz + sx * cy * sz,
+        cx * cy * sz - sx * sy * cz,
+        cx * cy * cz + sx * sy * sz,
+    ]
+}
+
+struct Node {
+    name: String,
+    mesh: Option<usize>,
+    translation: [f32; 3],
+    rotation: [f32; 4],
+    scale: f32,
+    children: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Document {
+    nodes: Vec<String>,
+    meshes: Vec<String>,
+    buffers: Vec<String>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl Document {
+    // Each mesh gets its own embedded buffer. Vertices are unrolled per triangle corner,
+    // since glTF has a single index for all the attributes.
+    fn add_mesh(&mut self, geom: &Geometry<DrawTriangle>) -> IoResult<usize> {
+        let num_vertices = geom.polygons.len() * 3;
+        let mut data = Vec::with_capacity(num_vertices * (12 + 12 + 4));
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for tri in geom.polygons.iter() {
+            for v in tri.vertices.iter() {
+                let p = geom.positions[v.pos as usize];
+                for ((&c, lo), hi) in p.iter().zip(min.iter_mut()).zip(max.iter_mut()) {
+                    let value = c as f32;
+                    *lo = lo.min(value);
+                    *hi = hi.max(value);
+                    data.write_f32::<E>(value)?;
+                }
+            }
+        }
+        let normals_offset = data.len();
+        for tri in geom.polygons.iter() {
+            for v in tri.vertices.iter() {
+                for &n in geom.normals[v.normal as usize].iter() {
+                    data.write_f32::<E>(n as f32 / NORMALIZER)?;
+                }
+            }
+        }
+        let colors_offset = data.len();
+        for tri in geom.polygons.iter() {
+            for _ in 0..3 {
+                // vertex attributes have to be aligned to 4 bytes
+                data.extend_from_slice(&[tri.material[0] as u8, 0, 0, 0]);
+            }
+        }
+
+        let buffer = self.buffers.len();
+        self.buffers.push(format!(
+            r#"{{ "uri": "data:application/octet-stream;base64,{}", "byteLength": {} }}"#,
+            encode_base64(&data),
+            data.len(),
+        ));
+        let view = self.buffer_views.len();
+        for &(offset, size, stride) in [
+            (0, normals_offset, 12),
+            (normals_offset, colors_offset - normals_offset, 12),
+            (colors_offset, data.len() - colors_offset, 4),
+        ]
+        .iter()
+        {
+            self.buffer_views.push(format!(
+                r#"{{ "buffer": {}, "byteOffset": {}, "byteLength": {}, "byteStride": {}, "target": {} }}"#,
+                buffer, offset, size, stride, ARRAY_BUFFER,
+            ));
+        }
+        let accessor = self.accessors.len();
+        if num_vertices != 0 {
+            self.accessors.push(format!(
+                r#"{{ "bufferView": {}, "componentType": {}, "count": {}, "type": "VEC3", "min": [{}, {}, {}], "max": [{}, {}, {}] }}"#,
+                view, FLOAT, num_vertices, min[0], min[1], min[2], max[0], max[1], max[2],
+            ));
+        } else {
+            self.accessors.push(format!(
+                r#"{{ "bufferView": {}, "componentType": {}, "count": 0, "type": "VEC3" }}"#,
+                view, FLOAT,
+            ));
+        }
+        self.accessors.push(format!(
+            r#"{{ "bufferView": {}, "componentType": {}, "count": {}, "type": "VEC3" }}"#,
+            view + 1,
+            FLOAT,
+            num_vertices,
+        ));
+        self.accessors.push(format!(
+            r#"{{ "bufferView": {}, "componentType": {}, "count": {}, "type": "SCALAR" }}"#,
+            view + 2,
+            UNSIGNED_BYTE,
+            num_vertices,
+        ));
+
+        let mesh = self.meshes.len();
+        self.meshes.push(format!(
+            r#"{{ "primitives": [{{ "attributes": {{ "POSITION": {}, "NORMAL": {}, "_COLOR_ID": {} }} }}] }}"#,
+            accessor,
+            accessor + 1,
+            accessor + 2,
+        ));
+        Ok(mesh)
+    }
+
+    fn add_node(&mut self, node: Node) -> usize {
+        let mut fields = vec![format!(r#""name": "{}""#, node.name)];
+        if let Some(mesh) = node.mesh {
+            fields.push(format!(r#""mesh": {}"#, mesh));
+        }
+        let t = node.translation;
+        fields.push(format!(r#""translation": [{}, {}, {}]"#, t[0], t[1], t[2]));
+        let r = node.rotation;
+        fields.push(format!(
+            r#""rotation": [{}, {}, {}, {}]"#,
+            r[0], r[1], r[2], r[3]
+        ));
+        let s = node.scale;
+        fields.push(format!(r#""scale": [{}, {}, {}]"#, s, s, s));
+        if !node.children.is_empty() {
+            let children = node
+                .children
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>();
+            fields.push(format!(r#""children": [{}]"#, children.join(", ")));
+        }
+        self.nodes.push(format!("{{ {} }}", fields.join(", ")));
+        self.nodes.len() - 1
+    }
+
+    fn add_mesh_node(
+        &mut self,
+        name: String,
+        mesh: &Mesh<Geometry<DrawTriangle>>,
+    ) -> IoResult<usize> {
+        let index = self.add_mesh(&mesh.geometry)?;
+        let o = mesh.parent_off;
+        Ok(self.add_node(Node {
+            name,
+            mesh: Some(index),
+            translation: [o[0] as f32, o[1] as f32, o[2] as f32],
+            rotation: euler_to_quat(mesh.parent_rot),
+            scale: 1.0,
+            children: Vec::new(),
+        }))
+    }
+
+    fn to_json(&self, root: usize) -> String {
+        let list = |items: &[String]| format!("[\n    {}\n  ]", items.join(",\n    "));
+        format!(
+            r#"{{
+  "asset": {{ "version": "2.0", "generator": "vangers" }},
+  "scene": 0,
+  "scenes": [{{ "nodes": [{}] }}],
+  "nodes": {},
+  "meshes": {},
+  "buffers": {},
+  "bufferViews": {},
+  "accessors": {}
+}}
+"#,
+            root,
+            list(&self.nodes),
+            list(&self.meshes),
+            list(&self.buffers),
+            list(&self.buffer_views),
+            list(&self.accessors),
+        )
+    }
+}
+
+// Writes the draw meshes of the model into a single self-contained glTF file.
+// The wheels, debris, and slots become children of the body node.
+// The color ID of each vertex is kept in the custom `_COLOR_ID` attribute.
+pub fn export_m3d(full: FullModel, model_path: &PathBuf) -> IoResult<()> {
+    let mut doc = Document::default();
+    let mut children = Vec::new();
+
+    for (i, wheel) in full.wheels.iter().enumerate() {
+        if let Some(ref mesh) = wheel.mesh {
+            children.push(doc.add_mesh_node(format!("wheel{}", i), mesh)?);
+        }
+    }
+    for (i, debrie) in full.debris.iter().enumerate() {
+        children.push(doc.add_mesh_node(format!("debrie{}", i), &debrie.mesh)?);
+    }
+    for (i, slot) in full.slots.iter().enumerate() {
+        if let Some(ref mesh) = slot.mesh {
+            // see `model::slot_transform`
+            let mesh_index = doc.add_mesh(&mesh.geometry)?;
+            let angle = (slot.angle as f32).to_radians();
+            let (sin, cos) = angle.sin_cos();
+            let (half_sin, half_cos) = (angle * 0.5).sin_cos();
+            let o = mesh.parent_off;
+            let off = [
+                slot.scale * o[0] as f32,
+                slot.scale * o[1] as f32,
+                slot.scale * o[2] as f32,
+            ];
+            children.push(doc.add_node(Node {
+                name: format!("slot{}", i),
+                mesh: Some(mesh_index),
+                translation: [
+                    slot.pos[0] as f32 - (cos * off[0] + sin * off[2]),
+                    slot.pos[1] as f32 - off[1],
+                    slot.pos[2] as f32 - (cos * off[2] - sin * off[0]),
+                ],
+                rotation: [0.0, half_sin, 0.0, half_cos],
+                scale: slot.scale,
+                children: Vec::new(),
+            }));
+        }
+    }
+
+    let body_mesh = doc.add_mesh(&full.body.geometry)?;
+    let body = doc.add_node(Node {
+        name: "body".to_string(),
+        mesh: Some(body_mesh),
+        translation: [0.0; 3],
+        rotation: IDENTITY,
+        scale: 1.0,
+        children,
+    });
+    let root = doc.add_node(Node {
+        name: "model".to_string(),
+        mesh: None,
+        translation: [0.0; 3],
+        rotation: Z_UP_TO_Y_UP,
+        scale: 1.0,
+        children: vec![body],
+    });
+
+    fs::write(model_path, doc.to_json(root))
+}
@@ -6,6 +6,7 @@ use m3d::{
 use obj::{IndexTuple, Obj};
 
 use std::{
+    collections::HashMap,
     fs,
     io::{Result as IoResult, Write},
     path::PathBuf,
@@ -76,14 +77,15 @@ pub fn export_m3d(full: FullModel, model_path: &PathBuf) {
     fs::write(model_path, string).unwrap();
 }
 
-pub fn import_m3d(model_path: &PathBuf) -> FullModel {
+pub fn import_m3d(model_path: &PathBuf, compact: bool) -> FullModel {
     let dir_path = model_path.parent().unwrap();
     let model_file = fs::File::open(model_path).unwrap();
     let model = ron::de::from_reader::<_, RefModel>(model_file).unwrap();
 
-    let resolve_geom_draw = |name| -> Geometry<DrawTriangle> { load_geometry(dir_path.join(name)) };
+    let resolve_geom_draw =
+        |name| -> Geometry<DrawTriangle> { load_geometry(dir_path.join(name), compact) };
     let resolve_geom_coll =
-        |name| -> Geometry<CollisionQuad> { load_geometry(dir_path.join(name)) };
+        |name| -> Geometry<CollisionQuad> { load_geometry(dir_path.join(name), compact) };
     let resolve_mesh = |mesh: Mesh<String>| mesh.map(&resolve_geom_draw);
 
     FullModel {
@@ -142,7 +144,7 @@ pub fn import_a3d(mesh_path: &PathBuf) -> DrawAnimatedMesh {
         meshes: a3d
             .meshes
             .into_iter()
-            .map(|mesh| mesh.map(|name| load_geometry(dir_path.join(&name))))
+            .map(|mesh| mesh.map(|name| load_geometry(dir_path.join(&name), false)))
             .collect(),
     }
 }
@@ -261,39 +263,65 @@ pub fn save_collision_geometry(geom: &Geometry<CollisionQuad>, path: PathBuf) ->
     Ok(())
 }
 
-pub fn load_geometry<P: Polygon>(path: PathBuf) -> Geometry<P> {
-    let obj = Obj::load(&path).unwrap();
-
-    let positions = obj
-        .data
-        .position
-        .iter()
-        .map(|p| {
-            [
-                p[0].min(NORMALIZER).max(-NORMALIZER) as i8,
-                p[1].min(NORMALIZER).max(-NORMALIZER) as i8,
-                p[2].min(NORMALIZER).max(-NORMALIZER) as i8,
-            ]
-        })
-        .collect();
-    let normals = obj
-        .data
-        .normal
-        .iter()
-        .map(|n| {
-            [
-                (n[0] * NORMALIZER) as i8,
-                (n[1] * NORMALIZER) as i8,
-                (n[2] * NORMALIZER) as i8,
-            ]
+// Shares the vertex data that is equal after quantization, if `compact` is set.
+// Returns the unique values and the remapping table for the original indices.
+fn share<T: Copy + Eq + std::hash::Hash>(data: Vec<T>, compact: bool) -> (Vec<T>, Vec<u16>) {
+    if !compact {
+        let remap = (0..data.len() as u16).collect();
+        return (data, remap);
+    }
+    let mut unique = Vec::new();
+    let mut lookup = HashMap::new();
+    let remap = data
+        .into_iter()
+        .map(|value| {
+            *lookup.entry(value).or_insert_with(|| {
+                unique.push(value);
+                unique.len() as u16 - 1
+            })
         })
         .collect();
+    (unique, remap)
+}
+
+pub fn load_geometry<P: Polygon>(path: PathBuf, compact: bool) -> Geometry<P> {
+    let obj = Obj::load(&path).unwrap();
+
+    let (positions, pos_remap) = share(
+        obj.data
+            .position
+            .iter()
+            .map(|p| {
+                [
+                    p[0].min(NORMALIZER).max(-NORMALIZER) as i8,
+                    p[1].min(NORMALIZER).max(-NORMALIZER) as i8,
+                    p[2].min(NORMALIZER).max(-NORMALIZER) as i8,
+                ]
+            })
+            .collect(),
+        compact,
+    );
+    let (normals, normal_remap) = share(
+        obj.data
+            .normal
+            .iter()
+            .map(|n| {
+                [
+                    (n[0] * NORMALIZER) as i8,
+                    (n[1] * NORMALIZER) as i8,
+                    (n[2] * NORMALIZER) as i8,
+                ]
+            })
+            .collect(),
+        compact,
+    );
 
     let color_names = (0..NUM_COLOR_IDS)
         .map(|id| format!("{:?}", map_color_id(id)))
         .collect::<Vec<_>>();
 
     let data_ref = &obj.data;
+    let (pos_remap, normal_remap) = (&pos_remap, &normal_remap);
     let polygons = obj
         .data
         .objects
@@ -309,8 +337,8 @@ pub fn load_geometry<P: Polygon>(path: PathBuf) -> Geometry<P> {
                     vertices.clear();
                     for &IndexTuple(pi, _, ni) in poly.0.iter() {
                         vertices.push(Vertex {
-                            pos: pi as u16,
-                            normal: ni.unwrap_or(0) as u16,
+                            pos: pos_remap[pi],
+                            normal: ni.map_or(0, |ni| normal_remap[ni]),
                         })
                     }
                     P::new(
@@ -1,14 +1,19 @@
 use crate::boilerplate::Application;
 use vangers::{
     config, level,
-    render::{body::GpuStoreInit, Batcher, Render, ScreenTargets},
+    render::{body::GpuStoreInit, Batcher, Render, RenderFormats, ScreenTargets},
     space,
 };
 
 use futures::executor::LocalSpawner;
-use log::info;
+use log::{info, warn};
 use winit::event;
 
+use std::time::Instant;
+
+const BENCH_STEPS: &[u32] = &[2, 4, 8, 16, 32, 64];
+const BENCH_FRAMES: u32 = 200;
+
 #[derive(Debug)]
 enum Input {
     Hor { dir: f32, alt: bool, shift: bool },
@@ -20,11 +25,21 @@ enum Input {
     Empty,
 }
 
+// Renders a number of frames with each of `BENCH_STEPS`,
+// logging the average frame time of the ray-traced terrain.
+struct Benchmark {
+    index: usize,
+    frames: u32,
+    start: Instant,
+    original_steps: u32,
+}
+
 pub struct LevelView {
     render: Render,
     _level: level::Level,
     cam: space::Camera,
     input: Input,
+    bench: Option<Benchmark>,
 
     last_mouse_pos: cgmath::Vector2<f32>,
     alt_button_pressed: bool,
@@ -106,6 +121,7 @@ impl LevelView {
             &settings.render,
             screen_extent,
             store_init.resource(),
+            RenderFormats::new(settings.render.srgb),
         );
 
         LevelView {
@@ -130,8 +146,10 @@ impl LevelView {
                         depth.0..depth.1,
                     ),
                 },
+                jitter: (0.0, 0.0),
             },
             input: Input::Empty,
+            bench: None,
             last_mouse_pos: cgmath::vec2(-1.0, -1.0),
             alt_button_pressed: false,
             mouse_button_pressed: false,
@@ -229,6 +247,19 @@ impl Application for LevelView {
                         alt: modifiers.alt(),
                     }
                 }
+                Key::B if self.bench.is_none() => match self.render.terrain.ray_steps() {
+                    Some(original_steps) => {
+                        info!("Benchmarking the ray-march steps, the camera should stay still");
+                        self.render.terrain.set_ray_steps(BENCH_STEPS[0]);
+                        self.bench = Some(Benchmark {
+                            index: 0,
+                            frames: 0,
+                            start: Instant::now(),
+                            original_steps,
+                        });
+                    }
+                    None => warn!("Benchmark requires the RayTraced terrain"),
+                },
                 Key::LAlt => self.alt_button_pressed = true,
                 _ => (),
             },
@@ -352,6 +383,34 @@ impl Application for LevelView {
         targets: ScreenTargets,
         _spawner: &LocalSpawner,
     ) -> wgpu::CommandBuffer {
+        let mut bench_done = false;
+        if let Some(ref mut bench) = self.bench {
+            bench.frames += 1;
+            if bench.frames == BENCH_FRAMES {
+                // includes the presentation, so the present mode shouldn't be Fifo
+                let elapsed = bench.start.elapsed();
+                info!(
+                    "\t{} steps: {:.2} ms per frame",
+                    BENCH_STEPS[bench.index],
+                    elapsed.as_secs_f32() * 1000.0 / BENCH_FRAMES as f32
+                );
+                bench.index += 1;
+                bench.frames = 0;
+                bench.start = Instant::now();
+                let steps = match BENCH_STEPS.get(bench.index) {
+                    Some(&steps) => steps,
+                    None => {
+                        bench_done = true;
+                        bench.original_steps
+                    }
+                };
+                self.render.terrain.set_ray_steps(steps);
+            }
+        }
+        if bench_done {
+            self.bench = None;
+        }
+
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
@@ -361,6 +420,7 @@ impl Application for LevelView {
             &self.cam,
             targets,
             device,
+            None,
         );
         encoder.finish()
     }
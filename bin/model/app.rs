@@ -27,7 +27,15 @@ impl ResourceView {
         info!("Initializing the render");
         let pal_data = level::read_palette(settings.open_palette(), None);
         let store_init = render::body::GpuStoreInit::new_dummy(device);
-        let global = render::global::Context::new(device, queue, store_init.resource(), None);
+        let global = render::global::Context::new(
+            device,
+            queue,
+            store_init.resource(),
+            None,
+            None,
+            render::RenderFormats::new(settings.render.srgb),
+            1,
+        );
         let object = render::object::Context::new(device, queue, &pal_data, &global);
 
         info!("Loading model {}", path);
@@ -52,6 +60,7 @@ impl ResourceView {
                     near: 5.0,
                     far: 400.0,
                 }),
+                jitter: (0.0, 0.0),
             },
             rotation: cgmath::Rad(0.),
             light_config: settings.render.light.clone(),
@@ -139,7 +148,13 @@ impl Application for ResourceView {
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
-        let global_data = render::global::Constants::new(&self.cam, &self.light_config, None);
+        let global_data = render::global::Constants::new(
+            &self.cam,
+            &self.light_config,
+            None,
+            &render::global::Time::default(),
+            None,
+        );
         let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: bytemuck::bytes_of(&global_data),
@@ -178,11 +193,14 @@ impl Application for ResourceView {
                 }),
             });
 
-            pass.set_pipeline(self.object.pipelines.select(render::PipelineKind::Main));
             pass.set_bind_group(0, &self.global.bind_group, &[]);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
 
-            batcher.draw(&mut pass);
+            batcher.draw(
+                &mut pass,
+                &self.object.pipelines,
+                render::PipelineKind::Main,
+            );
         }
 
         encoder.finish()
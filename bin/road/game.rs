@@ -3,11 +3,11 @@ use m3d::Mesh;
 use vangers::{
     config, level, model,
     render::{
-        body::{GpuBody, GpuStore, GpuStoreInit},
+        body::{AllocError, FixedTimestep, GpuBody, GpuStore, GpuStoreInit},
         collision::{GpuCollider, GpuEpoch},
         debug::LineBuffer,
         object::BodyColor,
-        Batcher, Render, ScreenTargets,
+        Batcher, Render, RenderFormats, ScreenTargets, WheelPose,
     },
     space,
 };
@@ -58,6 +58,57 @@ enum SimulationStep<'a> {
     },
 }
 
+// Pieces of a blown up vehicle, flying without collisions.
+struct Explosion {
+    model: model::VisualModel,
+    color: BodyColor,
+    // transform and velocity of each debrie of the model
+    pieces: Vec<(space::Transform, cgmath::Vector3<f32>)>,
+    time_left: f32,
+}
+
+impl Explosion {
+    const DURATION: f32 = 3.0;
+    const SPEED: f32 = 40.0;
+    const GRAVITY: f32 = 60.0;
+
+    fn new(model: &model::VisualModel, color: BodyColor, base: &space::Transform) -> Self {
+        let pieces = model
+            .debris
+            .iter()
+            .map(|debrie| {
+                let offset = cgmath::Vector3::from(debrie.mesh.offset);
+                let transform = base.concat(&space::Transform {
+                    disp: offset,
+                    rot: cgmath::Quaternion::one(),
+                    scale: 1.0,
+                });
+                let outward = if offset.magnitude2() > 0.0 {
+                    offset.normalize()
+                } else {
+                    cgmath::Vector3::zero()
+                };
+                let velocity = base.rot * (outward + cgmath::Vector3::unit_z()) * Self::SPEED;
+                (transform, velocity)
+            })
+            .collect();
+        Explosion {
+            model: model.clone(),
+            color,
+            pieces,
+            time_left: Self::DURATION,
+        }
+    }
+
+    fn step(&mut self, delta: f32) {
+        for (transform, velocity) in self.pieces.iter_mut() {
+            velocity.z -= Self::GRAVITY * delta;
+            transform.disp += *velocity * delta;
+        }
+        self.time_left -= delta;
+    }
+}
+
 pub struct Agent {
     _name: String,
     spirit: Spirit,
@@ -77,7 +128,7 @@ impl Agent {
         orientation: cgmath::Rad<f32>,
         level: &level::Level,
         gpu_store: Option<&mut GpuStore>,
-    ) -> Self {
+    ) -> Result<Self, AllocError> {
         let height = physics::get_height(level.get(coords).top()) + 5.; //center offset
         let transform = cgmath::Decomposed {
             scale: car.scale,
@@ -85,7 +136,19 @@ impl Agent {
             rot: cgmath::Quaternion::from_angle_z(orientation),
         };
 
-        Agent {
+        let physics = match gpu_store {
+            Some(store) => Physics::Gpu {
+                body: store.alloc(&transform, &car.model, &car.physics)?,
+                collision_epochs: HashMap::default(),
+                last_control: Control::default(),
+            },
+            None => Physics::Cpu {
+                transform,
+                dynamo: physics::Dynamo::default(),
+            },
+        };
+
+        Ok(Agent {
             _name: name,
             spirit: Spirit::Other(Ai {
                 last_transform: transform,
@@ -95,18 +158,8 @@ impl Agent {
             color,
             control: Control::default(),
             jump: None,
-            physics: match gpu_store {
-                Some(store) => Physics::Gpu {
-                    body: store.alloc(&transform, &car.model, &car.physics),
-                    collision_epochs: HashMap::default(),
-                    last_control: Control::default(),
-                },
-                None => Physics::Cpu {
-                    transform,
-                    dynamo: physics::Dynamo::default(),
-                },
-            },
-        }
+            physics,
+        })
     }
 
     fn cpu_apply_control(&mut self, dt: f32, common: &config::common::Common) {
@@ -252,6 +305,7 @@ impl CameraStyle {
                 },
                 speed: config.speed,
                 fix_z: true,
+                spring: None,
             })
         } else {
             //Note: this appears to be broken ATM
@@ -297,9 +351,11 @@ pub struct Game {
     line_buffer: LineBuffer,
     level: level::Level,
     agents: Vec<Agent>,
+    explosions: Vec<Explosion>,
     cam: space::Camera,
     cam_style: CameraStyle,
     max_quant: f32,
+    fixed_step: Option<FixedTimestep>,
     spin_hor: f32,
     spin_ver: f32,
     turbo: bool,
@@ -307,6 +363,7 @@ pub struct Game {
     roll: Option<Roll>,
     is_paused: bool,
     tick: Option<f32>,
+    selected: Option<usize>,
 }
 
 impl Game {
@@ -360,6 +417,7 @@ impl Game {
             &settings.render,
             screen_extent,
             store_init.resource(),
+            RenderFormats::new(settings.render.srgb),
         );
 
         log::info!("Loading world database");
@@ -404,7 +462,8 @@ impl Game {
             cgmath::Rad::turn_div_2(),
             &level,
             gpu.as_mut().map(|Gpu { ref mut store, .. }| store),
-        );
+        )
+        .expect("Unable to spawn the player");
         player_agent.spirit = Spirit::Player;
         for (ms, sid) in player_agent
             .car
@@ -414,7 +473,7 @@ impl Game {
             .zip(settings.car.slots.iter())
         {
             let info = &db.game.model_infos[sid];
-            let raw = Mesh::load(&mut settings.open_relative(&info.path));
+            let raw = Mesh::load(&mut settings.open_relative(&info.path)).unwrap();
             ms.mesh = Some(model::load_c3d(raw, device));
             ms.scale = info.scale;
         }
@@ -438,7 +497,7 @@ impl Game {
                     rng.gen_range(0, level.size.1),
                 ),
             };
-            let agent = Agent::spawn(
+            match Agent::spawn(
                 format!("Other-{}", i),
                 &db.cars[car_id],
                 color,
@@ -446,8 +505,13 @@ impl Game {
                 rng.gen(),
                 &level,
                 gpu.as_mut().map(|Gpu { ref mut store, .. }| store),
-            );
-            agents.push(agent);
+            ) {
+                Ok(agent) => agents.push(agent),
+                Err(e) => {
+                    log::warn!("Unable to spawn more agents: {:?}", e);
+                    break;
+                }
+            }
         }
 
         Game {
@@ -458,6 +522,7 @@ impl Game {
             line_buffer: LineBuffer::new(),
             level,
             agents,
+            explosions: Vec::new(),
             cam: space::Camera {
                 loc: cgmath::vec3(coords.0 as f32, coords.1 as f32, 200.0),
                 rot: cgmath::Quaternion::new(0.0, 0.0, 1.0, 0.0),
@@ -477,9 +542,11 @@ impl Game {
                         depth.0..depth.1,
                     ),
                 },
+                jitter: (0.0, 0.0),
             },
             cam_style: CameraStyle::new(&settings.game.camera),
             max_quant: settings.game.physics.max_quant,
+            fixed_step: settings.game.physics.fixed_dt.map(FixedTimestep::new),
             //debug_collision_map: settings.render.debug.collision_map,
             spin_hor: 0.0,
             spin_ver: 0.0,
@@ -488,6 +555,7 @@ impl Game {
             roll: None,
             is_paused: false,
             tick: None,
+            selected: None,
         }
     }
 
@@ -536,6 +604,16 @@ impl Application for Game {
                         self.is_paused = true;
                         self.cam.focus_on(&center);
                     }
+                    if let Some(ref mut fixed) = self.fixed_step {
+                        fixed.set_paused(self.is_paused);
+                    }
+                }
+                Key::Tab => {
+                    self.selected = match self.selected {
+                        None if !self.agents.is_empty() => Some(0),
+                        Some(i) if i + 1 < self.agents.len() => Some(i + 1),
+                        _ => None,
+                    };
                 }
                 Key::Comma => self.tick = Some(-1.0),
                 Key::Period => self.tick = Some(1.0),
@@ -543,16 +621,31 @@ impl Application for Game {
                 Key::LAlt => self.jump = Some(0.0),
                 Key::W => self.spin_ver = 1.0,
                 Key::S => self.spin_ver = -1.0,
-                Key::R => {
-                    if let Physics::Cpu {
+                Key::R => match player.physics {
+                    Physics::Cpu {
                         ref mut transform,
                         ref mut dynamo,
-                    } = player.physics
-                    {
+                    } => {
                         transform.rot = cgmath::One::one();
                         dynamo.linear_velocity = cgmath::Vector3::zero();
                         dynamo.angular_velocity = cgmath::Vector3::zero();
                     }
+                    Physics::Gpu { ref body, .. } => {
+                        self.gpu.as_mut().unwrap().store.reset(body);
+                    }
+                },
+                Key::X => {
+                    let center = match player.physics {
+                        Physics::Cpu { ref transform, .. } => transform.clone(),
+                        Physics::Gpu { ref body, .. } => {
+                            match self.gpu.as_ref().unwrap().store.cpu_mirror().get(body) {
+                                Some(transform) => transform.clone(),
+                                None => return true,
+                            }
+                        }
+                    };
+                    self.explosions
+                        .push(Explosion::new(&player.car.model, player.color, &center));
                 }
                 Key::A => self.spin_hor = -1.0,
                 Key::D => self.spin_hor = 1.0,
@@ -610,6 +703,13 @@ impl Application for Game {
     ) -> Vec<wgpu::CommandBuffer> {
         let focus_point = self.cam.intersect_height(level::HEIGHT_SCALE as f32 * 0.3);
 
+        if !self.is_paused {
+            for explosion in self.explosions.iter_mut() {
+                explosion.step(delta);
+            }
+            self.explosions.retain(|e| e.time_left > 0.0);
+        }
+
         if let Some(ref mut jump) = self.jump {
             let power = delta * (self.db.common.speed.standard_frame_rate as f32);
             *jump = (*jump + power).min(self.db.common.force.max_jump_power);
@@ -638,6 +738,14 @@ impl Application for Game {
                 if let Some(tick) = self.tick.take() {
                     self.line_buffer.clear();
                     player.control.roll = 0.0;
+                    // GPU bodies advance by exactly one fixed step
+                    if let (Physics::Gpu { .. }, Some(fixed)) =
+                        (&player.physics, self.fixed_step.as_mut())
+                    {
+                        if tick > 0.0 {
+                            fixed.step_once();
+                        }
+                    }
 
                     player.cpu_step(
                         tick * self.max_quant,
@@ -656,36 +764,43 @@ impl Application for Game {
                     cgmath::Rad(delta * self.spin_ver),
                 );
 
-                return Vec::new();
-            }
+                if !self
+                    .fixed_step
+                    .as_ref()
+                    .map_or(false, FixedTimestep::has_pending_steps)
+                {
+                    return Vec::new();
+                }
+            } else {
+                player.control.rudder = self.spin_hor;
+                player.control.motor = 1.0 * self.spin_ver;
+                player.control.turbo = self.turbo;
+                player.control.roll = match self.roll {
+                    Some(ref mut roll) => {
+                        let roll_count = (roll.time
+                            * self.db.common.speed.standard_frame_rate as f32)
+                            .min(100.0) as u8;
+                        roll.time += delta;
+                        if roll_count > self.db.common.force.side_impulse_delay {
+                            roll.time = 0.0;
+                        }
+                        if roll_count < self.db.common.force.side_impulse_duration {
+                            roll.dir
+                        } else {
+                            0.0
+                        }
+                    }
+                    None => 0.0,
+                };
 
-            player.control.rudder = self.spin_hor;
-            player.control.motor = 1.0 * self.spin_ver;
-            player.control.turbo = self.turbo;
-            player.control.roll = match self.roll {
-                Some(ref mut roll) => {
-                    let roll_count = (roll.time * self.db.common.speed.standard_frame_rate as f32)
-                        .min(100.0) as u8;
-                    roll.time += delta;
-                    if roll_count > self.db.common.force.side_impulse_delay {
-                        roll.time = 0.0;
+                match self.cam_style {
+                    CameraStyle::Simple(ref dir) => {
+                        self.cam.look_by(&target, dir);
                     }
-                    if roll_count < self.db.common.force.side_impulse_duration {
-                        roll.dir
-                    } else {
-                        0.0
+                    CameraStyle::Follow(ref mut follow) => {
+                        self.cam.follow(&target, delta, follow);
                     }
                 }
-                None => 0.0,
-            };
-
-            match self.cam_style {
-                CameraStyle::Simple(ref dir) => {
-                    self.cam.look_by(&target, dir);
-                }
-                CameraStyle::Follow(ref follow) => {
-                    self.cam.follow(&target, delta, follow);
-                }
             }
         }
 
@@ -743,38 +858,53 @@ impl Application for Game {
             }
             gpu.store.update_entries(device, &mut encoder);
 
-            while physics_dt > self.max_quant {
+            let mut quants = Vec::new();
+            match self.fixed_step {
+                Some(ref mut fixed) => {
+                    let count = fixed.advance(physics_dt);
+                    quants.extend((0..count).map(|_| fixed.dt));
+                }
+                None => {
+                    while physics_dt > self.max_quant {
+                        quants.push(self.max_quant);
+                        physics_dt -= self.max_quant;
+                    }
+                    quants.push(physics_dt);
+                }
+            }
+
+            if let Some(last_quant) = quants.pop() {
+                for quant in quants {
+                    let mut session =
+                        gpu.collider
+                            .begin(&mut encoder, &self.render.terrain, spawner);
+                    for agent in &mut self.agents {
+                        if let Physics::Gpu { ref body, .. } = agent.physics {
+                            session.add(&agent.car.model.shape, body.index());
+                        }
+                    }
+                    let ranges = session.finish(&mut prep_encoder, device);
+                    gpu.store.step(device, &mut encoder, quant, ranges);
+                }
+
                 let mut session = gpu
                     .collider
                     .begin(&mut encoder, &self.render.terrain, spawner);
                 for agent in &mut self.agents {
-                    if let Physics::Gpu { ref body, .. } = agent.physics {
-                        session.add(&agent.car.model.shape, body.index());
+                    if let Physics::Gpu {
+                        ref body,
+                        ref mut collision_epochs,
+                        ..
+                    } = agent.physics
+                    {
+                        let start_index = session.add(&agent.car.model.shape, body.index());
+                        let old = collision_epochs.insert(session.epoch, start_index);
+                        assert_eq!(old, None);
                     }
                 }
                 let ranges = session.finish(&mut prep_encoder, device);
-
-                gpu.store.step(device, &mut encoder, self.max_quant, ranges);
-                physics_dt -= self.max_quant;
-            }
-
-            let mut session = gpu
-                .collider
-                .begin(&mut encoder, &self.render.terrain, spawner);
-            for agent in &mut self.agents {
-                if let Physics::Gpu {
-                    ref body,
-                    ref mut collision_epochs,
-                    ..
-                } = agent.physics
-                {
-                    let start_index = session.add(&agent.car.model.shape, body.index());
-                    let old = collision_epochs.insert(session.epoch, start_index);
-                    assert_eq!(old, None);
-                }
+                gpu.store.step(device, &mut encoder, last_quant, ranges);
             }
-            let ranges = session.finish(&mut prep_encoder, device);
-            gpu.store.step(device, &mut encoder, physics_dt, ranges);
             gpu.store.produce_gpu_results(device, &mut encoder);
 
             vec![prep_encoder.finish(), encoder.finish()]
@@ -822,6 +952,16 @@ impl Application for Game {
         self.render.resize(extent, device);
     }
 
+    fn on_settings(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &config::Settings,
+    ) {
+        self.render
+            .apply_settings(device, queue, &self.level, &settings.render);
+    }
+
     fn reload(&mut self, device: &wgpu::Device) {
         self.render.reload(device);
         if let Some(Gpu {
@@ -847,38 +987,75 @@ impl Application for Game {
         }
 
         let identity_transform = space::Transform::one();
-        let clipper = Clipper::new(&self.cam);
+        // GPU bodies are culled by their last known transform
+        let mirror = self.gpu.as_ref().map(|gpu| gpu.store.cpu_mirror());
+        let frustum = if self.render.frustum_culling {
+            Some(space::Frustum::from_camera(&self.cam))
+        } else {
+            None
+        };
         self.batcher.clear();
 
-        for agent in self.agents.iter() {
-            let (gpu_body, transform) = match agent.physics {
-                Physics::Cpu { ref transform, .. } => {
-                    if clipper.clip(&transform.disp) {
-                        continue;
-                    }
-                    (&GpuBody::ZERO, transform)
-                }
-                Physics::Gpu { ref body, .. } => (body, &identity_transform),
+        for (i, agent) in self.agents.iter().enumerate() {
+            let (gpu_body, transform, bounds) = match agent.physics {
+                Physics::Cpu { ref transform, .. } => (&GpuBody::ZERO, transform, Some(transform)),
+                Physics::Gpu { ref body, .. } => (
+                    body,
+                    &identity_transform,
+                    mirror.as_ref().and_then(|m| m.get(body)),
+                ),
             };
+            if let (Some(frustum), Some(bounds)) = (frustum.as_ref(), bounds) {
+                let radius = agent.car.model.body.bbox.radius * bounds.scale;
+                if !frustum.contains_sphere(bounds.disp, radius) {
+                    continue;
+                }
+            }
             let debug_shape_scale = match agent.spirit {
                 Spirit::Player => Some(agent.car.physics.scale_bound),
                 Spirit::Other { .. } => None,
             };
-            self.batcher.add_model(
-                &agent.car.model,
-                &transform,
-                debug_shape_scale,
-                gpu_body,
-                agent.color,
-            );
+            if self.selected == Some(i) {
+                self.batcher.add_model_outlined(
+                    &agent.car.model,
+                    &transform,
+                    debug_shape_scale,
+                    gpu_body,
+                    agent.color,
+                    &WheelPose::default(),
+                );
+            } else {
+                self.batcher.add_model(
+                    &agent.car.model,
+                    &transform,
+                    debug_shape_scale,
+                    gpu_body,
+                    agent.color,
+                );
+            }
+        }
+
+        for explosion in self.explosions.iter() {
+            for (debrie, &(ref transform, _)) in
+                explosion.model.debris.iter().zip(explosion.pieces.iter())
+            {
+                self.batcher
+                    .add_debrie(&debrie.mesh, transform, &GpuBody::ZERO, explosion.color);
+            }
         }
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
 
-        self.render
-            .draw_world(&mut encoder, &mut self.batcher, &self.cam, targets, device);
+        self.render.draw_world(
+            &mut encoder,
+            &mut self.batcher,
+            &self.cam,
+            targets,
+            device,
+            mirror.as_deref(),
+        );
 
         /*
         self.render.debug.draw_lines(
@@ -45,16 +45,17 @@ impl HitAccumulator {
     }
 }
 
-pub fn get_height(altitude: u8) -> f32 {
-    altitude as f32 * (level::HEIGHT_SCALE as f32) / 255.0
+pub fn get_height(altitude: level::Altitude) -> f32 {
+    level::altitude_to_height(altitude)
 }
 
 // see `GET_MIDDLE_HIGHT` macro
-fn get_middle(low: u8, high: u8) -> f32 {
-    let extra_room = if high.saturating_sub(low) > 130 {
-        110
+fn get_middle(low: level::Altitude, high: level::Altitude) -> f32 {
+    let step = |alt: level::Altitude| alt << level::ALTITUDE_FRACTION_BITS;
+    let extra_room = if high.saturating_sub(low) > step(130) {
+        step(110)
     } else {
-        48
+        step(48)
     };
     get_height(low.saturating_add(extra_room))
 }
@@ -0,0 +1,320 @@
+use crate::geometry::{DrawTriangle, Geometry, Vertex, NORMALIZER};
+
+use std::{cmp::Reverse, collections::BinaryHeap, collections::HashMap};
+
+// Keeps the open borders in place, relative to the surface error.
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+type Vec3 = [f64; 3];
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: Vec3) -> Option<Vec3> {
+    let len = dot(a, a).sqrt();
+    if len > 0.0 {
+        Some([a[0] / len, a[1] / len, a[2] / len])
+    } else {
+        None
+    }
+}
+
+// Symmetric 4x4 matrix of a sum of squared plane distances,
+// stored as the upper triangle.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(n: Vec3, d: f64, weight: f64) -> Self {
+        let p = [n[0], n[1], n[2], d];
+        let mut q = [0.0; 10];
+        let mut k = 0;
+        for i in 0..4 {
+            for j in i..4 {
+                q[k] = weight * p[i] * p[j];
+                k += 1;
+            }
+        }
+        Quadric(q)
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, v: Vec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (v[0], v[1], v[2]);
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+struct Collapse {
+    from: usize,
+    to: usize,
+    stamps: (u32, u32),
+}
+
+struct Mesh {
+    positions: Vec<Vec3>,
+    triangles: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    vertex_triangles: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    stamps: Vec<u32>,
+    removed: Vec<bool>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    collapses: Vec<Collapse>,
+}
+
+impl Mesh {
+    fn normal(&self, tri: [usize; 3]) -> Vec3 {
+        let [a, b, c] = tri;
+        let p = &self.positions;
+        cross(sub(p[b], p[a]), sub(p[c], p[a]))
+    }
+
+    fn push(&mut self, from: usize, to: usize) {
+        let mut q = self.quadrics[from];
+        q.add(&self.quadrics[to]);
+        // costs are never negative, so the bit patterns are ordered the same way
+        let cost = q.error(self.positions[to]).max(0.0);
+        self.heap
+            .push(Reverse((cost.to_bits(), self.collapses.len())));
+        self.collapses.push(Collapse {
+            from,
+            to,
+            stamps: (self.stamps[from], self.stamps[to]),
+        });
+    }
+
+    fn push_around(&mut self, vertex: usize) {
+        let mut neighbors = Vec::new();
+        for &t in self.vertex_triangles[vertex].iter() {
+            if self.alive[t] {
+                neighbors.extend(self.triangles[t].iter().filter(|&&v| v != vertex));
+            }
+        }
+        neighbors.sort();
+        neighbors.dedup();
+        for other in neighbors {
+            self.push(vertex, other);
+            self.push(other, vertex);
+        }
+    }
+
+    // Moving `from` onto `to` must not turn any of the remaining triangles over.
+    fn flips(&self, from: usize, to: usize) -> bool {
+        self.vertex_triangles[from].iter().any(|&t| {
+            let tri = self.triangles[t];
+            if !self.alive[t] || tri.contains(&to) {
+                return false;
+            }
+            let moved = [
+                if tri[0] == from { to } else { tri[0] },
+                if tri[1] == from { to } else { tri[1] },
+                if tri[2] == from { to } else { tri[2] },
+            ];
+            dot(self.normal(tri), self.normal(moved)) <= 0.0
+        })
+    }
+
+    // Returns the number of triangles removed.
+    fn collapse(&mut self, from: usize, to: usize) -> usize {
+        let mut removed = 0;
+        let triangles = std::mem::replace(&mut self.vertex_triangles[from], Vec::new());
+        for t in triangles {
+            if !self.alive[t] {
+                continue;
+            }
+            if self.triangles[t].contains(&to) {
+                self.alive[t] = false;
+                removed += 1;
+            } else {
+                for v in self.triangles[t].iter_mut() {
+                    if *v == from {
+                        *v = to;
+                    }
+                }
+                self.vertex_triangles[to].push(t);
+            }
+        }
+        let q = self.quadrics[from];
+        self.quadrics[to].add(&q);
+        self.removed[from] = true;
+        self.stamps[to] += 1;
+        self.push_around(to);
+        removed
+    }
+}
+
+impl Geometry<DrawTriangle> {
+    // Quadric error metric simplification down to roughly `target_ratio`
+    // of the triangles. Each collapse moves a vertex onto its neighbor,
+    // so the surviving vertices and the triangle materials are kept intact.
+    pub fn decimate(&self, target_ratio: f32) -> Self {
+        let positions = self
+            .positions
+            .iter()
+            .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+            .collect::<Vec<_>>();
+        let triangles = self
+            .polygons
+            .iter()
+            .map(|p| {
+                [
+                    p.vertices[0].pos as usize,
+                    p.vertices[1].pos as usize,
+                    p.vertices[2].pos as usize,
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut mesh = Mesh {
+            quadrics: vec![Quadric::default(); positions.len()],
+            vertex_triangles: vec![Vec::new(); positions.len()],
+            stamps: vec![0; positions.len()],
+            removed: vec![false; positions.len()],
+            alive: triangles
+                .iter()
+                .map(|&[a, b, c]| a != b && b != c && c != a)
+                .collect(),
+            positions,
+            triangles,
+            heap: BinaryHeap::new(),
+            collapses: Vec::new(),
+        };
+
+        let mut edge_uses = HashMap::new();
+        for (t, &tri) in mesh.triangles.iter().enumerate() {
+            if !mesh.alive[t] {
+                continue;
+            }
+            let raw = mesh.normal(tri);
+            let n = match normalize(raw) {
+                Some(n) => n,
+                None => continue,
+            };
+            let area = 0.5 * dot(raw, raw).sqrt();
+            let plane = Quadric::from_plane(n, -dot(n, mesh.positions[tri[0]]), area);
+            for i in 0..3 {
+                mesh.quadrics[tri[i]].add(&plane);
+                mesh.vertex_triangles[tri[i]].push(t);
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                edge_uses
+                    .entry((a.min(b), a.max(b)))
+                    .or_insert_with(Vec::new)
+                    .push((t, a, b));
+            }
+        }
+        for uses in edge_uses.values() {
+            if let [(t, a, b)] = uses[..] {
+                let n = match normalize(mesh.normal(mesh.triangles[t])) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let edge = sub(mesh.positions[b], mesh.positions[a]);
+                if let Some(m) = normalize(cross(edge, n)) {
+                    let weight = BOUNDARY_WEIGHT * dot(edge, edge);
+                    let plane = Quadric::from_plane(m, -dot(m, mesh.positions[a]), weight);
+                    mesh.quadrics[a].add(&plane);
+                    mesh.quadrics[b].add(&plane);
+                }
+            }
+        }
+
+        let mut live = mesh.alive.iter().filter(|&&alive| alive).count();
+        let target = (live as f32 * target_ratio.max(0.0).min(1.0)).round() as usize;
+        for v in 0..mesh.positions.len() {
+            mesh.push_around(v);
+        }
+
+        while live > target {
+            let index = match mesh.heap.pop() {
+                Some(Reverse((_, index))) => index,
+                None => break,
+            };
+            let Collapse { from, to, stamps } = mesh.collapses[index];
+            if mesh.removed[from]
+                || mesh.removed[to]
+                || stamps != (mesh.stamps[from], mesh.stamps[to])
+                || mesh.flips(from, to)
+            {
+                continue;
+            }
+            live -= mesh.collapse(from, to);
+        }
+
+        self.rebuild(&mesh)
+    }
+
+    fn rebuild(&self, mesh: &Mesh) -> Self {
+        let mut position_map = vec![None; self.positions.len()];
+        let mut normal_map = HashMap::new();
+        let mut result = Geometry {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            polygons: Vec::new(),
+        };
+
+        for (t, polygon) in self.polygons.iter().enumerate() {
+            if !mesh.alive[t] {
+                continue;
+            }
+            let tri = mesh.triangles[t];
+            let mut vertices = [Vertex::DUMMY; 3];
+            for (i, (vertex, &pos)) in vertices.iter_mut().zip(tri.iter()).enumerate() {
+                let positions = &mut result.positions;
+                vertex.pos = *position_map[pos].get_or_insert_with(|| {
+                    positions.push(self.positions[pos]);
+                    positions.len() as u16 - 1
+                });
+                let normal = polygon.vertices[i].normal;
+                let normals = &mut result.normals;
+                vertex.normal = *normal_map.entry(normal).or_insert_with(|| {
+                    normals.push(self.normals[normal as usize]);
+                    normals.len() as u16 - 1
+                });
+            }
+            let flat_normal = match normalize(mesh.normal(tri)) {
+                Some(n) => [
+                    (n[0] * NORMALIZER as f64) as i8,
+                    (n[1] * NORMALIZER as f64) as i8,
+                    (n[2] * NORMALIZER as f64) as i8,
+                ],
+                None => polygon.flat_normal,
+            };
+            result.polygons.push(DrawTriangle {
+                vertices,
+                flat_normal,
+                material: polygon.material,
+            });
+        }
+
+        result
+    }
+}
@@ -1,3 +1,4 @@
+mod decimate;
 mod geometry;
 
 pub use self::geometry::{
@@ -6,25 +7,56 @@ pub use self::geometry::{
 
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::Write};
+use std::{collections::HashMap, error::Error, fmt, fs::File, io, io::Write};
 
 const MAX_SLOTS: usize = 3;
 const MAGIC_VERSION: u32 = 8;
 
-fn read_vec_i32<I: ReadBytesExt>(source: &mut I) -> [i32; 3] {
-    [
-        source.read_i32::<E>().unwrap(),
-        source.read_i32::<E>().unwrap(),
-        source.read_i32::<E>().unwrap(),
-    ]
+#[derive(Debug)]
+pub enum M3dError {
+    Io(io::Error),
+    BadMagic { found: u32, expected: u32 },
+    BadCornerCount(u32),
+    TooManyVertices(usize),
 }
 
-fn read_vec_i8<I: ReadBytesExt>(source: &mut I) -> [i8; 3] {
-    [
-        source.read_i8().unwrap(),
-        source.read_i8().unwrap(),
-        source.read_i8().unwrap(),
-    ]
+impl fmt::Display for M3dError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            M3dError::Io(ref e) => write!(f, "Unable to read the model: {}", e),
+            M3dError::BadMagic { found, expected } => write!(
+                f,
+                "Unsupported model version {}, expected {}",
+                found, expected
+            ),
+            M3dError::BadCornerCount(count) => {
+                write!(f, "Unsupported polygon with {} corners", count)
+            }
+            M3dError::TooManyVertices(count) => {
+                write!(f, "Unable to index {} vertices with 16 bits", count)
+            }
+        }
+    }
+}
+
+impl Error for M3dError {}
+
+impl From<io::Error> for M3dError {
+    fn from(e: io::Error) -> Self {
+        M3dError::Io(e)
+    }
+}
+
+fn read_vec_i32<I: ReadBytesExt>(source: &mut I) -> io::Result<[i32; 3]> {
+    Ok([
+        source.read_i32::<E>()?,
+        source.read_i32::<E>()?,
+        source.read_i32::<E>()?,
+    ])
+}
+
+fn read_vec_i8<I: ReadBytesExt>(source: &mut I) -> io::Result<[i8; 3]> {
+    Ok([source.read_i8()?, source.read_i8()?, source.read_i8()?])
 }
 
 fn write_vec_i32<W: WriteBytesExt>(dest: &mut W, v: [i32; 3]) {
@@ -47,13 +79,13 @@ pub struct Physics {
 }
 
 impl Physics {
-    fn load<I: ReadBytesExt>(source: &mut I) -> Self {
+    fn load<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
         let mut q = [0.0f32; 1 + 3 + 9];
         for qel in q.iter_mut() {
-            *qel = source.read_f64::<E>().unwrap() as f32;
+            *qel = source.read_f64::<E>()? as f32;
         }
 
-        Physics {
+        Ok(Physics {
             volume: q[0],
             rcm: [q[1], q[2], q[3]],
             jacobi: [
@@ -61,7 +93,7 @@ impl Physics {
                 [q[5], q[8], q[11]],
                 [q[6], q[9], q[12]],
             ],
-        }
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -168,15 +200,15 @@ pub struct UpperBound {
 }
 
 impl UpperBound {
-    fn read<I: ReadBytesExt>(source: &mut I) -> Self {
-        UpperBound {
+    fn read<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
+        Ok(UpperBound {
             dimensions: [
-                source.read_u32::<E>().unwrap(),
-                source.read_u32::<E>().unwrap(),
-                source.read_u32::<E>().unwrap(),
+                source.read_u32::<E>()?,
+                source.read_u32::<E>()?,
+                source.read_u32::<E>()?,
             ],
-            radius: source.read_u32::<E>().unwrap(),
-        }
+            radius: source.read_u32::<E>()?,
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -194,11 +226,11 @@ pub struct BodyColor {
 }
 
 impl BodyColor {
-    fn read<I: ReadBytesExt>(source: &mut I) -> Self {
-        BodyColor {
-            offset: source.read_u32::<E>().unwrap(),
-            shift: source.read_u32::<E>().unwrap(),
-        }
+    fn read<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
+        Ok(BodyColor {
+            offset: source.read_u32::<E>()?,
+            shift: source.read_u32::<E>()?,
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -231,11 +263,11 @@ pub struct Bounds {
 }
 
 impl Bounds {
-    fn read<I: ReadBytesExt>(source: &mut I) -> Self {
-        Bounds {
-            coord_max: read_vec_i32(source),
-            coord_min: read_vec_i32(source),
-        }
+    fn read<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
+        Ok(Bounds {
+            coord_max: read_vec_i32(source)?,
+            coord_min: read_vec_i32(source)?,
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -284,6 +316,61 @@ impl Polygon for CollisionQuad {
     }
 }
 
+impl<P: Polygon> Geometry<P> {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    // Polygon corners, each referencing a position and a normal.
+    pub fn index_count(&self) -> usize {
+        self.polygons.len() * P::num_vertices() as usize
+    }
+
+    // Approximate footprint: the vertex data plus a `Vertex` per polygon corner.
+    pub fn byte_size(&self) -> usize {
+        use std::mem::size_of;
+        (self.positions.len() + self.normals.len()) * size_of::<[i8; 3]>()
+            + self.index_count() * size_of::<Vertex>()
+    }
+}
+
+// A triangle corner with its color, as it's drawn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IndexedVertex {
+    pub pos: u16,
+    pub normal: u16,
+    pub color: u32,
+}
+
+impl Geometry<DrawTriangle> {
+    // Merges the corners sharing the position, the normal, and the color,
+    // returning the unique vertices and 3 indices per triangle.
+    pub fn compact(&self) -> Result<(Vec<IndexedVertex>, Vec<u16>), M3dError> {
+        let mut vertices = Vec::new();
+        let mut lookup = HashMap::new();
+        let mut indices = Vec::with_capacity(self.polygons.len() * 3);
+        for tri in self.polygons.iter() {
+            for v in tri.vertices.iter() {
+                let key = IndexedVertex {
+                    pos: v.pos,
+                    normal: v.normal,
+                    color: tri.material[0],
+                };
+                let index = *lookup.entry(key).or_insert_with(|| {
+                    vertices.push(key);
+                    vertices.len() - 1
+                });
+                indices.push(index);
+            }
+        }
+        if vertices.len() > u16::MAX as usize + 1 {
+            return Err(M3dError::TooManyVertices(vertices.len()));
+        }
+        let indices = indices.into_iter().map(|i| i as u16).collect();
+        Ok((vertices, indices))
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Mesh<G> {
     pub geometry: G,
@@ -308,13 +395,18 @@ impl<G> Mesh<G> {
 }
 
 impl<P: Polygon> Mesh<Geometry<P>> {
-    pub fn load<I: ReadBytesExt>(source: &mut I) -> Self {
-        let version = source.read_u32::<E>().unwrap();
-        assert_eq!(version, MAGIC_VERSION);
-        let num_positions = source.read_u32::<E>().unwrap();
-        let num_normals = source.read_u32::<E>().unwrap();
-        let num_polygons = source.read_u32::<E>().unwrap();
-        let _total_verts = source.read_u32::<E>().unwrap();
+    pub fn load<I: ReadBytesExt>(source: &mut I) -> Result<Self, M3dError> {
+        let version = source.read_u32::<E>()?;
+        if version != MAGIC_VERSION {
+            return Err(M3dError::BadMagic {
+                found: version,
+                expected: MAGIC_VERSION,
+            });
+        }
+        let num_positions = source.read_u32::<E>()?;
+        let num_normals = source.read_u32::<E>()?;
+        let num_polygons = source.read_u32::<E>()?;
+        let _total_verts = source.read_u32::<E>()?;
 
         let mut result = Mesh {
             geometry: Geometry {
@@ -322,11 +414,11 @@ impl<P: Polygon> Mesh<Geometry<P>> {
                 normals: Vec::with_capacity(num_normals as usize),
                 polygons: Vec::with_capacity(num_polygons as usize),
             },
-            bounds: Bounds::read(source),
-            parent_off: read_vec_i32(source),
-            max_radius: source.read_u32::<E>().unwrap(),
-            parent_rot: read_vec_i32(source),
-            physics: Physics::load(source),
+            bounds: Bounds::read(source)?,
+            parent_off: read_vec_i32(source)?,
+            max_radius: source.read_u32::<E>()?,
+            parent_rot: read_vec_i32(source)?,
+            physics: Physics::load(source)?,
         };
         log::debug!(
             "\tBounds {:?} with offset {:?}",
@@ -336,55 +428,69 @@ impl<P: Polygon> Mesh<Geometry<P>> {
 
         log::debug!("\tReading {} positions...", num_positions);
         for _ in 0..num_positions {
-            read_vec_i32(source); //unknown
-            let pos = read_vec_i8(source);
-            let _sort_info = source.read_u32::<E>().unwrap();
+            read_vec_i32(source)?; //unknown
+            let pos = read_vec_i8(source)?;
+            let _sort_info = source.read_u32::<E>()?;
             result.geometry.positions.push(pos);
         }
 
         log::debug!("\tReading {} normals...", num_normals);
         for _ in 0..num_normals {
-            let norm = read_vec_i8(source);
-            let _something = source.read_i8().unwrap();
-            let _sort_info = source.read_u32::<E>().unwrap();
+            let norm = read_vec_i8(source)?;
+            let _something = source.read_i8()?;
+            let _sort_info = source.read_u32::<E>()?;
             result.geometry.normals.push(norm);
         }
 
         log::debug!("\tReading {} polygons...", num_polygons);
         let mut vertices = Vec::with_capacity(4);
         for _ in 0..num_polygons {
-            let num_corners = source.read_u32::<E>().unwrap();
-            let _sort_info = source.read_u32::<E>().unwrap();
-            let material = [
-                source.read_u32::<E>().unwrap(),
-                source.read_u32::<E>().unwrap(),
-            ];
-            let flat_normal = read_vec_i8(source);
-            let _something = source.read_i8().unwrap();
-            let middle = read_vec_i8(source);
-
+            let num_corners = source.read_u32::<E>()?;
+            let _sort_info = source.read_u32::<E>()?;
+            let material = [source.read_u32::<E>()?, source.read_u32::<E>()?];
+            let flat_normal = read_vec_i8(source)?;
+            let _something = source.read_i8()?;
+            let middle = read_vec_i8(source)?;
+
+            // quads are split into triangles for the draw meshes
+            let fits =
+                num_corners == P::num_vertices() || (P::num_vertices() == 3 && num_corners == 4);
+            if !fits {
+                return Err(M3dError::BadCornerCount(num_corners));
+            }
             vertices.clear();
             for _ in 0..num_corners {
                 vertices.push(Vertex {
-                    pos: source.read_u32::<E>().unwrap() as u16,
-                    normal: source.read_u32::<E>().unwrap() as u16,
+                    pos: source.read_u32::<E>()? as u16,
+                    normal: source.read_u32::<E>()? as u16,
                 });
             }
 
-            result
-                .geometry
-                .polygons
-                .push(P::new(middle, flat_normal, material, &vertices));
+            if vertices.len() == P::num_vertices() as usize {
+                result
+                    .geometry
+                    .polygons
+                    .push(P::new(middle, flat_normal, material, &vertices));
+            } else {
+                // split into a fan around the first corner
+                for k in 1..vertices.len() - 1 {
+                    let corners = [vertices[0], vertices[k], vertices[k + 1]];
+                    result
+                        .geometry
+                        .polygons
+                        .push(P::new(middle, flat_normal, material, &corners));
+                }
+            }
         }
 
         // sorted variable polygons
         for _ in 0..3 {
             for _ in 0..num_polygons {
-                let _poly_ind = source.read_u32::<E>().unwrap();
+                let _poly_ind = source.read_u32::<E>()?;
             }
         }
 
-        result
+        Ok(result)
     }
 
     pub fn save<W: Write>(&self, dest: &mut W) {
@@ -459,13 +565,15 @@ pub struct AnimatedMesh<G> {
 }
 
 impl<P: Polygon> AnimatedMesh<Geometry<P>> {
-    pub fn load(mut input: File) -> Self {
-        let count = input.read_u32::<E>().unwrap();
-        AnimatedMesh {
-            bound: UpperBound::read(&mut input),
-            color: BodyColor::read(&mut input),
-            meshes: (0..count).map(|_| Mesh::load(&mut input)).collect(),
-        }
+    pub fn load(mut input: File) -> Result<Self, M3dError> {
+        let count = input.read_u32::<E>()?;
+        Ok(AnimatedMesh {
+            bound: UpperBound::read(&mut input)?,
+            color: BodyColor::read(&mut input)?,
+            meshes: (0..count)
+                .map(|_| Mesh::load(&mut input))
+                .collect::<Result<_, _>>()?,
+        })
     }
 
     pub fn save(&self, mut output: File) {
@@ -481,29 +589,29 @@ impl<P: Polygon> AnimatedMesh<Geometry<P>> {
 pub type FullModel = Model<DrawMesh, CollisionMesh>;
 
 impl FullModel {
-    pub fn load(mut input: File) -> Self {
+    pub fn load(mut input: File) -> Result<Self, M3dError> {
         log::debug!("\tReading the body...");
-        let body: DrawMesh = Mesh::load(&mut input);
+        let body: DrawMesh = Mesh::load(&mut input)?;
 
-        let bound = UpperBound::read(&mut input);
-        let num_wheels = input.read_u32::<E>().unwrap();
-        let num_debris = input.read_u32::<E>().unwrap();
-        let color = BodyColor::read(&mut input);
+        let bound = UpperBound::read(&mut input)?;
+        let num_wheels = input.read_u32::<E>()?;
+        let num_debris = input.read_u32::<E>()?;
+        let color = BodyColor::read(&mut input)?;
 
         let mut wheels = Vec::with_capacity(num_wheels as usize);
         log::debug!("\tReading {} wheels...", num_wheels);
         for _ in 0..num_wheels {
-            let steer = input.read_u32::<E>().unwrap();
+            let steer = input.read_u32::<E>()?;
             let pos = [
-                input.read_f64::<E>().unwrap() as f32,
-                input.read_f64::<E>().unwrap() as f32,
-                input.read_f64::<E>().unwrap() as f32,
+                input.read_f64::<E>()? as f32,
+                input.read_f64::<E>()? as f32,
+                input.read_f64::<E>()? as f32,
             ];
-            let width = input.read_u32::<E>().unwrap();
-            let radius = input.read_u32::<E>().unwrap();
-            let bound_index = input.read_u32::<E>().unwrap();
+            let width = input.read_u32::<E>()?;
+            let radius = input.read_u32::<E>()?;
+            let bound_index = input.read_u32::<E>()?;
             let mesh: Option<DrawMesh> = if steer != 0 {
-                Some(Mesh::load(&mut input))
+                Some(Mesh::load(&mut input)?)
             } else {
                 None
             };
@@ -522,26 +630,26 @@ impl FullModel {
         log::debug!("\tReading {} debris...", num_debris);
         for _ in 0..num_debris {
             debris.push(Debrie {
-                mesh: Mesh::load(&mut input),
-                shape: Mesh::load(&mut input),
+                mesh: Mesh::load(&mut input)?,
+                shape: Mesh::load(&mut input)?,
             });
         }
 
         log::debug!("\tReading the shape...");
-        let shape: CollisionMesh = Mesh::load(&mut input);
+        let shape: CollisionMesh = Mesh::load(&mut input)?;
 
         let mut slots = [Slot::EMPTY, Slot::EMPTY, Slot::EMPTY];
-        let slot_mask = input.read_u32::<E>().unwrap();
+        let slot_mask = input.read_u32::<E>()?;
         log::debug!("\tReading {} slot mask...", slot_mask);
         for slot in &mut slots {
             for p in &mut slot.pos {
-                *p = input.read_i32::<E>().unwrap();
+                *p = input.read_i32::<E>()?;
             }
-            slot.angle = input.read_i32::<E>().unwrap();
+            slot.angle = input.read_i32::<E>()?;
             slot.scale = 1.0;
         }
 
-        FullModel {
+        Ok(FullModel {
             body,
             shape,
             bound,
@@ -549,7 +657,7 @@ impl FullModel {
             wheels,
             debris,
             slots,
-        }
+        })
     }
 
     pub fn save(&self, mut output: File) {
@@ -1,5 +1,5 @@
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 pub struct Splay {
     tree1: [i32; 512],
@@ -25,15 +25,16 @@ impl Splay {
         splay
     }
 
-    pub fn write_trivial<O: WriteBytesExt>(output: &mut O) {
+    pub fn write_trivial<O: WriteBytesExt>(output: &mut O) -> io::Result<()> {
         for _ in 0..2 {
             for i in 0i32..256 {
-                output.write_i32::<E>(i).unwrap();
+                output.write_i32::<E>(i)?;
             }
             for i in 0i32..256 {
-                output.write_i32::<E>(-i).unwrap();
+                output.write_i32::<E>(-i)?;
             }
         }
+        Ok(())
     }
 
     pub fn tree_size() -> u64 {
@@ -105,16 +106,21 @@ impl Splay {
         assert_eq!(off1 + off2, input.len());
     }
 
-    pub fn compress_trivial<O: Write>(input1: &[u8], input2: &[u8], output: &mut O) {
+    pub fn compress_trivial<O: Write>(
+        input1: &[u8],
+        input2: &[u8],
+        output: &mut O,
+    ) -> io::Result<()> {
         let mut last_char = 0;
         for &b in input1 {
-            output.write_u8(b.wrapping_sub(last_char)).unwrap();
+            output.write_u8(b.wrapping_sub(last_char))?;
             last_char = b;
         }
         last_char = 0;
         for &b in input2 {
-            output.write_u8(b ^ last_char).unwrap();
+            output.write_u8(b ^ last_char)?;
             last_char = b;
         }
+        Ok(())
     }
 }
@@ -0,0 +1,30 @@
+//!include fs:globals.inc
+
+layout(location = 0) varying vec2 v_Ndc;
+
+#ifdef SHADER_VS
+
+void main() {
+    // a single triangle covering the whole screen
+    v_Ndc = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1) * 4.0 - 1.0;
+    gl_Position = vec4(v_Ndc, 1.0, 1.0);
+}
+#endif //VS
+
+
+#ifdef SHADER_FS
+
+layout(set = 1, binding = 0) uniform c_Background {
+    vec4 u_Zenith;
+    vec4 u_Horizon;
+};
+
+layout(location = 0) out vec4 o_Color;
+
+void main() {
+    vec4 near = u_InvViewProj * vec4(v_Ndc, 0.0, 1.0);
+    vec4 far = u_InvViewProj * vec4(v_Ndc, 1.0, 1.0);
+    vec3 dir = normalize(far.xyz / far.w - near.xyz / near.w);
+    o_Color = mix(u_Horizon, u_Zenith, clamp(dir.z, 0.0, 1.0));
+}
+#endif //FS
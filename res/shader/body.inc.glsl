@@ -1,5 +1,3 @@
-#define MAX_WHEELS  4
-
 struct Physics {
     vec4 scale; // size, bound, box, z offset of center
     vec4 mobility_ship; // X = mobility, YZW = water
@@ -28,8 +26,12 @@ struct Body {
     Model model;
     Physics physics;
     vec4 wheels[MAX_WHEELS]; //XYZ = position, W = steer
+    uvec4 flags; // X = collision mask, Y = active
 };
 
+#define COLLISION_LAYER_TERRAIN 1U
+#define COLLISION_LAYER_BODY 2U
+
 struct DragConstants {
     vec2 free;
     vec2 speed;
@@ -43,6 +45,7 @@ struct DragConstants {
 
 struct GlobalConstants {
     vec4 nature; // X = time delta0, Y = density, Z = gravity
+    vec4 gravity; // XYZ = acceleration vector
     vec4 global_speed; // X = main, Y = water, Z = air, W = underground
     vec4 global_mobility; // X = mobility
     vec4 car_rudder; // X = step, Y = max, Z = decr
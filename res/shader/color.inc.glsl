@@ -8,6 +8,8 @@ layout(set = 1, binding = 5) uniform utexture1D t_Table;
 // corresponds to SDL palette
 layout(set = 1, binding = 6) uniform texture1D t_Palette;
 layout(set = 1, binding = 8) uniform sampler s_FloodSampler;
+// Height gradient, precomputed from the height map.
+layout(set = 1, binding = 10) uniform texture2D t_Normal;
 
 layout(set = 0, binding = 1) uniform sampler s_PaletteSampler;
 
@@ -38,9 +40,7 @@ float evaluate_palette(uint type, float value, float ycoord) {
 }
 
 float evaluate_color_id(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
-    float diff =
-        textureLodOffset(sampler2D(t_Height, s_MainSampler), tex_coord, 0.0, ivec2(1, 0)).x -
-        textureLodOffset(sampler2D(t_Height, s_MainSampler), tex_coord, 0.0, ivec2(-1, 0)).x;
+    float diff = 2.0 * textureLod(sampler2D(t_Normal, s_MainSampler), tex_coord, 0.0).x;
     vec3 mat = type == 0U ? vec3(5.0, 1.25, 0.5) : vec3(1.0);
     float light_clr = evaluate_light(mat, diff);
     float tmp = light_clr - c_HorFactor * (1.0 - height_normalized);
@@ -1,20 +1,40 @@
-//!include vs:body.inc vs:globals.inc vs:encode.inc vs:quat.inc vs:shape.inc
+//!include vs:body.inc vs:globals.inc vs:encode.inc vs:quat.inc
 
 #ifdef SHADER_VS
-//imported: Polygon, get_shape_polygon
+//imported: decode_pos, qrot
 
 layout(set = 0, binding = 2, std430) readonly buffer Storage {
     Body s_Bodies[];
 };
 
+layout(set = 2, binding = 0, std430) readonly buffer Positions {
+    uint r_Positions[];
+};
+// `ShapePolygon` words: 2 for the indices, 1 for the normal, 4 for the origin and square
+layout(set = 2, binding = 1, std430) readonly buffer Polygons {
+    uint r_Polygons[];
+};
+const int c_PolygonWords = 7;
+
 layout(location = 3) attribute vec4 a_PosScale;
 layout(location = 4) attribute vec4 a_Orientation;
 layout(location = 5) attribute float a_ShapeScale;
 layout(location = 6) attribute uvec2 a_BodyAndColorId;
 
+// The object instances are repeated for each polygon,
+// so that all the models sharing the shape are drawn at once.
+vec3 get_polygon_vertex() {
+    int count = r_Polygons.length() / c_PolygonWords;
+    int base = (gl_InstanceIndex % count) * c_PolygonWords;
+    // same corner order as in "shape.inc"
+    uint corner = uvec4(0U, 1U, 3U, 2U)[gl_VertexIndex];
+    uint pair = r_Polygons[base + int(corner >> 1U)];
+    uint index = (pair >> (16U * (corner & 1U))) & 0xFFFFU;
+    return decode_pos(r_Positions[int(index)]);
+}
+
 void main() {
-    Polygon poly = get_shape_polygon();
-    vec3 vertex = vec3(poly.vertex) * a_ShapeScale;
+    vec3 vertex = get_polygon_vertex() * a_ShapeScale;
     vec3 local = qrot(a_Orientation, vertex) * a_PosScale.w + a_PosScale.xyz;
 
     int body_id = int(a_BodyAndColorId.x);
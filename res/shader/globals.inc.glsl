@@ -1,8 +1,46 @@
+#define MAX_LIGHTS 4
+
+struct Light {
+    vec4 pos; // W = 0 for directional, 1 for point
+    vec4 color;
+    vec4 params; // X = range of a point light, 0 = unlimited
+};
+
 layout(set = 0, binding = 0) uniform Globals {
     vec4 u_CameraPos;
     mat4 u_ViewProj;
     mat4 u_InvViewProj;
     mat4 u_LightViewProj;
-    vec4 u_LightPos;
-    vec4 u_LightColor; // not used
+    vec4 u_Time; // X = total, Y = frame delta
+    vec4 u_FogColor;
+    vec4 u_FogParams; // X = density, Y = start distance
+    uvec4 u_LightCount; // X = active lights
+    Light u_Lights[MAX_LIGHTS]; // 0 = main light, color not used
 };
+
+// Direction to the main light, which is the one casting shadows.
+vec3 main_light_dir(vec3 pos) {
+    return normalize(u_Lights[0].pos.xyz - pos * u_Lights[0].pos.w);
+}
+
+// Diffuse contribution of the dynamic lights after the main one,
+// weighted by their brightness, since the colors come from the palette.
+float extra_lighting(vec3 pos, vec3 normal) {
+    float total = 0.0;
+    for (uint i = 1U; i < u_LightCount.x; ++i) {
+        Light light = u_Lights[i];
+        vec3 dir = light.pos.xyz - pos * light.pos.w;
+        float range = light.pos.w * light.params.x;
+        float fade = range > 0.0 ? clamp(1.0 - length(dir) / range, 0.0, 1.0) : 1.0;
+        float brightness = dot(light.color.rgb, vec3(0.299, 0.587, 0.114));
+        total += fade * brightness * max(0.0, dot(normal, normalize(dir)));
+    }
+    return total;
+}
+
+// Exponential fog, driven by the true distance to the camera.
+vec4 apply_exp_fog(vec4 color, vec3 world_pos) {
+    float dist = max(0.0, length(world_pos - u_CameraPos.xyz) - u_FogParams.y);
+    float amount = 1.0 - exp(-u_FogParams.x * dist);
+    return mix(color, u_FogColor, amount);
+}
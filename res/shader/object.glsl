@@ -1,15 +1,18 @@
 //!include vs:body.inc vs:globals.inc vs:quat.inc fs:globals.inc fs:shadow.inc
-//!specialization COLOR
+//!specialization COLOR OUTLINE
 
 #if COLOR
 layout(location = 0) varying vec2 v_PaletteRange;
 layout(location = 1) varying vec3 v_Position;
 layout(location = 2) varying vec3 v_Normal;
+layout(location = 3) flat varying uint v_PaletteId;
 #endif
 
 #ifdef SHADER_VS
 
 const uint BODY_COLOR_ID = 1;
+// in model units, so that the outline follows the model scale
+const float OUTLINE_WIDTH = 2.0;
 
 layout(set = 0, binding = 2, std430) readonly buffer Storage {
     Body s_Bodies[];
@@ -23,19 +26,28 @@ layout(set = 1, binding = 2) uniform sampler s_ColorTableSampler;
 layout(location = 0) attribute ivec4 a_Vertex;
 #if COLOR
 layout(location = 1) attribute uint a_ColorIndex;
+#endif
+#if COLOR || OUTLINE
 layout(location = 2) attribute vec4 a_Normal;
 #endif
 
 layout(location = 3) attribute vec4 a_PosScale;
 layout(location = 4) attribute vec4 a_Orientation;
 layout(location = 6) attribute uvec2 a_BodyAndColorId;
+#if COLOR
+layout(location = 7) attribute uint a_PaletteId;
+#endif
 
 void main() {
     int body_id = int(a_BodyAndColorId.x);
     vec4 body_pos_scale = s_Bodies[body_id].pos_scale;
     vec4 body_orientation = s_Bodies[body_id].orientation;
 
-    vec3 local = qrot(a_Orientation, vec3(a_Vertex.xyz)) * a_PosScale.w + a_PosScale.xyz;
+    vec3 vertex = vec3(a_Vertex.xyz);
+    #if OUTLINE
+    vertex += normalize(a_Normal.xyz) * OUTLINE_WIDTH;
+    #endif
+    vec3 local = qrot(a_Orientation, vertex) * a_PosScale.w + a_PosScale.xyz;
     vec3 world = qrot(body_orientation, local) * body_pos_scale.w + body_pos_scale.xyz;
     gl_Position = u_ViewProj * vec4(world, 1.0);
     #if COLOR
@@ -46,6 +58,7 @@ void main() {
     vec3 n = normalize(a_Normal.xyz);
     v_Position = world;
     v_Normal = qrot(body_orientation, qrot(a_Orientation, n));
+    v_PaletteId = a_PaletteId;
     #endif //COLOR
 }
 #endif //VS
@@ -55,7 +68,12 @@ void main() {
 
 #if COLOR
 layout(set = 0, binding = 1) uniform sampler s_PaletteSampler;
-layout(set = 1, binding = 1) uniform texture1D t_Palette;
+layout(set = 1, binding = 1) uniform texture2D t_Palette;
+
+layout(location = 0) out vec4 o_Color;
+#endif
+#if OUTLINE
+const vec4 OUTLINE_COLOR = vec4(1.0, 0.8, 0.2, 1.0);
 
 layout(location = 0) out vec4 o_Color;
 #endif
@@ -64,11 +82,15 @@ void main() {
     #if COLOR
     float lit_factor = fetch_shadow(v_Position);
     vec3 normal = normalize(v_Normal) * (gl_FrontFacing ? -1.0 : 1.0);
-    vec3 light = normalize(u_LightPos.xyz - v_Position * u_LightPos.w);
-    float n_dot_l = lit_factor * max(0.0, dot(normal, light));
+    vec3 light = main_light_dir(v_Position);
+    float n_dot_l = lit_factor * max(0.0, dot(normal, light)) + extra_lighting(v_Position, normal);
     float tc_raw = mix(v_PaletteRange.x, v_PaletteRange.y, n_dot_l);
     float tc = clamp(tc_raw, v_PaletteRange.x + 0.5, v_PaletteRange.y - 0.5) / 256.0;
-    o_Color = texture(sampler1D(t_Palette, s_PaletteSampler), tc);
+    float row = (float(v_PaletteId) + 0.5) / float(textureSize(sampler2D(t_Palette, s_PaletteSampler), 0).y);
+    o_Color = apply_exp_fog(texture(sampler2D(t_Palette, s_PaletteSampler), vec2(tc, row)), v_Position);
+    #endif
+    #if OUTLINE
+    o_Color = OUTLINE_COLOR;
     #endif
 }
 #endif //FS
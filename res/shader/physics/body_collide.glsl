@@ -0,0 +1,70 @@
+//!include cs:body.inc cs:quat.inc
+
+layout(set = 0, binding = 0, std430) buffer Storage {
+    Body s_Bodies[];
+};
+
+layout(set = 0, binding = 2, std140) uniform Constants {
+    GlobalConstants u_Constants;
+};
+
+#ifdef SHADER_CS
+
+// Fraction of the penetration depth resolved per second.
+const float SEPARATION_RATE = 4.0;
+
+float get_radius(uint index) {
+    return s_Bodies[index].model.jacobi1.w * s_Bodies[index].pos_scale.w;
+}
+
+float get_mass(uint index) {
+    float scale = s_Bodies[index].pos_scale.w;
+    return s_Bodies[index].model.jacobi0.w * scale * scale * scale;
+}
+
+bool is_colliding(uint index) {
+    return s_Bodies[index].flags.y != 0U &&
+        (s_Bodies[index].flags.x & COLLISION_LAYER_BODY) != 0U;
+}
+
+// Brute force body-vs-body pass, where each body is approximated by
+// its bounding sphere. Every invocation only changes the velocity of its own body,
+// reading only the positions and the models of the others, so the pass is free of races.
+void main() {
+    uint index =
+        gl_GlobalInvocationID.z * gl_WorkGroupSize.x * gl_NumWorkGroups.x * gl_WorkGroupSize.y * gl_NumWorkGroups.y +
+        gl_GlobalInvocationID.y * gl_WorkGroupSize.x * gl_NumWorkGroups.x +
+        gl_GlobalInvocationID.x;
+    // the last work group may go past the end of the storage
+    uint count = min(gl_WorkGroupSize.x * gl_NumWorkGroups.x, uint(s_Bodies.length()));
+
+    if (index >= count || !is_colliding(index)) {
+        return;
+    }
+    vec3 pos = s_Bodies[index].pos_scale.xyz;
+    float radius = get_radius(index);
+    float mass = get_mass(index);
+    vec4 irot = qinv(s_Bodies[index].orientation);
+    vec3 vel = s_Bodies[index].v_linear.xyz;
+
+    for (uint i = 0U; i < count; ++i) {
+        if (i == index || !is_colliding(i)) {
+            continue;
+        }
+        vec3 offset = pos - s_Bodies[i].pos_scale.xyz;
+        float dist = length(offset);
+        float depth = radius + get_radius(i) - dist;
+        if (depth <= 0.0 || dist == 0.0) {
+            continue;
+        }
+        // the lighter body takes the most of the response
+        float other_mass = get_mass(i);
+        float share = other_mass / (mass + other_mass);
+        vec3 normal = qrot(irot, offset / dist);
+        float approach = min(dot(vel, normal), 0.0);
+        vel += normal * share * (depth * SEPARATION_RATE - 2.0 * approach);
+    }
+
+    s_Bodies[index].v_linear.xyz = vel;
+}
+#endif //CS
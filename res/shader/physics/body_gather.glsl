@@ -26,6 +26,9 @@ void main() {
 
     uvec2 range = (uvec2(s_Ranges[index]) >> uvec2(0, 16)) & 0xFFFF;
     Body body = s_Bodies[index];
+    if (body.flags.y == 0U || (body.flags.x & COLLISION_LAYER_TERRAIN) == 0U) {
+        return;
+    }
     float scale = body.pos_scale.w * body.physics.scale.y;
     vec3 springs = vec3(0.0);
 
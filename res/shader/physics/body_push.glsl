@@ -24,6 +24,9 @@ void main() {
         return;
     }
     int index = int(push.dir_id.w);
+    if (s_Bodies[index].flags.y == 0U) {
+        return;
+    }
     float device_modulation = 1.0;
     float dt_impulse = 1.0;
 
@@ -63,6 +63,9 @@ void main() {
         gl_GlobalInvocationID.y * gl_WorkGroupSize.x * gl_NumWorkGroups.x +
         gl_GlobalInvocationID.x;
     Body body = s_Bodies[index];
+    if (body.flags.y == 0U) {
+        return;
+    }
 
     vec4 engine = apply_control(body.engine, body.control);
 
@@ -81,7 +84,7 @@ void main() {
         abs(qrot(body.orientation, vec3(1.0, 0.0, 0.0)).z) < 0.7;
     bool after_collision = false; //TODO
 
-    vec3 v_accel = qrot(irot, vec3(0.0, 0.0, body.springs.z - u_Constants.nature.z));
+    vec3 v_accel = qrot(irot, vec3(0.0, 0.0, body.springs.z) + u_Constants.gravity.xyz);
     vec3 w_accel = qrot(irot, vec3(body.springs.xy, 0.0));
     mat3 j_inv = calc_j_inv(body.model, body.pos_scale.w);
 
@@ -120,7 +123,7 @@ void main() {
 
     if (spring_touch || wheels_touch) {
         vec3 tmp = vec3(0.0, 0.0, body.physics.scale.w * body.pos_scale.w);
-        w_accel -= u_Constants.nature.z * cross(tmp, z_axis);
+        w_accel += cross(tmp, qrot(irot, u_Constants.gravity.xyz));
         float vz = dot(z_axis, vel);
         if (vz < -10.0) {
             drag.x *= pow(u_Constants.drag.other.y, -vz);
@@ -0,0 +1,51 @@
+//!include vs:globals.inc vs:terrain/locals.inc vs:surface.inc fs:globals.inc fs:terrain/locals.inc fs:surface.inc fs:shadow.inc fs:color.inc
+//!specialization EARLY_DEPTH
+
+layout(location = 0) varying vec3 v_TexCoord;
+layout(location = 1) flat varying uint v_Type;
+layout(location = 2) varying vec3 v_Pos;
+
+#ifdef SHADER_VS
+layout(location = 0) attribute uvec2 a_Pos;
+
+// the depth pre-pass has to produce exactly the same depth
+invariant gl_Position;
+
+vec2 generate_patch_origin() {
+    float size = float(u_Params.x);
+    vec2 start = floor(u_SampleRange.xz / size);
+    int row_size = int(ceil(u_SampleRange.y / size) - start.x);
+    vec2 rel = vec2(gl_InstanceIndex % row_size, gl_InstanceIndex / row_size);
+    return (start + rel) * size;
+}
+
+void main() {
+    vec2 pos = generate_patch_origin() + vec2(a_Pos);
+
+    // sample at the tile center, so that the vertex takes the tile height
+    Surface suf = get_surface(pos + 0.5);
+    float altitude = suf.high_alt;
+    v_Pos = vec3(pos, altitude);
+
+    v_Type = suf.high_type;
+    v_TexCoord = vec3(suf.tex_coord, altitude / u_TextureScale.z);
+    gl_Position = u_ViewProj * vec4(v_Pos, 1.0);
+}
+#endif //VS
+
+
+#ifdef SHADER_FS
+//imported: Surface, u_TextureScale, get_surface, evaluate_color, apply_fog, apply_exp_fog, fetch_shadow
+
+layout(location = 0) out vec4 o_Color;
+
+#if EARLY_DEPTH
+layout(early_fragment_tests) in;
+#endif
+
+void main() {
+    float lit_factor = fetch_shadow(v_Pos) + extra_lighting(v_Pos, vec3(0.0, 0.0, 1.0));
+    vec4 terrain_color = evaluate_color(v_Type, v_TexCoord.xy, v_TexCoord.z, lit_factor);
+    o_Color = apply_exp_fog(apply_fog(terrain_color, v_Pos.xy), v_Pos);
+}
+#endif //FS
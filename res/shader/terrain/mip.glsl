@@ -1,3 +1,6 @@
+//!include
+//!specialization REDUCE_MIN REDUCE_MODE
+
 #ifdef SHADER_VS
 
 layout(location = 0) in vec2 a_Pos;
@@ -11,18 +14,77 @@ void main() {
 #ifdef SHADER_FS
 
 layout(set = 0, binding = 0) uniform sampler s_Height;
+
+// Computes the range of source texels covered by the current fragment.
+void get_block(ivec2 src_size, out ivec2 start, out ivec2 end) {
+    ivec2 dst_size = max(src_size / 2, ivec2(1));
+    ivec2 dst = ivec2(gl_FragCoord.xy);
+    start = min(dst * 2, src_size - 1);
+    // the last texel in a row/column of an odd-sized level also covers the remainder
+    end = min(mix(start + 2, src_size, equal(dst, dst_size - 1)), src_size);
+}
+
+#if REDUCE_MODE
+
+// Terrain type bits of the meta, see "surface.inc.glsl"
+const uint c_TerrainShift = 3U;
+const int c_TerrainCount = 8;
+
+layout(set = 0, binding = 1) uniform utexture2D t_Meta;
+
+layout(location = 0) out uint o_Meta;
+
+// Picks the meta of the dominant terrain type in the block,
+// since the terrain types can't be meaningfully averaged.
+void main() {
+    ivec2 start, end;
+    get_block(textureSize(usampler2D(t_Meta, s_Height), 0), start, end);
+
+    uint counts[c_TerrainCount];
+    for (int i = 0; i < c_TerrainCount; ++i) {
+        counts[i] = 0U;
+    }
+    uint result = 0U, best = 0U;
+    for (int y = start.y; y < end.y; ++y) {
+        for (int x = start.x; x < end.x; ++x) {
+            uint meta = texelFetch(usampler2D(t_Meta, s_Height), ivec2(x, y), 0).x;
+            uint type = (meta >> c_TerrainShift) & uint(c_TerrainCount - 1);
+            counts[type] += 1U;
+            if (counts[type] > best) {
+                best = counts[type];
+                result = meta;
+            }
+        }
+    }
+    o_Meta = result;
+}
+
+#else //REDUCE_MODE
+
 layout(set = 0, binding = 1) uniform texture2D t_Height;
 
 layout(location = 0) out float o_Height;
 
+float reduce(float a, float b) {
+#if REDUCE_MIN
+    return min(a, b);
+#else
+    return max(a, b);
+#endif
+}
+
 void main() {
-    ivec2 tc = ivec2(gl_FragCoord.xy * 2.0);
-    vec4 heights = vec4(
-        texelFetch(sampler2D(t_Height, s_Height), tc - ivec2(0, 0), 0).x,
-        texelFetch(sampler2D(t_Height, s_Height), tc - ivec2(0, 1), 0).x,
-        texelFetch(sampler2D(t_Height, s_Height), tc - ivec2(1, 0), 0).x,
-        texelFetch(sampler2D(t_Height, s_Height), tc - ivec2(1, 1), 0).x
-    );
-    o_Height = max(max(heights.x, heights.y), max(heights.z, heights.w));
+    ivec2 start, end;
+    get_block(textureSize(sampler2D(t_Height, s_Height), 0), start, end);
+
+    float result = texelFetch(sampler2D(t_Height, s_Height), start, 0).x;
+    for (int y = start.y; y < end.y; ++y) {
+        for (int x = start.x; x < end.x; ++x) {
+            float h = texelFetch(sampler2D(t_Height, s_Height), ivec2(x, y), 0).x;
+            result = reduce(result, h);
+        }
+    }
+    o_Height = result;
 }
+#endif //REDUCE_MODE
 #endif //FS
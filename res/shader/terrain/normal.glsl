@@ -0,0 +1,31 @@
+#ifdef SHADER_VS
+
+layout(location = 0) in vec2 a_Pos;
+
+void main() {
+    gl_Position = vec4(2.0 * a_Pos - 1.0, 0.0, 1.0);
+}
+#endif //VS
+
+
+#ifdef SHADER_FS
+
+layout(set = 0, binding = 0) uniform sampler s_Height;
+layout(set = 0, binding = 1) uniform texture2D t_Height;
+
+layout(location = 0) out vec2 o_Gradient;
+
+float fetch_height(ivec2 pos, ivec2 size) {
+    ivec2 wrapped = (pos + size) % size;
+    return texelFetch(sampler2D(t_Height, s_Height), wrapped, 0).x;
+}
+
+void main() {
+    ivec2 size = textureSize(sampler2D(t_Height, s_Height), 0);
+    ivec2 pos = ivec2(gl_FragCoord.xy);
+    o_Gradient = 0.5 * vec2(
+        fetch_height(pos + ivec2(1, 0), size) - fetch_height(pos - ivec2(1, 0), size),
+        fetch_height(pos + ivec2(0, 1), size) - fetch_height(pos - ivec2(0, 1), size)
+    );
+}
+#endif //FS
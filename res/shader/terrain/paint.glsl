@@ -36,13 +36,13 @@ void main() {
 
 
 #ifdef SHADER_FS
-//imported: Surface, u_TextureScale, get_surface, evaluate_color, apply_fog, fetch_shadow
+//imported: Surface, u_TextureScale, get_surface, evaluate_color, apply_fog, apply_exp_fog, fetch_shadow
 
 layout(location = 0) out vec4 o_Color;
 
 void main() {
-    float lit_factor = fetch_shadow(v_Pos);
+    float lit_factor = fetch_shadow(v_Pos) + extra_lighting(v_Pos, vec3(0.0, 0.0, 1.0));
     vec4 terrain_color = evaluate_color(v_Type, v_TexCoord.xy, v_TexCoord.z, lit_factor);
-    o_Color = apply_fog(terrain_color, v_Pos.xy);
+    o_Color = apply_exp_fog(apply_fog(terrain_color, v_Pos.xy), v_Pos);
 }
 #endif //FS
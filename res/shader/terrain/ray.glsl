@@ -23,10 +23,14 @@ const float c_DepthBias = COLOR != 0 ? 0.0 : 0.01;
 #if COLOR
 const float
     c_ReflectionVariance = 0.5,
-    c_ReflectionPower = 0.2;
+    c_ReflectionPower = 0.2,
+    c_FresnelBase = 0.02;
 
 #define TERRAIN_WATER   0U
 
+// objects mirrored across the water plane, transparent where there are none
+layout(set = 0, binding = 5) uniform texture2D t_Reflection;
+
 layout(location = 0) out vec4 o_Color;
 #endif //COLOR
 
@@ -93,7 +97,9 @@ CastPoint cast_ray_to_map(vec3 base, vec3 dir) {
     vec3 c = cast_ray_to_plane(0.0, base, dir);
     vec3 b = c;
 
-    Surface suf = cast_ray_impl(a, b, true, 8, 4);
+    // forward steps of the main cast, the others are scaled from it
+    int num_steps = int(u_Params.x);
+    Surface suf = cast_ray_impl(a, b, true, num_steps, 4);
     result.type = suf.high_type;
     result.is_underground = false;
 
@@ -101,7 +107,7 @@ CastPoint cast_ray_to_map(vec3 base, vec3 dir) {
         // continue the cast underground, but reserve
         // the right to re-appear above the surface.
         a = b; b = c;
-        suf = cast_ray_impl(a, b, false, 6, 3);
+        suf = cast_ray_impl(a, b, false, num_steps * 3 / 4, 3);
         if (b.z >= suf.low_alt + suf.delta) {
             result.type = suf.high_type;
         } else {
@@ -131,7 +137,7 @@ void main() {
     CastPoint pt = cast_ray_to_map(sp_near_world, view);
 
     #if COLOR
-    float lit_factor = fetch_shadow(pt.pos);
+    float lit_factor = fetch_shadow(pt.pos) + extra_lighting(pt.pos, vec3(0.0, 0.0, 1.0));
     vec4 frag_color = color_point(pt, lit_factor);
 
     if (pt.type == TERRAIN_WATER) {
@@ -141,7 +147,7 @@ void main() {
         vec3 outside = cast_ray_to_plane(u_TextureScale.z, a, reflected);
         vec3 b = outside;
 
-        Surface suf = cast_ray_impl(a, b, true, 4, 4);
+        Surface suf = cast_ray_impl(a, b, true, int(u_Params.x) / 2, 4);
         if (b != outside) {
             CastPoint other;
             other.pos = b;
@@ -150,8 +156,13 @@ void main() {
             vec4 ref_color = color_point(other, 0.8);
             frag_color += c_ReflectionPower * ref_color;
         }
+
+        vec2 screen_tc = gl_FragCoord.xy / vec2(u_ScreenSize.xy);
+        vec4 planar = textureLod(sampler2D(t_Reflection, s_PaletteSampler), screen_tc, 0.0);
+        float fresnel = c_FresnelBase + (1.0 - c_FresnelBase) * pow(1.0 - abs(view.z), 5.0);
+        frag_color = mix(frag_color, planar, planar.a * fresnel);
     }
-    o_Color = frag_color;
+    o_Color = apply_exp_fog(frag_color, pt.pos);
     #endif //COLOR
 
     vec4 target_ndc = u_ViewProj * vec4(pt.pos, 1.0);
@@ -117,10 +117,11 @@ void main() {
 
     #if COLOR
     if (u_Params.w == 0U) {
-        float lit_factor = fetch_shadow(point);
+        float lit_factor = fetch_shadow(point) + extra_lighting(point, vec3(0.0, 0.0, 1.0));
         Surface surface = get_surface(point.xy);
         uint type = point.z <= surface.low_alt ? surface.low_type : surface.high_type;
-        o_Color = evaluate_color(type, surface.tex_coord, point.z / u_TextureScale.z, lit_factor);
+        vec4 terrain_color = evaluate_color(type, surface.tex_coord, point.z / u_TextureScale.z, lit_factor);
+        o_Color = apply_exp_fog(terrain_color, point);
     }
     #endif //COLOR
 
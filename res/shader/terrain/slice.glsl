@@ -1,4 +1,4 @@
-//!include vs:globals.inc vs:surface.inc fs:surface.inc fs:color.inc
+//!include vs:globals.inc vs:surface.inc fs:globals.inc fs:surface.inc fs:color.inc
 
 layout(location = 0) varying vec4 v_Pos;
 
@@ -14,7 +14,7 @@ void main() {
 
 
 #ifdef SHADER_FS
-//imported: Surface, u_TextureScale, get_surface, evaluate_color
+//imported: Surface, u_TextureScale, get_surface, evaluate_color, apply_exp_fog
 
 layout(location = 0) out vec4 o_Color;
 
@@ -30,6 +30,7 @@ void main() {
     };
 
     float lit_factor = v_Pos.z <= surface.low_alt && surface.delta != 0.0 ? 0.25 : 1.0;
-    o_Color = evaluate_color(type, surface.tex_coord, v_Pos.z / u_TextureScale.z, lit_factor);
+    vec4 terrain_color = evaluate_color(type, surface.tex_coord, v_Pos.z / u_TextureScale.z, lit_factor);
+    o_Color = apply_exp_fog(terrain_color, v_Pos.xyz);
 }
 #endif //FS
@@ -0,0 +1,51 @@
+//!include vs:globals.inc vs:terrain/locals.inc vs:surface.inc fs:globals.inc fs:terrain/locals.inc fs:surface.inc fs:color.inc
+
+layout(location = 0) varying vec3 v_Pos;
+layout(location = 1) varying float v_Flood;
+
+#ifdef SHADER_VS
+layout(set = 1, binding = 4) uniform texture1D t_Flood;
+layout(set = 1, binding = 8) uniform sampler s_FloodSampler;
+
+const vec2 c_Corners[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+    vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)
+);
+
+// One quad per flood section, spanning the sampled X range.
+void main() {
+    int sections = textureSize(sampler1D(t_Flood, s_FloodSampler), 0);
+    float section_size = u_TextureScale.y / float(sections);
+    int section = int(floor(u_SampleRange.z / section_size)) + gl_InstanceIndex;
+    float flood = texelFetch(sampler1D(t_Flood, s_FloodSampler), modulo(section, sections), 0).x;
+
+    vec2 corner = c_Corners[gl_VertexIndex];
+    vec2 pos = vec2(
+        mix(u_SampleRange.x, u_SampleRange.y, corner.x),
+        (float(section) + corner.y) * section_size
+    );
+    v_Pos = vec3(pos, flood * u_TextureScale.z);
+    v_Flood = flood;
+    // dry sections collapse into nothing
+    gl_Position = flood > 0.0 ? u_ViewProj * vec4(v_Pos, 1.0) : vec4(0.0);
+}
+#endif //VS
+
+
+#ifdef SHADER_FS
+//imported: u_TextureScale, evaluate_light, evaluate_palette, extra_lighting, apply_fog, apply_exp_fog
+
+const vec3 c_WaterMaterial = vec3(5.0, 1.25, 0.5);
+const float c_WaterAlpha = 0.6;
+
+layout(location = 0) out vec4 o_Color;
+
+void main() {
+    // the surface is flat, so it's lit like the water tiles with no height difference
+    float lit_factor = 1.0 + extra_lighting(v_Pos, vec3(0.0, 0.0, 1.0));
+    float light = evaluate_light(c_WaterMaterial, 0.0) - c_HorFactor * (1.0 - v_Flood);
+    float color_id = evaluate_palette(0U, lit_factor * light, v_Pos.y / u_TextureScale.y);
+    vec4 color = texture(sampler1D(t_Palette, s_PaletteSampler), color_id);
+    o_Color = vec4(apply_exp_fog(apply_fog(color, v_Pos.xy), v_Pos).xyz, c_WaterAlpha);
+}
+#endif //FS
@@ -209,3 +209,12 @@ pub fn load_registry(
 
     map
 }
+
+pub fn car_ids(registry: &HashMap<String, CarInfo>) -> impl Iterator<Item = (&str, &str)> {
+    let mut ids = registry
+        .iter()
+        .map(|(id, info)| (id.as_str(), info.physics.name.as_str()))
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.into_iter()
+}
@@ -5,6 +5,7 @@ pub mod escaves;
 pub mod game;
 pub mod settings;
 mod text;
+pub mod tuning;
 pub mod worlds;
 
 pub use self::settings::Settings;
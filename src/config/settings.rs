@@ -1,7 +1,11 @@
 use crate::render::object::BodyColor;
 
 use std::fs::File;
+use std::io::Error as IoError;
 use std::path::PathBuf;
+use std::{error::Error, fmt};
+
+const TEMPLATE: &str = include_str!("../../config/settings.template.ron");
 
 #[derive(Deserialize)]
 pub struct Car {
@@ -47,6 +51,7 @@ pub struct GpuCollision {
 #[derive(Deserialize)]
 pub struct Physics {
     pub max_quant: f32,
+    pub fixed_dt: Option<f32>,
     pub shape_sampling: u8,
     pub gpu_collision: Option<GpuCollision>,
 }
@@ -89,15 +94,52 @@ impl Backend {
     }
 }
 
+#[derive(Deserialize)]
+pub enum PowerPreference {
+    Default,
+    LowPower,
+    HighPerformance,
+}
+
+impl PowerPreference {
+    pub fn to_wgpu(&self) -> wgpu::PowerPreference {
+        match *self {
+            PowerPreference::Default => wgpu::PowerPreference::Default,
+            PowerPreference::LowPower => wgpu::PowerPreference::LowPower,
+            PowerPreference::HighPerformance => wgpu::PowerPreference::HighPerformance,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+impl PresentMode {
+    pub fn to_wgpu(&self) -> wgpu::PresentMode {
+        match *self {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct DebugRender {
     pub max_vertices: usize,
     pub collision_shapes: bool,
     pub collision_map: bool,
     pub impulses: bool,
+    pub velocities: bool,
+    // spacing of the ground grid in the model viewer, 0 disables it
+    pub grid_spacing: f32,
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, PartialEq)]
 pub enum ShadowTerrain {
     RayTraced,
 }
@@ -115,9 +157,13 @@ pub struct Light {
     pub shadow: Shadow,
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, PartialEq)]
 pub enum Terrain {
+    // the default ray-march step count, kept for the existing configs
     RayTraced,
+    RayTracedSteps {
+        max_steps: u32,
+    },
     RayMipTraced {
         mip_count: u32,
         max_jumps: u32,
@@ -129,6 +175,7 @@ pub enum Terrain {
     },
     Sliced,
     Painted,
+    Meshed,
     Scattered {
         density: [u32; 3],
     },
@@ -138,12 +185,30 @@ pub enum Terrain {
 pub struct Fog {
     pub color: [f32; 4],
     pub depth: f32,
+    pub density: f32,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub enum Background {
+    // the fog color
+    Fog,
+    Gradient { zenith: [f32; 4], horizon: [f32; 4] },
 }
 
 #[derive(Deserialize)]
 pub struct Render {
+    pub present_mode: PresentMode,
+    pub srgb: bool,
+    pub msaa_samples: u32,
+    pub frustum_culling: bool,
+    pub depth_prepass: bool,
+    pub water: bool,
+    pub reflection_size: u32,
+    pub background: Background,
     pub light: Light,
     pub terrain: Terrain,
+    // vertical exaggeration of the rendered terrain, physics is unaffected
+    pub terrain_height_scale: f32,
     pub fog: Fog,
     pub debug: DebugRender,
 }
@@ -155,34 +220,123 @@ pub struct Settings {
     pub game: Game,
     pub window: Window,
     pub backend: Backend,
+    pub power_preference: PowerPreference,
     pub render: Render,
 }
 
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(IoError),
+    Parse(ron::de::Error),
+    MissingPath { field: &'static str, path: PathBuf },
+    UnknownLevel(String),
+    InvalidFile { path: PathBuf, reason: String },
+    InvalidValue { field: &'static str, reason: String },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SettingsError::Io(ref e) => write!(f, "Unable to read the settings file: {}", e),
+            SettingsError::Parse(ref e) => write!(
+                f,
+                "Unable to parse settings RON: {}. {}",
+                e,
+                "Please check if `config/settings.template.ron` has changed and your local config needs to be adjusted."
+            ),
+            SettingsError::MissingPath {
+                field,
+                ref path,
+            } => write!(f, "Path {:?} referenced by `{}` does not exist", path, field),
+            SettingsError::UnknownLevel(ref name) => {
+                write!(f, "Unknown level '{}' in `game.level`", name)
+            }
+            SettingsError::InvalidFile {
+                ref path,
+                ref reason,
+            } => write!(f, "Unable to load {:?}: {}", path, reason),
+            SettingsError::InvalidValue { field, ref reason } => {
+                write!(f, "Invalid value of `{}`: {}", field, reason)
+            }
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+impl From<IoError> for SettingsError {
+    fn from(e: IoError) -> Self {
+        SettingsError::Io(e)
+    }
+}
+
 impl Settings {
-    pub fn load(path: &str) -> Self {
+    pub fn load(path: &str) -> Result<Self, SettingsError> {
         use std::io::Read;
 
         let mut string = String::new();
-        File::open(path)
-            .expect("Unable to open the settings file")
-            .read_to_string(&mut string)
-            .unwrap();
-        let set: Settings = match ron::de::from_str(&string) {
-            Ok(set) => set,
-            Err(e) => panic!("Unable to parse settings RON.\n\t{}\n\tError: {:?}",
-                "Please check if `config/settings.template.ron` has changed and your local config needs to be adjusted.",
-                e,
-            ),
-        };
+        File::open(path)?.read_to_string(&mut string)?;
+        let set: Settings = ron::de::from_str(&string).map_err(SettingsError::Parse)?;
+        set.validate()?;
+        Ok(set)
+    }
+
+    pub fn write_template(path: &str) -> Result<(), IoError> {
+        std::fs::write(path, TEMPLATE)
+    }
+
+    fn require(&self, field: &'static str, path: PathBuf) -> Result<PathBuf, SettingsError> {
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(SettingsError::MissingPath { field, path })
+        }
+    }
+
+    fn validate(&self) -> Result<(), SettingsError> {
+        if let Terrain::RayTracedSteps { max_steps } = self.render.terrain {
+            // the secondary casts take a half and three quarters of the steps
+            if max_steps < 2 {
+                return Err(SettingsError::InvalidValue {
+                    field: "render.terrain",
+                    reason: format!("max_steps is {}, has to be at least 2", max_steps),
+                });
+            }
+        }
+        self.require("data_path", self.data_path.join("options.dat"))?;
+        self.require(
+            "data_path",
+            self.data_path
+                .join("resource")
+                .join("pal")
+                .join("objects.pal"),
+        )?;
 
-        if !set.check_path("options.dat") {
-            panic!(
-                "Can't find the resources of the original Vangers game at {:?}, {}",
-                set.data_path, "please check your `config/settings.ron`"
-            );
+        if !self.game.level.is_empty() {
+            let worlds_path = self.require("data_path", self.data_path.join("wrlds.dat"))?;
+            let worlds = super::worlds::try_load(File::open(&worlds_path)?).map_err(|reason| {
+                SettingsError::InvalidFile {
+                    path: worlds_path,
+                    reason,
+                }
+            })?;
+            let ini_name = worlds
+                .get(&self.game.level)
+                .ok_or_else(|| SettingsError::UnknownLevel(self.game.level.clone()))?;
+            let ini_path = self.require("game.level", self.data_path.join(ini_name))?;
+            let config = crate::level::LevelConfig::try_load(&ini_path).map_err(|reason| {
+                SettingsError::InvalidFile {
+                    path: ini_path,
+                    reason,
+                }
+            })?;
+            let extension = if config.is_compressed { "vmc" } else { "vmp" };
+            self.require("game.level", config.path_data.with_extension(extension))?;
+            // the VPR is optional, `level::load_flood` falls back to an empty flood map
+            self.require("game.level", config.path_palette)?;
         }
 
-        set
+        Ok(())
     }
 
     pub fn open_relative(&self, path: &str) -> File {
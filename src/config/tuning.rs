@@ -0,0 +1,389 @@
+use crate::config::{
+    car::CarPhysics,
+    common::{Common, VelocityPair},
+};
+
+use std::{
+    collections::BTreeMap, error::Error, fmt, fs::File, io::Error as IoError, path::Path,
+    str::FromStr,
+};
+
+// Bumped on breaking changes only. New fields are optional, so that
+// the profiles saved before they were added still load.
+pub const TUNING_VERSION: u32 = 1;
+
+// A tuning profile holds the physics constants that feed `GpuStore`.
+// Every value is an override: the missing ones keep the game data intact.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tuning {
+    pub version: u32,
+    #[serde(default)]
+    pub common: CommonTuning,
+    // keyed by `CarPhysics::name`
+    #[serde(default)]
+    pub cars: BTreeMap<String, CarTuning>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommonTuning {
+    pub nature: NatureTuning,
+    pub global: GlobalTuning,
+    pub car: CarControlTuning,
+    pub impulse: ImpulseTuning,
+    pub drag: DragTuning,
+    pub contact: ContactTuning,
+    pub force: ForceTuning,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NatureTuning {
+    pub gravity: Option<f32>,
+    pub density: Option<f32>,
+    pub time_delta0: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlobalTuning {
+    pub speed_factor: Option<f32>,
+    pub mobility_factor: Option<f32>,
+    pub water_speed_factor: Option<f32>,
+    pub air_speed_factor: Option<f32>,
+    pub underground_speed_factor: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarControlTuning {
+    pub rudder_step: Option<f32>,
+    pub rudder_max: Option<f32>,
+    pub rudder_k_decr: Option<f32>,
+    pub traction_incr: Option<f32>,
+    pub traction_decr: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImpulseTuning {
+    pub elastic_restriction: Option<f32>,
+    pub elastic_time_scale_factor: Option<f32>,
+    pub rolling_scale: Option<f32>,
+    pub normal_threshold: Option<f32>,
+    pub k_wheel: Option<f32>,
+    pub factors: Option<[f32; 2]>,
+    pub k_friction: Option<f32>,
+}
+
+// Velocity pairs are stored as [linear, angular].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DragTuning {
+    pub free: Option<[f32; 2]>,
+    pub speed: Option<[f32; 2]>,
+    pub spring: Option<[f32; 2]>,
+    pub abs_min: Option<[f32; 2]>,
+    pub abs_stop: Option<[f32; 2]>,
+    pub coll: Option<[f32; 2]>,
+    pub wheel_speed: Option<f32>,
+    pub z: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactTuning {
+    pub k_elastic_wheel: Option<f32>,
+    pub k_elastic_spring: Option<f32>,
+    pub k_elastic_xy: Option<f32>,
+    pub k_elastic_db_coll: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForceTuning {
+    pub k_distance_to_force: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarTuning {
+    pub scale_size: Option<f32>,
+    pub scale_bound: Option<f32>,
+    pub scale_box: Option<f32>,
+    pub z_offset_of_mass_center: Option<f32>,
+    pub speed_factor: Option<f32>,
+    pub mobility_factor: Option<f32>,
+    pub water_speed_factor: Option<f32>,
+    pub air_speed_factor: Option<f32>,
+    pub underground_speed_factor: Option<f32>,
+    pub k_archimedean: Option<f32>,
+    pub k_water_traction: Option<f32>,
+    pub k_water_rudder: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum TuningError {
+    Io(IoError),
+    Parse(ron::de::Error),
+    Serialize(String),
+    Version { found: u32 },
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TuningError::Io(ref e) => write!(f, "Unable to access the tuning file: {}", e),
+            TuningError::Parse(ref e) => write!(f, "Unable to parse tuning RON: {}", e),
+            TuningError::Serialize(ref e) => write!(f, "Unable to serialize the tuning: {}", e),
+            TuningError::Version { found } => write!(
+                f,
+                "Tuning version {} is newer than the supported {}",
+                found, TUNING_VERSION
+            ),
+        }
+    }
+}
+
+impl Error for TuningError {}
+
+impl From<IoError> for TuningError {
+    fn from(e: IoError) -> Self {
+        TuningError::Io(e)
+    }
+}
+
+fn set<T>(target: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *target = value;
+    }
+}
+
+fn set_pair(target: &mut VelocityPair, value: Option<[f32; 2]>) {
+    if let Some([v, w]) = value {
+        target.v = v;
+        target.w = w;
+    }
+}
+
+impl CommonTuning {
+    pub fn capture(common: &Common) -> Self {
+        CommonTuning {
+            nature: NatureTuning {
+                gravity: Some(common.nature.gravity),
+                density: Some(common.nature.density),
+                time_delta0: Some(common.nature.time_delta0),
+            },
+            global: GlobalTuning {
+                speed_factor: Some(common.global.speed_factor),
+                mobility_factor: Some(common.global.mobility_factor),
+                water_speed_factor: Some(common.global.water_speed_factor),
+                air_speed_factor: Some(common.global.air_speed_factor),
+                underground_speed_factor: Some(common.global.underground_speed_factor),
+            },
+            car: CarControlTuning {
+                rudder_step: Some(common.car.rudder_step),
+                rudder_max: Some(common.car.rudder_max),
+                rudder_k_decr: Some(common.car.rudder_k_decr),
+                traction_incr: Some(common.car.traction_incr),
+                traction_decr: Some(common.car.traction_decr),
+            },
+            impulse: ImpulseTuning {
+                elastic_restriction: Some(common.impulse.elastic_restriction),
+                elastic_time_scale_factor: Some(common.impulse.elastic_time_scale_factor),
+                rolling_scale: Some(common.impulse.rolling_scale),
+                normal_threshold: Some(common.impulse.normal_threshold),
+                k_wheel: Some(common.impulse.k_wheel),
+                factors: Some(common.impulse.factors),
+                k_friction: Some(common.impulse.k_friction),
+            },
+            drag: DragTuning {
+                free: Some(common.drag.free.to_array()),
+                speed: Some(common.drag.speed.to_array()),
+                spring: Some(common.drag.spring.to_array()),
+                abs_min: Some(common.drag.abs_min.to_array()),
+                abs_stop: Some(common.drag.abs_stop.to_array()),
+                coll: Some(common.drag.coll.to_array()),
+                wheel_speed: Some(common.drag.wheel_speed),
+                z: Some(common.drag.z),
+            },
+            contact: ContactTuning {
+                k_elastic_wheel: Some(common.contact.k_elastic_wheel),
+                k_elastic_spring: Some(common.contact.k_elastic_spring),
+                k_elastic_xy: Some(common.contact.k_elastic_xy),
+                k_elastic_db_coll: Some(common.contact.k_elastic_db_coll),
+            },
+            force: ForceTuning {
+                k_distance_to_force: Some(common.force.k_distance_to_force),
+            },
+        }
+    }
+
+    pub fn apply(&self, common: &mut Common) {
+        let nature = &mut common.nature;
+        set(&mut nature.gravity, self.nature.gravity);
+        set(&mut nature.density, self.nature.density);
+        set(&mut nature.time_delta0, self.nature.time_delta0);
+
+        let global = &mut common.global;
+        set(&mut global.speed_factor, self.global.speed_factor);
+        set(&mut global.mobility_factor, self.global.mobility_factor);
+        set(
+            &mut global.water_speed_factor,
+            self.global.water_speed_factor,
+        );
+        set(&mut global.air_speed_factor, self.global.air_speed_factor);
+        set(
+            &mut global.underground_speed_factor,
+            self.global.underground_speed_factor,
+        );
+
+        let car = &mut common.car;
+        set(&mut car.rudder_step, self.car.rudder_step);
+        set(&mut car.rudder_max, self.car.rudder_max);
+        set(&mut car.rudder_k_decr, self.car.rudder_k_decr);
+        set(&mut car.traction_incr, self.car.traction_incr);
+        set(&mut car.traction_decr, self.car.traction_decr);
+
+        let impulse = &mut common.impulse;
+        set(
+            &mut impulse.elastic_restriction,
+            self.impulse.elastic_restriction,
+        );
+        set(
+            &mut impulse.elastic_time_scale_factor,
+            self.impulse.elastic_time_scale_factor,
+        );
+        set(&mut impulse.rolling_scale, self.impulse.rolling_scale);
+        set(&mut impulse.normal_threshold, self.impulse.normal_threshold);
+        set(&mut impulse.k_wheel, self.impulse.k_wheel);
+        set(&mut impulse.factors, self.impulse.factors);
+        set(&mut impulse.k_friction, self.impulse.k_friction);
+
+        let drag = &mut common.drag;
+        set_pair(&mut drag.free, self.drag.free);
+        set_pair(&mut drag.speed, self.drag.speed);
+        set_pair(&mut drag.spring, self.drag.spring);
+        set_pair(&mut drag.abs_min, self.drag.abs_min);
+        set_pair(&mut drag.abs_stop, self.drag.abs_stop);
+        set_pair(&mut drag.coll, self.drag.coll);
+        set(&mut drag.wheel_speed, self.drag.wheel_speed);
+        set(&mut drag.z, self.drag.z);
+
+        let contact = &mut common.contact;
+        set(&mut contact.k_elastic_wheel, self.contact.k_elastic_wheel);
+        set(&mut contact.k_elastic_spring, self.contact.k_elastic_spring);
+        set(&mut contact.k_elastic_xy, self.contact.k_elastic_xy);
+        set(
+            &mut contact.k_elastic_db_coll,
+            self.contact.k_elastic_db_coll,
+        );
+
+        set(
+            &mut common.force.k_distance_to_force,
+            self.force.k_distance_to_force,
+        );
+    }
+}
+
+impl CarTuning {
+    pub fn capture(physics: &CarPhysics) -> Self {
+        CarTuning {
+            scale_size: Some(physics.scale_size),
+            scale_bound: Some(physics.scale_bound),
+            scale_box: Some(physics.scale_box),
+            z_offset_of_mass_center: Some(physics.z_offset_of_mass_center),
+            speed_factor: Some(physics.speed_factor),
+            mobility_factor: Some(physics.mobility_factor),
+            water_speed_factor: Some(physics.water_speed_factor),
+            air_speed_factor: Some(physics.air_speed_factor),
+            underground_speed_factor: Some(physics.underground_speed_factor),
+            k_archimedean: Some(physics.k_archimedean),
+            k_water_traction: Some(physics.k_water_traction),
+            k_water_rudder: Some(physics.k_water_rudder),
+        }
+    }
+
+    pub fn apply(&self, physics: &mut CarPhysics) {
+        set(&mut physics.scale_size, self.scale_size);
+        set(&mut physics.scale_bound, self.scale_bound);
+        set(&mut physics.scale_box, self.scale_box);
+        set(
+            &mut physics.z_offset_of_mass_center,
+            self.z_offset_of_mass_center,
+        );
+        set(&mut physics.speed_factor, self.speed_factor);
+        set(&mut physics.mobility_factor, self.mobility_factor);
+        set(&mut physics.water_speed_factor, self.water_speed_factor);
+        set(&mut physics.air_speed_factor, self.air_speed_factor);
+        set(
+            &mut physics.underground_speed_factor,
+            self.underground_speed_factor,
+        );
+        set(&mut physics.k_archimedean, self.k_archimedean);
+        set(&mut physics.k_water_traction, self.k_water_traction);
+        set(&mut physics.k_water_rudder, self.k_water_rudder);
+    }
+}
+
+impl Tuning {
+    pub fn new(common: &Common) -> Self {
+        Tuning {
+            version: TUNING_VERSION,
+            common: CommonTuning::capture(common),
+            cars: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_car(&mut self, physics: &CarPhysics) {
+        self.cars
+            .insert(physics.name.clone(), CarTuning::capture(physics));
+    }
+
+    pub fn apply(&self, common: &mut Common) {
+        self.common.apply(common);
+    }
+
+    // Returns true if the profile had an entry for this car.
+    pub fn apply_car(&self, physics: &mut CarPhysics) -> bool {
+        match self.cars.get(&physics.name) {
+            Some(car) => {
+                car.apply(physics);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn to_ron(&self) -> Result<String, TuningError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| TuningError::Serialize(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, TuningError> {
+        use std::io::Read;
+
+        let mut string = String::new();
+        File::open(path)?.read_to_string(&mut string)?;
+        string.parse()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), TuningError> {
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+}
+
+impl FromStr for Tuning {
+    type Err = TuningError;
+
+    fn from_str(string: &str) -> Result<Self, TuningError> {
+        let tuning: Tuning = ron::de::from_str(string).map_err(TuningError::Parse)?;
+        if tuning.version > TUNING_VERSION {
+            return Err(TuningError::Version {
+                found: tuning.version,
+            });
+        }
+        Ok(tuning)
+    }
+}
@@ -1,17 +1,48 @@
-use crate::config::text::Reader;
+use crate::config::{text::Reader, Settings};
 
 use std::collections::HashMap;
 use std::fs::File;
+use std::path::PathBuf;
 
 pub type Worlds = HashMap<String, String>;
 
 pub fn load(file: File) -> Worlds {
+    try_load(file).unwrap()
+}
+
+// Same as `load`, but reports a malformed list instead of panicking.
+pub fn try_load(file: File) -> Result<Worlds, String> {
     let mut fi = Reader::new(file);
-    let count = fi.next_value::<usize>();
+    fi.advance();
+    let count = fi
+        .cur()
+        .parse::<usize>()
+        .map_err(|e| format!("Invalid world count {:?}: {}", fi.cur(), e))?;
     (0..count)
         .map(|_| {
-            fi.advance();
-            fi.scan()
+            if !fi.advance() {
+                return Err("Unexpected end of the world list".to_string());
+            }
+            serde_scan::from_str(fi.cur())
+                .map_err(|e| format!("Unable to scan line {:?}: {:?}", fi.cur(), e))
         })
         .collect()
 }
+
+pub fn list_levels(settings: &Settings) -> Vec<(String, PathBuf)> {
+    let path = settings.data_path.join("wrlds.dat");
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Unable to open {:?}: {}", path, e);
+            return Vec::new();
+        }
+    };
+    let mut levels = load(file)
+        .into_iter()
+        .map(|(name, ini)| (name, settings.data_path.join(ini)))
+        .filter(|&(_, ref ini_path)| ini_path.is_file())
+        .collect::<Vec<_>>();
+    levels.sort();
+    levels
+}
@@ -5,6 +5,13 @@ type Epoch = u16;
 
 pub struct Id<T>(Index, Epoch, PhantomData<T>);
 
+impl<T> Clone for Id<T> {
+    fn clone(&self) -> Self {
+        Id(self.0, self.1, PhantomData)
+    }
+}
+impl<T> Copy for Id<T> {}
+
 impl<T> Id<T> {
     pub const ZERO: Self = Id(0, 0, PhantomData);
 
@@ -13,9 +20,11 @@ impl<T> Id<T> {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FreeList<T> {
     epochs: Vec<Epoch>,
     free: Vec<Index>,
+    #[serde(skip)]
     marker: PhantomData<T>,
 }
 
@@ -40,6 +49,14 @@ impl<T> FreeList<T> {
         }
     }
 
+    pub fn alloc_within(&mut self, limit: usize) -> Option<Id<T>> {
+        if self.free.is_empty() && self.epochs.len() >= limit {
+            None
+        } else {
+            Some(self.alloc())
+        }
+    }
+
     pub fn free(&mut self, id: Id<T>) {
         assert_eq!(self.epochs[id.0 as usize], id.1);
         self.epochs[id.0 as usize] += 1;
@@ -49,4 +66,8 @@ impl<T> FreeList<T> {
     pub fn length(&self) -> usize {
         self.epochs.len()
     }
+
+    pub fn live_count(&self) -> usize {
+        self.epochs.len() - self.free.len()
+    }
 }
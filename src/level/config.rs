@@ -1,6 +1,7 @@
 use ini::Ini;
 use std::ops::Range;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 pub struct Power(pub i32);
 impl Power {
@@ -33,17 +34,36 @@ pub struct LevelConfig {
 
 impl LevelConfig {
     pub fn load(ini_path: &PathBuf) -> Self {
-        let ini = Ini::load_from_file(ini_path).expect(&format!(
-            "Unable to read the level's INI description: {:?}",
-            ini_path
-        ));
-        let global = &ini["Global Parameters"];
-        let storage = &ini["Storage"];
-        let render = &ini["Rendering Parameters"];
+        Self::try_load(ini_path).unwrap()
+    }
+
+    // Same as `load`, but reports a malformed description instead of panicking.
+    pub fn try_load(ini_path: &PathBuf) -> Result<Self, String> {
+        let ini = Ini::load_from_file(ini_path).map_err(|e| {
+            format!(
+                "Unable to read the level's INI description {:?}: {}",
+                ini_path, e
+            )
+        })?;
+        let global = "Global Parameters";
+        let storage = "Storage";
+        let render = "Rendering Parameters";
+        let get = |section: &str, key: &str| {
+            ini.get_from(Some(section), key)
+                .ok_or_else(|| format!("Missing '{}' in [{}]", key, section))
+        };
+        fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value {:?} of '{}'", value, key))
+        }
+        let power =
+            |key: &str| -> Result<Power, String> { Ok(Power(parse(key, get(global, key)?)?)) };
 
-        let terra_count = render
-            .get("Terrain Max")
-            .map_or(8, |value| value.parse::<usize>().unwrap());
+        let terra_count = match ini.get_from(Some(render), "Terrain Max") {
+            Some(value) => parse::<usize>("Terrain Max", value)?,
+            None => 8,
+        };
         let mut terrains = (0..terra_count)
             .map(|_| TerrainConfig {
                 shadow_offset: 0,
@@ -54,43 +74,40 @@ impl LevelConfig {
 
         for (t, val) in terrains
             .iter_mut()
-            .zip(render["Shadow Offsets"].split_whitespace())
+            .zip(get(render, "Shadow Offsets")?.split_whitespace())
         {
-            t.shadow_offset = val.parse().unwrap();
+            t.shadow_offset = parse("Shadow Offsets", val)?;
         }
         for (t, val) in terrains
             .iter_mut()
-            .zip(render["Height Shifts"].split_whitespace())
+            .zip(get(render, "Height Shifts")?.split_whitespace())
         {
-            t.height_shift = val.parse().unwrap();
+            t.height_shift = parse("Height Shifts", val)?;
         }
         for (t, val) in terrains
             .iter_mut()
-            .zip(render["Begin Colors"].split_whitespace())
+            .zip(get(render, "Begin Colors")?.split_whitespace())
         {
-            t.colors.start = val.parse().unwrap();
+            t.colors.start = parse("Begin Colors", val)?;
         }
         for (t, val) in terrains
             .iter_mut()
-            .zip(render["End Colors"].split_whitespace())
+            .zip(get(render, "End Colors")?.split_whitespace())
         {
-            t.colors.end = val.parse().unwrap();
+            t.colors.end = parse("End Colors", val)?;
         }
 
-        let path_data = ini_path.with_file_name(&storage["File Name"]);
-        LevelConfig {
+        let path_data = ini_path.with_file_name(get(storage, "File Name")?);
+        Ok(LevelConfig {
             path_data,
-            path_palette: ini_path.with_file_name(&storage["Palette File"]),
-            is_compressed: &storage["Compressed Format Using"] != "0",
+            path_palette: ini_path.with_file_name(get(storage, "Palette File")?),
+            is_compressed: get(storage, "Compressed Format Using")? != "0",
             //name: self.game.level.clone(),
-            size: (
-                Power(global["Map Power X"].parse().unwrap()),
-                Power(global["Map Power Y"].parse().unwrap()),
-            ),
-            geo: Power(global["GeoNet Power"].parse().unwrap()),
-            section: Power(global["Section Size Power"].parse().unwrap()),
-            min_square: Power(global["Minimal Square Power"].parse().unwrap()),
+            size: (power("Map Power X")?, power("Map Power Y")?),
+            geo: power("GeoNet Power")?,
+            section: power("Section Size Power")?,
+            min_square: power("Minimal Square Power")?,
             terrains,
-        }
+        })
     }
 }
@@ -0,0 +1,94 @@
+use super::Level;
+use crate::render::terrain::Rect;
+
+use byteorder::{LittleEndian as E, WriteBytesExt};
+
+use std::{
+    fs::File,
+    io::{Error as IoError, Write},
+    path::PathBuf,
+};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_INT: u32 = 5125;
+
+impl Level {
+    // Writes the JSON to `out` and the binary data next to it, with the "bin"
+    // extension. The level Z-up space is rotated into the Y-up glTF space.
+    pub fn export_gltf(&self, region: Option<Rect>, out: &PathBuf) -> Result<(), IoError> {
+        let mesh = self.triangulate(region);
+        let bin_path = out.with_extension("bin");
+
+        let mut data = Vec::new();
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in mesh.positions.iter() {
+            let v = [p[0], p[2], -p[1]];
+            for (i, &value) in v.iter().enumerate() {
+                min[i] = min[i].min(value);
+                max[i] = max[i].max(value);
+                data.write_f32::<E>(value)?;
+            }
+        }
+        let colors_offset = data.len();
+        for c in mesh.colors.iter() {
+            data.extend_from_slice(c);
+        }
+        let indices_offset = data.len();
+        for &i in mesh.indices.iter() {
+            data.write_u32::<E>(i)?;
+        }
+
+        let json = format!(
+            r#"{{
+  "asset": {{ "version": "2.0", "generator": "vangers" }},
+  "scene": 0,
+  "scenes": [{{ "nodes": [0] }}],
+  "nodes": [{{ "mesh": 0, "name": "terrain" }}],
+  "meshes": [{{ "primitives": [{{
+    "attributes": {{ "POSITION": 0, "COLOR_0": 1 }},
+    "indices": 2
+  }}] }}],
+  "buffers": [{{ "uri": "{uri}", "byteLength": {total} }}],
+  "bufferViews": [
+    {{ "buffer": 0, "byteOffset": 0, "byteLength": {colors_offset}, "target": {array} }},
+    {{ "buffer": 0, "byteOffset": {colors_offset}, "byteLength": {colors_size}, "target": {array} }},
+    {{ "buffer": 0, "byteOffset": {indices_offset}, "byteLength": {indices_size}, "target": {element} }}
+  ],
+  "accessors": [
+    {{ "bufferView": 0, "componentType": {float}, "count": {num_vertices}, "type": "VEC3",
+      "min": [{min0}, {min1}, {min2}], "max": [{max0}, {max1}, {max2}] }},
+    {{ "bufferView": 1, "componentType": {ubyte}, "normalized": true, "count": {num_vertices}, "type": "VEC4" }},
+    {{ "bufferView": 2, "componentType": {uint}, "count": {num_indices}, "type": "SCALAR" }}
+  ]
+}}
+"#,
+            uri = bin_path.file_name().unwrap().to_string_lossy(),
+            total = data.len(),
+            colors_offset = colors_offset,
+            colors_size = indices_offset - colors_offset,
+            indices_offset = indices_offset,
+            indices_size = data.len() - indices_offset,
+            array = ARRAY_BUFFER,
+            element = ELEMENT_ARRAY_BUFFER,
+            float = FLOAT,
+            ubyte = UNSIGNED_BYTE,
+            uint = UNSIGNED_INT,
+            num_vertices = mesh.positions.len(),
+            num_indices = mesh.indices.len(),
+            min0 = min[0],
+            min1 = min[1],
+            min2 = min[2],
+            max0 = max[0],
+            max1 = max[1],
+            max2 = max[2],
+        );
+
+        File::create(&bin_path)?.write_all(&data)?;
+        File::create(out)?.write_all(json.as_bytes())?;
+        Ok(())
+    }
+}
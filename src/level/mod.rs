@@ -1,29 +1,50 @@
+use crate::render::terrain::Rect;
+
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
 
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 use std::time::Instant;
 
 mod config;
+#[cfg(feature = "gltf")]
+mod gltf;
 
-pub use self::config::{LevelConfig, TerrainConfig};
+pub use self::config::{LevelConfig, Power, TerrainConfig};
 
 pub type TerrainType = u8;
 
+#[cfg(not(feature = "altitude16"))]
 pub type Altitude = u8;
-pub type Delta = Altitude;
+#[cfg(feature = "altitude16")]
+pub type Altitude = u16;
+// Bits of precision below the original 8-bit altitude step.
+pub const ALTITUDE_FRACTION_BITS: u32 = mem::size_of::<Altitude>() as u32 * 8 - 8;
+pub type Delta = u8;
 pub const DOUBLE_LEVEL: u8 = 1 << 6;
 pub const DELTA_SHIFT0: u8 = 2 + 3;
 pub const DELTA_SHIFT1: u8 = 0 + 3;
 pub const DELTA_MASK: u8 = 0x3;
 pub const HEIGHT_SCALE: u32 = 128;
 
+pub fn altitude_to_height(alt: Altitude) -> f32 {
+    alt as f32 * HEIGHT_SCALE as f32 / (255u32 << ALTITUDE_FRACTION_BITS) as f32
+}
+
 pub struct Level {
     pub size: (i32, i32),
     pub flood_map: Vec<u8>,
     pub flood_section_power: usize,
+    // power of the geo net step, as found in the level config
+    pub geo_power: usize,
     pub height: Vec<u8>,
+    // low bytes of the 16-bit altitudes, `height` holds the high ones
+    #[cfg(feature = "altitude16")]
+    pub height_fine: Vec<u8>,
     pub meta: Vec<u8>,
     pub palette: [[u8; 4]; 0x100],
     pub terrains: Box<[TerrainConfig]>,
@@ -31,6 +52,13 @@ pub struct Level {
 
 pub struct Point(pub Altitude, pub TerrainType);
 
+// Triangulated surface of a level region, one vertex per tile corner.
+pub struct TerrainMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub colors: Vec<[u8; 4]>,
+    pub indices: Vec<u32>,
+}
+
 pub struct TerrainBits {
     pub shift: u8,
     pub mask: TerrainType,
@@ -69,6 +97,11 @@ pub enum Texel {
     },
 }
 
+pub struct TexelChange {
+    pub before: Texel,
+    pub after: Texel,
+}
+
 impl Texel {
     pub fn top(&self) -> Altitude {
         match *self {
@@ -89,13 +122,26 @@ impl Level {
             size: (2, 1),
             flood_map: vec![0],
             flood_section_power: 0,
+            geo_power: 0,
             height: vec![0, 0],
+            #[cfg(feature = "altitude16")]
+            height_fine: vec![0, 0],
             meta: vec![0, 0],
             palette: [[0xFF; 4]; 0x100],
             terrains: (0..8).map(|_| tc.clone()).collect(),
         }
     }
 
+    #[cfg(not(feature = "altitude16"))]
+    pub fn altitude(&self, index: usize) -> Altitude {
+        self.height[index]
+    }
+
+    #[cfg(feature = "altitude16")]
+    pub fn altitude(&self, index: usize) -> Altitude {
+        (self.height[index] as Altitude) << 8 | self.height_fine[index] as Altitude
+    }
+
     pub fn get(&self, mut coord: (i32, i32)) -> Texel {
         let bits = TerrainBits::new(self.terrains.len() as u8);
         while coord.0 < 0 {
@@ -105,6 +151,10 @@ impl Level {
             coord.1 += self.size.1;
         }
         let i = ((coord.1 % self.size.1) * self.size.0 + (coord.0 % self.size.0)) as usize;
+        self.get_index(i, &bits)
+    }
+
+    fn get_index(&self, i: usize, bits: &TerrainBits) -> Texel {
         let meta = self.meta[i];
         if meta & DOUBLE_LEVEL != 0 {
             let meta0 = self.meta[i & !1];
@@ -112,16 +162,164 @@ impl Level {
             let d0 = (meta0 & DELTA_MASK) << DELTA_SHIFT0;
             let d1 = (meta1 & DELTA_MASK) << DELTA_SHIFT1;
             Texel::Dual {
-                low: Point(self.height[i & !1], bits.read(meta0)),
-                high: Point(self.height[i | 1], bits.read(meta1)),
+                low: Point(self.altitude(i & !1), bits.read(meta0)),
+                high: Point(self.altitude(i | 1), bits.read(meta1)),
                 delta: d0 + d1,
             }
         } else {
-            Texel::Single(Point(self.height[i], bits.read(meta)))
+            Texel::Single(Point(self.altitude(i), bits.read(meta)))
+        }
+    }
+
+    pub fn sample_height(&self, x: f32, y: f32) -> f32 {
+        self.sample_height_near(x, y, f32::INFINITY)
+    }
+
+    // Bilinear height, taking the surface of dual-level tiles that is closer to `reference`.
+    // Tile altitudes are placed at the tile centers, see `tile_to_world`.
+    pub fn sample_height_near(&self, x: f32, y: f32, reference: f32) -> f32 {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let height = |dx: i32, dy: i32| match self.get((x0 as i32 + dx, y0 as i32 + dy)) {
+            Texel::Single(Point(alt, _)) => altitude_to_height(alt),
+            Texel::Dual { low, high, .. } => {
+                let (low, high) = (altitude_to_height(low.0), altitude_to_height(high.0));
+                if (reference - low).abs() < (reference - high).abs() {
+                    low
+                } else {
+                    high
+                }
+            }
+        };
+        let top = height(0, 0) * (1.0 - fx) + height(1, 0) * fx;
+        let bottom = height(0, 1) * (1.0 - fx) + height(1, 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // Up-facing normal from the central differences of `sample_height`,
+    // one tile apart. The coordinates are wrapped by `get`.
+    pub fn sample_normal(&self, x: f32, y: f32) -> cgmath::Vector3<f32> {
+        use cgmath::InnerSpace as _;
+        let dx = 0.5 * (self.sample_height(x + 1.0, y) - self.sample_height(x - 1.0, y));
+        let dy = 0.5 * (self.sample_height(x, y + 1.0) - self.sample_height(x, y - 1.0));
+        cgmath::vec3(-dx, -dy, 1.0).normalize()
+    }
+
+    // Water surface altitude for the tile row `y`, wrapped around the level edges.
+    pub fn water_level(&self, y: i32) -> Altitude {
+        let row = y.rem_euclid(self.size.1) as usize;
+        let flood = self.flood_map[row >> self.flood_section_power];
+        (flood as Altitude) << ALTITUDE_FRACTION_BITS
+    }
+
+    pub fn is_underwater(&self, coord: (i32, i32), altitude: Altitude) -> bool {
+        altitude < self.water_level(coord.1)
+    }
+
+    // Water surface height for the row `y`, wrapped around the level edges.
+    pub fn flood_level_at(&self, y: f32) -> f32 {
+        altitude_to_height(self.water_level(y.floor() as i32))
+    }
+
+    // One tile per world unit, wrapped around the level edges.
+    pub fn world_to_tile(&self, pos: cgmath::Point3<f32>) -> (i32, i32) {
+        (
+            (pos.x.floor() as i32).rem_euclid(self.size.0),
+            (pos.y.floor() as i32).rem_euclid(self.size.1),
+        )
+    }
+
+    // Terrain type of the top surface at the tile under the position.
+    pub fn terrain_at(&self, x: f32, y: f32) -> TerrainType {
+        let coord = self.world_to_tile(cgmath::Point3::new(x, y, 0.0));
+        match self.get(coord) {
+            Texel::Single(Point(_, ty)) => ty,
+            Texel::Dual {
+                high: Point(_, ty), ..
+            } => ty,
+        }
+    }
+
+    // Unbounded version of `raycast`, returning the world-space hit point
+    // and the terrain type of the layer that got hit.
+    pub fn raycast(
+        &self,
+        origin: cgmath::Vector3<f32>,
+        dir: cgmath::Vector3<f32>,
+    ) -> Option<(cgmath::Vector3<f32>, TerrainType)> {
+        // the ground is solid below zero, and nothing is above the height scale,
+        // so the ray is done after crossing the height range
+        let max_dist = if dir.z < 0.0 {
+            origin.z.max(0.0) / -dir.z + 1.0
+        } else if dir.z > 0.0 {
+            (HEIGHT_SCALE as f32 - origin.z).max(0.0) / dir.z
+        } else if origin.z >= HEIGHT_SCALE as f32 || (dir.x == 0.0 && dir.y == 0.0) {
+            return None;
+        } else if dir.x.abs() >= dir.y.abs() {
+            // a horizontal ray repeats itself after one lap of the wrapped level
+            self.size.0 as f32 / dir.x.abs()
+        } else {
+            self.size.1 as f32 / dir.y.abs()
+        };
+
+        let start = cgmath::Point3::new(origin.x, origin.y, origin.z);
+        let (x, y, t) = raycast(self, start, dir, max_dist)?;
+        let point = origin + dir * t;
+        let ty = match self.get((x, y)) {
+            Texel::Single(Point(_, ty)) => ty,
+            Texel::Dual {
+                low: Point(low_alt, low_ty),
+                high: Point(_, high_ty),
+                delta,
+            } => {
+                // the high layer starts `delta` above the low one
+                let low = altitude_to_height(low_alt);
+                let gap = delta as f32 * HEIGHT_SCALE as f32 / 255.0;
+                if point.z > low + 0.5 * gap {
+                    high_ty
+                } else {
+                    low_ty
+                }
+            }
+        };
+        Some((point, ty))
+    }
+
+    // Writes the data back into the files that `load` reads.
+    // Only the flood map is updated in the VPR, see `save_flood`,
+    // and the VMC is not really compressed, see `LevelData::save_vmc`.
+    pub fn save(&self, config: &LevelConfig) -> io::Result<()> {
+        #[cfg(feature = "altitude16")]
+        {
+            if self.height_fine.iter().any(|&fine| fine != 0) {
+                warn!("Fine altitudes are not stored in the level data");
+            }
+        }
+        let data = LevelData {
+            height: self.height.clone(),
+            meta: self.meta.clone(),
+            size: self.size,
+        };
+        if config.is_compressed {
+            data.save_vmc(&config.path_data.with_extension("vmc"))?;
+        } else {
+            data.save_vmp(&config.path_data.with_extension("vmp"))?;
         }
+        save_flood(&self.flood_map, config)
+    }
+
+    // Center of the tile, at the top of its terrain.
+    pub fn tile_to_world(&self, coord: (i32, i32)) -> cgmath::Point3<f32> {
+        cgmath::Point3::new(
+            coord.0 as f32 + 0.5,
+            coord.1 as f32 + 0.5,
+            altitude_to_height(self.get(coord).top()),
+        )
     }
 
     pub fn export(&self) -> Vec<u8> {
+        let coarse = |alt: Altitude| (alt >> ALTITUDE_FRACTION_BITS) as u8;
         let mut data = vec![0; self.size.0 as usize * self.size.1 as usize * 4];
         for y in 0..self.size.1 {
             let base_y = (y * self.size.0) as usize * 4;
@@ -130,8 +328,8 @@ impl Level {
                 let color = &mut data[base_x..base_x + 4];
                 match self.get((x, y)) {
                     Texel::Single(Point(alt, ty)) => {
-                        color[0] = alt;
-                        color[1] = alt;
+                        color[0] = coarse(alt);
+                        color[1] = coarse(alt);
                         color[2] = 0;
                         color[3] = ty | (ty << 4);
                     }
@@ -140,8 +338,8 @@ impl Level {
                         high: Point(high_alt, high_ty),
                         delta,
                     } => {
-                        color[0] = low_alt;
-                        color[1] = high_alt;
+                        color[0] = coarse(low_alt);
+                        color[1] = coarse(high_alt);
                         color[2] = delta;
                         color[3] = low_ty | (high_ty << 4);
                     }
@@ -150,6 +348,359 @@ impl Level {
         }
         data
     }
+
+    // Lossless version of `export`, keeping its data in the high bytes
+    // of 16-bit RGBA: R and G are the low and high altitudes, B is the delta,
+    // and A has the low and high terrain types in its upper nibbles.
+    // The low byte of A carries the meta bits that are not covered by the
+    // other channels, so that `import_wide` restores the data exactly.
+    pub fn export_wide(&self) -> Vec<u16> {
+        let wide = |alt: Altitude| u16::from(alt) << (8 - ALTITUDE_FRACTION_BITS);
+        let bits = TerrainBits::new(self.terrains.len() as u8);
+        let mut data = vec![0; self.size.0 as usize * self.size.1 as usize * 4];
+        for (i, color) in data.chunks_mut(4).enumerate() {
+            let meta = self.meta[i] & !bits.write(bits.mask);
+            match self.get_index(i, &bits) {
+                Texel::Single(Point(alt, ty)) => {
+                    color[0] = wide(alt);
+                    color[1] = wide(alt);
+                    color[2] = 0;
+                    color[3] = ((ty | (ty << 4)) as u16) << 8 | meta as u16;
+                }
+                Texel::Dual {
+                    low: Point(low_alt, low_ty),
+                    high: Point(high_alt, high_ty),
+                    delta,
+                } => {
+                    // the dual-level flag is implied unless the texel looks single
+                    let implied = if low_alt != high_alt || delta != 0 {
+                        DOUBLE_LEVEL
+                    } else {
+                        0
+                    };
+                    color[0] = wide(low_alt);
+                    color[1] = wide(high_alt);
+                    color[2] = (delta as u16) << 8;
+                    color[3] = ((low_ty | (high_ty << 4)) as u16) << 8
+                        | (meta & !(implied | DELTA_MASK)) as u16;
+                }
+            }
+        }
+        data
+    }
+
+    // Inverse of `export_wide`.
+    pub fn import_wide(&mut self, data: &[u16]) {
+        assert_eq!(data.len(), self.height.len() * 4);
+        let bits = TerrainBits::new(self.terrains.len() as u8);
+        for (i, color) in data.chunks(4).enumerate() {
+            let extra = color[3] as u8;
+            let types = (color[3] >> 8) as u8;
+            let delta = (color[2] >> 8) as u8;
+            // single tiles are exported with the same low and high altitudes
+            let (alt, meta) = if color[0] != color[1] || delta != 0 || extra & DOUBLE_LEVEL != 0 {
+                let (alt, ty, shift) = if i & 1 == 0 {
+                    (color[0], types & 0xF, DELTA_SHIFT0)
+                } else {
+                    (color[1], types >> 4, DELTA_SHIFT1)
+                };
+                let delta = (delta >> shift) & DELTA_MASK;
+                (alt, DOUBLE_LEVEL | bits.write(ty) | delta)
+            } else {
+                (color[0], bits.write(types & 0xF))
+            };
+            self.height[i] = (alt >> 8) as u8;
+            #[cfg(feature = "altitude16")]
+            {
+                self.height_fine[i] = alt as u8;
+            }
+            self.meta[i] = meta | extra;
+        }
+    }
+
+    // Writes the result of `export_wide` into a 16-bit RGBA image,
+    // so that the level can be edited by regular image tools.
+    pub fn export_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.size.0 as u32, self.size.1 as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut bytes = Vec::with_capacity(self.height.len() * 8);
+        for value in self.export_wide() {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&bytes))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+
+    // Reads an image written by `export_png`, which has to match the level size.
+    pub fn import_png(&mut self, path: &Path) -> io::Result<()> {
+        let png_error =
+            |e: png::DecodingError| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+        let file = File::open(path)?;
+        let (info, mut reader) = png::Decoder::new(file).read_info().map_err(png_error)?;
+        if (info.width as i32, info.height as i32) != self.size
+            || info.color_type != png::ColorType::RGBA
+            || info.bit_depth != png::BitDepth::Sixteen
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "PNG {:?} is {}x{} {:?} {:?}, expected {}x{} 16-bit RGBA",
+                    path,
+                    info.width,
+                    info.height,
+                    info.color_type,
+                    info.bit_depth,
+                    self.size.0,
+                    self.size.1
+                ),
+            ));
+        }
+        let mut bytes = vec![0; info.buffer_size()];
+        reader.next_frame(&mut bytes).map_err(png_error)?;
+        let data = bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect::<Vec<_>>();
+        self.import_wide(&data);
+        Ok(())
+    }
+
+    // The vertex at the corner (x, y) takes the height and the color of the
+    // tile (x, y), matching the mesh terrain renderer. Only the upper level of
+    // dual-level tiles is used.
+    pub fn triangulate(&self, region: Option<Rect>) -> TerrainMesh {
+        let rect = region.unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            w: self.size.0 as u16,
+            h: self.size.1 as u16,
+        });
+        let row = rect.w as u32 + 1;
+        let num_vertices = row as usize * (rect.h as usize + 1);
+        let mut mesh = TerrainMesh {
+            positions: Vec::with_capacity(num_vertices),
+            colors: Vec::with_capacity(num_vertices),
+            indices: Vec::with_capacity(rect.w as usize * rect.h as usize * 6),
+        };
+
+        for y in rect.y as i32..=rect.y as i32 + rect.h as i32 {
+            for x in rect.x as i32..=rect.x as i32 + rect.w as i32 {
+                let Point(alt, ty) = match self.get((x, y)) {
+                    Texel::Single(p) => p,
+                    Texel::Dual { high, .. } => high,
+                };
+                let colors = &self.terrains[ty as usize].colors;
+                let index = colors.start + (colors.end - colors.start) / 2;
+                let [r, g, b, _] = self.palette[index as usize];
+                mesh.positions
+                    .push([x as f32, y as f32, altitude_to_height(alt)]);
+                mesh.colors.push([r, g, b, 0xFF]);
+            }
+        }
+        for y in 0..rect.h as u32 {
+            for x in 0..rect.w as u32 {
+                let i0 = y * row + x;
+                let i1 = i0 + row;
+                mesh.indices
+                    .extend_from_slice(&[i0, i0 + 1, i1 + 1, i1 + 1, i1, i0]);
+            }
+        }
+        mesh
+    }
+
+    // Dual texels span a pair of tiles, so the pairs are compared as a whole.
+    pub fn diff(&self, other: &Level) -> Vec<((i32, i32), TexelChange)> {
+        assert_eq!(self.size, other.size);
+        let bits = TerrainBits::new(self.terrains.len() as u8);
+        let tile_changed = |i: usize| {
+            #[cfg(feature = "altitude16")]
+            {
+                if self.height_fine[i] != other.height_fine[i] {
+                    return true;
+                }
+            }
+            self.height[i] != other.height[i] || self.meta[i] != other.meta[i]
+        };
+
+        let mut changes = Vec::new();
+        for pair in (0..self.meta.len()).step_by(2) {
+            let changed = [tile_changed(pair), tile_changed(pair | 1)];
+            if !changed[0] && !changed[1] {
+                continue;
+            }
+            let metas = [self.meta[pair], other.meta[pair]];
+            let dual = metas.iter().any(|&m| m & DOUBLE_LEVEL != 0);
+            for (offset, &own) in changed.iter().enumerate() {
+                if own || dual {
+                    let i = pair | offset;
+                    let coord = (i as i32 % self.size.0, i as i32 / self.size.0);
+                    let change = TexelChange {
+                        before: self.get_index(i, &bits),
+                        after: other.get_index(i, &bits),
+                    };
+                    changes.push((coord, change));
+                }
+            }
+        }
+        changes
+    }
+
+    // CPU reference of the max chain that `MaxMipper` builds from the height texture.
+    // The first level is the height itself, each next one halves the size,
+    // with the last texel of an odd-sized row/column also covering the remainder.
+    pub fn compute_max_mip(&self, levels: u32) -> Vec<Vec<u8>> {
+        let mut mips = Vec::with_capacity(levels as usize);
+        if levels == 0 {
+            return mips;
+        }
+        let mut src_size = (self.size.0 as usize, self.size.1 as usize);
+        mips.push(self.height.clone());
+        for _ in 1..levels {
+            let dst_size = ((src_size.0 / 2).max(1), (src_size.1 / 2).max(1));
+            let block = |dst: usize, dst_size: usize, src_size: usize| {
+                let start = (dst * 2).min(src_size - 1);
+                let end = if dst + 1 == dst_size {
+                    src_size
+                } else {
+                    start + 2
+                };
+                start..end.min(src_size)
+            };
+            let src = mips.last().unwrap();
+            let mut dst = Vec::with_capacity(dst_size.0 * dst_size.1);
+            for y in 0..dst_size.1 {
+                for x in 0..dst_size.0 {
+                    let value = block(y, dst_size.1, src_size.1)
+                        .flat_map(|sy| {
+                            block(x, dst_size.0, src_size.0)
+                                .map(move |sx| src[sy * src_size.0 + sx])
+                        })
+                        .max()
+                        .unwrap();
+                    dst.push(value);
+                }
+            }
+            mips.push(dst);
+            src_size = dst_size;
+        }
+        mips
+    }
+
+    pub fn dual_mask(&self) -> Vec<bool> {
+        self.meta.iter().map(|&m| m & DOUBLE_LEVEL != 0).collect()
+    }
+
+    // Grey for single texels, red/green for the low/high surfaces of dual ones.
+    pub fn export_dual(&self) -> Vec<u8> {
+        let coarse = |alt: Altitude| (alt >> ALTITUDE_FRACTION_BITS) as u8;
+        let mut data = Vec::with_capacity(self.size.0 as usize * self.size.1 as usize * 4);
+        for y in 0..self.size.1 {
+            for x in 0..self.size.0 {
+                let color = match self.get((x, y)) {
+                    Texel::Single(Point(alt, _)) => {
+                        let c = coarse(alt);
+                        [c, c, c, 0xFF]
+                    }
+                    Texel::Dual {
+                        low: Point(low_alt, _),
+                        high: Point(high_alt, _),
+                        ..
+                    } => [coarse(low_alt), coarse(high_alt), 0, 0xFF],
+                };
+                data.extend_from_slice(&color);
+            }
+        }
+        data
+    }
+}
+
+// Marches the ray across the tiles, returns the wrapped tile coordinates
+// of the first solid hit and the distance to it.
+pub fn raycast(
+    level: &Level,
+    origin: cgmath::Point3<f32>,
+    dir: cgmath::Vector3<f32>,
+    max_dist: f32,
+) -> Option<(i32, i32, f32)> {
+    let delta_scale = HEIGHT_SCALE as f32 / 255.0;
+    let step = |d: f32| if d > 0.0 { 1 } else { -1 };
+    let next_border = |pos: f32, cell: i32, d: f32| {
+        if d == 0.0 {
+            std::f32::INFINITY
+        } else {
+            let border = if d > 0.0 { cell + 1 } else { cell };
+            (border as f32 - pos) / d
+        }
+    };
+
+    let mut cell = (origin.x.floor() as i32, origin.y.floor() as i32);
+    let mut t_next = (
+        next_border(origin.x, cell.0, dir.x),
+        next_border(origin.y, cell.1, dir.y),
+    );
+    let t_delta = (1.0 / dir.x.abs(), 1.0 / dir.y.abs());
+    let mut t = 0.0;
+
+    while t < max_dist {
+        let t_exit = t_next.0.min(t_next.1).min(max_dist);
+        let (z_enter, z_exit) = (origin.z + t * dir.z, origin.z + t_exit * dir.z);
+        // solid layers of the tile as `(bottom, top)` ranges
+        let (ground, ceiling) = match level.get(cell) {
+            Texel::Single(Point(alt, _)) => (altitude_to_height(alt), None),
+            Texel::Dual {
+                low: Point(low_alt, _),
+                high: Point(high_alt, _),
+                delta,
+            } => {
+                let low = altitude_to_height(low_alt);
+                let bottom = low + delta as f32 * delta_scale;
+                (low, Some((bottom, altitude_to_height(high_alt))))
+            }
+        };
+
+        let mut hit = None;
+        if z_enter <= ground {
+            hit = Some(t);
+        } else if z_exit <= ground {
+            hit = Some(t + (z_enter - ground) / -dir.z);
+        }
+        if let Some((bottom, top)) = ceiling {
+            let t_layer = if z_enter >= bottom && z_enter <= top {
+                Some(t)
+            } else if z_enter > top && z_exit <= top {
+                Some(t + (top - z_enter) / dir.z)
+            } else if z_enter < bottom && z_exit >= bottom {
+                Some(t + (bottom - z_enter) / dir.z)
+            } else {
+                None
+            };
+            if let Some(tl) = t_layer {
+                hit = Some(hit.map_or(tl, |th: f32| th.min(tl)));
+            }
+        }
+        if let Some(th) = hit {
+            return Some((
+                cell.0.rem_euclid(level.size.0),
+                cell.1.rem_euclid(level.size.1),
+                th,
+            ));
+        }
+
+        t = t_exit;
+        if t_next.0 < t_next.1 {
+            cell.0 += step(dir.x);
+            t_next.0 += t_delta.0;
+        } else {
+            cell.1 += step(dir.y);
+            t_next.1 += t_delta.1;
+        }
+    }
+
+    None
 }
 
 #[allow(unused)]
@@ -168,7 +719,7 @@ pub fn read_palette(input: File, config: Option<&[TerrainConfig]>) -> [[u8; 4];
     let mut file = BufReader::new(input);
     let mut data = [[0; 4]; 0x100];
     for p in data.iter_mut() {
-        file.read(&mut p[..3]).unwrap();
+        file.read_exact(&mut p[..3]).unwrap();
         //p[0] <<= 2; p[1] <<= 2; p[2] <<= 2;
     }
     //print_palette(&data, "read from file");
@@ -210,9 +761,20 @@ fn report_time(start: Instant) {
     );
 }
 
-pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
+// Returns the number of flood sections and the offset of the flood map in the VPR.
+fn flood_layout(config: &LevelConfig) -> (i32, u64) {
     let size = (config.size.0.as_value(), config.size.1.as_value());
     let flood_size = size.1 >> config.section.as_power();
+    let geo_pow = config.geo.as_power();
+    let net_size = size.0 * size.1 >> (2 * geo_pow);
+    let flood_offset =
+        (2 * 4 + (1 + 4 + 4) * 4 + 2 * net_size + 2 * geo_pow * 4 + 2 * flood_size * geo_pow * 4)
+            as u64;
+    (flood_size, flood_offset)
+}
+
+pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
+    let (flood_size, flood_offset) = flood_layout(config);
 
     let instant = Instant::now();
     let flood_map = {
@@ -222,13 +784,6 @@ pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
         };
 
         info!("Loading flood map...");
-        let geo_pow = config.geo.as_power();
-        let net_size = size.0 * size.1 >> (2 * geo_pow);
-        let flood_offset = (2 * 4
-            + (1 + 4 + 4) * 4
-            + 2 * net_size
-            + 2 * geo_pow * 4
-            + 2 * flood_size * geo_pow * 4) as u64;
         let expected_file_size = flood_offset + (flood_size * 4) as u64;
         assert_eq!(vpr_file.metadata().unwrap().len(), expected_file_size,);
         let mut vpr = BufReader::new(vpr_file);
@@ -242,6 +797,49 @@ pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
     flood_map
 }
 
+// Patches the flood map in place, keeping the rest of the VPR tables.
+// A missing VPR is created with these tables zeroed.
+pub fn save_flood(flood_map: &[u8], config: &LevelConfig) -> io::Result<()> {
+    let (flood_size, flood_offset) = flood_layout(config);
+    if flood_map.len() != flood_size as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Flood map has {} sections, expected {}",
+                flood_map.len(),
+                flood_size
+            ),
+        ));
+    }
+    let expected_file_size = flood_offset + (flood_size * 4) as u64;
+    let path = config.path_data.with_extension("vpr");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&path)?;
+    match file.metadata()?.len() {
+        0 => file.set_len(expected_file_size)?,
+        size if size == expected_file_size => {}
+        size => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "VPR {:?} has {} bytes, expected {}",
+                    path, size, expected_file_size
+                ),
+            ))
+        }
+    }
+
+    let mut vpr = BufWriter::new(file);
+    vpr.seek(SeekFrom::Start(flood_offset))?;
+    for &level in flood_map {
+        vpr.write_u32::<E>(level as u32)?;
+    }
+    vpr.flush()
+}
+
 pub struct LevelData {
     pub height: Vec<u8>,
     pub meta: Vec<u8>,
@@ -263,37 +861,43 @@ fn avg(a: u8, b: u8) -> u8 {
 }
 
 impl LevelData {
-    pub fn save_vmp(&self, path: &Path) {
-        let mut vmp = BufWriter::new(File::create(path).unwrap());
-        self.height
+    pub fn save_vmp(&self, path: &Path) -> io::Result<()> {
+        let mut vmp = BufWriter::new(File::create(path)?);
+        for (h_row, m_row) in self
+            .height
             .chunks(self.size.0 as _)
             .zip(self.meta.chunks(self.size.0 as _))
-            .for_each(|(h_row, m_row)| {
-                vmp.write(h_row).unwrap();
-                vmp.write(m_row).unwrap();
-            });
+        {
+            vmp.write_all(h_row)?;
+            vmp.write_all(m_row)?;
+        }
+        vmp.flush()
     }
 
-    pub fn save_vmc(&self, path: &Path) {
+    // Writes the rows with a trivial splay tree that keeps every byte as is,
+    // so the output is as large as a VMP. It is only meant to be read back
+    // by `load_vmc`, and isn't tested with the original game.
+    pub fn save_vmc(&self, path: &Path) -> io::Result<()> {
         use splay::Splay;
-        let mut vmc = BufWriter::new(File::create(path).unwrap());
+        let mut vmc = BufWriter::new(File::create(path)?);
 
         let base_offset = self.size.1 as u64 * (2 + 4) + Splay::tree_size();
         for i in 0..self.size.1 {
-            vmc.write_i32::<E>(base_offset as i32 + i * self.size.0 * 2)
-                .unwrap();
-            vmc.write_i16::<E>(self.size.0 as i16 * 2).unwrap();
+            vmc.write_i32::<E>(base_offset as i32 + i * self.size.0 * 2)?;
+            vmc.write_i16::<E>(self.size.0 as i16 * 2)?;
         }
 
-        Splay::write_trivial(&mut vmc);
-        assert_eq!(vmc.seek(SeekFrom::Current(0)).unwrap(), base_offset);
+        Splay::write_trivial(&mut vmc)?;
+        assert_eq!(vmc.seek(SeekFrom::Current(0))?, base_offset);
 
-        self.height
+        for (h_row, m_row) in self
+            .height
             .chunks(self.size.0 as _)
             .zip(self.meta.chunks(self.size.0 as _))
-            .for_each(|(h_row, m_row)| {
-                Splay::compress_trivial(h_row, m_row, &mut vmc);
-            });
+        {
+            Splay::compress_trivial(h_row, m_row, &mut vmc)?;
+        }
+        vmc.flush()
     }
 
     pub fn import(data: &[u8], size: (i32, i32), terrain_shift: u8) -> Self {
@@ -334,7 +938,70 @@ impl LevelData {
     }
 }
 
+pub fn read_vmc_tables(vmc: &mut impl Read, rows: i32) -> (Vec<i32>, Vec<i16>) {
+    let mut st_table = Vec::<i32>::with_capacity(rows as usize);
+    let mut sz_table = Vec::<i16>::with_capacity(rows as usize);
+    for _ in 0..rows {
+        st_table.push(vmc.read_i32::<E>().unwrap());
+        sz_table.push(vmc.read_i16::<E>().unwrap());
+    }
+    (st_table, sz_table)
+}
+
+fn expand_row<R: Read + Seek>(
+    vmc: &mut R,
+    splay: &splay::Splay,
+    data: &mut Vec<u8>,
+    offset: i32,
+    size: i16,
+    h_row: &mut [u8],
+    m_row: &mut [u8],
+) {
+    if data.len() < size as usize {
+        data.resize(size as usize, 0);
+    }
+    vmc.seek(SeekFrom::Start(offset as u64)).unwrap();
+    vmc.read_exact(&mut data[..size as usize]).unwrap();
+    splay.expand(&data[..size as usize], h_row, m_row);
+}
+
+/// Decodes the height and meta planes of a VMC stream, given its row tables.
+pub fn decompress_rows<R: Read + Seek>(
+    mut vmc: R,
+    st_table: &[i32],
+    sz_table: &[i16],
+    size: (i32, i32),
+) -> (Vec<u8>, Vec<u8>) {
+    let total = (size.0 * size.1) as usize;
+    let mut height = vec![0u8; total];
+    let mut meta = vec![0u8; total];
+
+    // the splay tree follows the row tables
+    vmc.seek(SeekFrom::Start(size.1 as u64 * (4 + 2))).unwrap();
+    let splay = splay::Splay::new(&mut vmc);
+
+    let mut data = Vec::new();
+    for ((h_row, m_row), (&offset, &row_size)) in height
+        .chunks_mut(size.0 as _)
+        .zip(meta.chunks_mut(size.0 as _))
+        .zip(st_table.iter().zip(sz_table))
+    {
+        expand_row(&mut vmc, &splay, &mut data, offset, row_size, h_row, m_row);
+    }
+
+    (height, meta)
+}
+
 pub fn load_vmc(path: &Path, size: (i32, i32)) -> LevelData {
+    load_vmc_with_progress(path, size, &mut |_| {})
+}
+
+// Reports the fraction of the rows decompressed so far.
+fn load_vmc_with_progress(
+    path: &Path,
+    size: (i32, i32),
+    progress: &mut dyn FnMut(f32),
+) -> LevelData {
     use rayon::prelude::*;
     use splay::Splay;
 
@@ -350,39 +1017,50 @@ pub fn load_vmc(path: &Path, size: (i32, i32)) -> LevelData {
     let mut vmc_base = BufReader::new(File::open(path).expect("Unable to open VMC"));
 
     info!("\tLoading compression tables...");
-    let mut st_table = Vec::<i32>::with_capacity(size.1 as usize);
-    let mut sz_table = Vec::<i16>::with_capacity(size.1 as usize);
-    for _ in 0..size.1 {
-        st_table.push(vmc_base.read_i32::<E>().unwrap());
-        sz_table.push(vmc_base.read_i16::<E>().unwrap());
-    }
+    let (st_table, sz_table) = read_vmc_tables(&mut vmc_base, size.1);
 
     info!("\tDecompressing level data...");
     let splay = Splay::new(&mut vmc_base);
 
-    level
+    let mut rows = level
         .height
         .chunks_mut(size.0 as _)
         .zip(level.meta.chunks_mut(size.0 as _))
         .zip(st_table.iter().zip(&sz_table))
-        .collect::<Vec<_>>()
-        .par_chunks_mut(64)
-        .for_each(|source_group| {
-            //Note: a separate file per group is required
-            let mut vmc = File::open(path).unwrap();
-            let data_size: i16 = source_group
-                .iter()
-                .map(|(_, (_, &size))| size)
-                .max()
-                .unwrap();
-            let mut data = vec![0u8; data_size as usize];
-            for &mut ((ref mut h_row, ref mut m_row), (offset, &size)) in source_group {
-                vmc.seek(SeekFrom::Start(*offset as u64)).unwrap();
-                vmc.read(&mut data[..size as usize]).unwrap();
-                splay.expand(&data[..size as usize], h_row, m_row);
-            }
+        .collect::<Vec<_>>();
+    // The progress callback can't leave this thread, so the decompression
+    // runs on a scoped thread and the groups send their row counts back here.
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        let splay = &splay;
+        let rows = &mut rows;
+        scope.spawn(move || {
+            rows.par_chunks_mut(64)
+                .for_each_with(sender, |sender, source_group| {
+                    //Note: a separate file per group is required
+                    let mut vmc = File::open(path).unwrap();
+                    let data_size: i16 = source_group
+                        .iter()
+                        .map(|(_, (_, &size))| size)
+                        .max()
+                        .unwrap();
+                    let mut data = vec![0u8; data_size as usize];
+                    for &mut ((ref mut h_row, ref mut m_row), (&offset, &size)) in
+                        source_group.iter_mut()
+                    {
+                        expand_row(&mut vmc, splay, &mut data, offset, size, h_row, m_row);
+                    }
+                    let _ = sender.send(source_group.len());
+                });
         });
 
+        let mut rows_done = 0;
+        for count in receiver {
+            rows_done += count;
+            progress(rows_done as f32 / size.1 as f32);
+        }
+    });
+
     report_time(instant);
     level
 }
@@ -401,33 +1079,93 @@ pub fn load_vmp(path: &Path, size: (i32, i32)) -> LevelData {
         .chunks_mut(size.0 as _)
         .zip(level.meta.chunks_mut(size.0 as _))
         .for_each(|(h_row, m_row)| {
-            vmp.read(h_row).unwrap();
-            vmp.read(m_row).unwrap();
+            vmp.read_exact(h_row).unwrap();
+            vmp.read_exact(m_row).unwrap();
         });
 
     level
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadPhase {
+    Vmc,
+    Vmp,
+    Vpr,
+    Palette,
+}
+
 pub fn load(config: &LevelConfig) -> Level {
+    load_with_pool(config, None)
+}
+
+// Runs the parallel decompression on the given pool instead of the global one.
+pub fn load_with_pool(config: &LevelConfig, pool: Option<&rayon::ThreadPool>) -> Level {
+    match pool {
+        Some(pool) => pool.install(|| load_level(config, &mut |_, _| {})),
+        None => load_level(config, &mut |_, _| {}),
+    }
+}
+
+// Calls `progress` with the current phase and its completed fraction,
+// always starting each phase at 0 and finishing it at 1.
+pub fn load_with_progress(config: &LevelConfig, progress: &mut dyn FnMut(LoadPhase, f32)) -> Level {
+    load_level(config, progress)
+}
+
+fn load_level(config: &LevelConfig, progress: &mut dyn FnMut(LoadPhase, f32)) -> Level {
     info!("Loading data map...");
     let size = (config.size.0.as_value(), config.size.1.as_value());
     let LevelData { height, meta, size } = if config.is_compressed {
-        load_vmc(&config.path_data.with_extension("vmc"), size)
+        progress(LoadPhase::Vmc, 0.0);
+        let path = config.path_data.with_extension("vmc");
+        load_vmc_with_progress(&path, size, &mut |ratio| progress(LoadPhase::Vmc, ratio))
     } else {
-        load_vmp(&config.path_data.with_extension("vmp"), size)
+        progress(LoadPhase::Vmp, 0.0);
+        let data = load_vmp(&config.path_data.with_extension("vmp"), size);
+        progress(LoadPhase::Vmp, 1.0);
+        data
     };
 
     info!("Loading flood map...");
+    progress(LoadPhase::Vpr, 0.0);
     let flood_map = load_flood(config);
+    progress(LoadPhase::Vpr, 1.0);
+
+    progress(LoadPhase::Palette, 0.0);
     let palette = File::open(&config.path_palette).expect("Unable to open the palette file");
+    let palette = read_palette(palette, Some(&config.terrains));
+    progress(LoadPhase::Palette, 1.0);
 
     Level {
         size,
         flood_map,
         flood_section_power: config.section.as_power() as usize,
+        geo_power: config.geo.as_power() as usize,
+        #[cfg(feature = "altitude16")]
+        height_fine: vec![0; height.len()],
         height,
         meta,
-        palette: read_palette(palette, Some(&config.terrains)),
+        palette,
         terrains: config.terrains.clone(),
     }
 }
+
+// Runs `load` as a task on the global rayon pool, resolving once the level is ready.
+pub fn load_async(config: LevelConfig) -> impl std::future::Future<Output = Level> {
+    use futures::FutureExt as _;
+    use std::panic;
+
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    // A panic in a rayon task would abort the process,
+    // so it's forwarded to the future instead, the same as `load` would panic.
+    rayon::spawn(move || {
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| load(&config)));
+        let _ = sender.send(result);
+    });
+    receiver.map(
+        |result| match result.expect("Level loading task was dropped") {
+            Ok(level) => level,
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    )
+}
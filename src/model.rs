@@ -1,7 +1,10 @@
-use crate::render::{
-    debug::Position as DebugPos,
-    object::{Context as ObjectContext, Vertex as ObjectVertex},
-    ShapePolygon,
+use crate::{
+    render::{
+        debug::Position as DebugPos,
+        object::{Context as ObjectContext, Vertex as ObjectVertex},
+        ShapePolygon,
+    },
+    space::Transform,
 };
 use m3d;
 use wgpu::util::DeviceExt as _;
@@ -17,6 +20,8 @@ pub struct BoundingBox {
 pub struct Mesh {
     pub num_vertices: usize,
     pub vertex_buf: wgpu::Buffer,
+    pub num_indices: usize,
+    pub index_buf: wgpu::Buffer,
     pub offset: [f32; 3],
     pub bbox: BoundingBox,
     pub physics: m3d::Physics,
@@ -124,39 +129,40 @@ pub fn load_c3d(
     raw: m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>,
     device: &wgpu::Device,
 ) -> Arc<Mesh> {
-    let num_vertices = raw.geometry.polygons.len() * 3;
-    debug!("\tGot {} GPU vertices...", num_vertices);
-    let vertex_size = mem::size_of::<ObjectVertex>();
-    let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
+    let (vertices, indices) = raw.geometry.compact().unwrap();
+    debug!(
+        "\tGot {} GPU vertices for {} corners...",
+        vertices.len(),
+        indices.len()
+    );
+    let vertex_data = vertices
+        .iter()
+        .map(|v| {
+            let p = raw.geometry.positions[v.pos as usize];
+            let n = raw.geometry.normals[v.normal as usize];
+            ObjectVertex {
+                pos: [p[0], p[1], p[2], 1],
+                color: v.color,
+                normal: [n[0], n[1], n[2], 0],
+            }
+        })
+        .collect::<Vec<_>>();
+    let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("C3D"),
-        size: (num_vertices * vertex_size) as wgpu::BufferAddress,
+        contents: bytemuck::cast_slice(&vertex_data),
         usage: wgpu::BufferUsage::VERTEX,
-        mapped_at_creation: true,
     });
-    {
-        let mut mapping = vertex_buf.slice(..).get_mapped_range_mut();
-        for (chunk, tri) in mapping
-            .chunks_mut(3 * vertex_size)
-            .zip(&raw.geometry.polygons)
-        {
-            let out_vertices =
-                unsafe { slice::from_raw_parts_mut(chunk.as_mut_ptr() as *mut ObjectVertex, 3) };
-            for (vo, v) in out_vertices.iter_mut().zip(&tri.vertices) {
-                let p = raw.geometry.positions[v.pos as usize];
-                let n = raw.geometry.normals[v.normal as usize];
-                *vo = ObjectVertex {
-                    pos: [p[0], p[1], p[2], 1],
-                    color: tri.material[0],
-                    normal: [n[0], n[1], n[2], 0],
-                };
-            }
-        }
-    }
-    vertex_buf.unmap();
+    let index_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("C3D indices"),
+        contents: bytemuck::cast_slice(&indices),
+        usage: wgpu::BufferUsage::INDEX,
+    });
 
     Arc::new(Mesh {
-        num_vertices,
+        num_vertices: vertices.len(),
         vertex_buf,
+        num_indices: indices.len(),
+        index_buf,
         offset: vec_i2f(raw.parent_off),
         bbox: BoundingBox {
             min: vec_i2f(raw.bounds.coord_min),
@@ -262,13 +268,24 @@ pub fn load_c3d_shape(
         }
     };
     vertex_buf.unmap();
+    let polygon_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("polygons"),
+        contents: bytemuck::cast_slice(&polygon_data),
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::STORAGE,
+    });
     let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("Shape"),
         layout: &object.shape_bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: vertex_buf.as_entire_binding(),
-        }],
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: vertex_buf.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: polygon_buf.as_entire_binding(),
+            },
+        ],
     });
 
     Arc::new(Shape {
@@ -276,11 +293,7 @@ pub fn load_c3d_shape(
         samples,
         vertex_buf,
         bind_group,
-        polygon_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("polygons"),
-            contents: bytemuck::cast_slice(&polygon_data),
-            usage: wgpu::BufferUsage::VERTEX,
-        }),
+        polygon_buf,
         sample_buf: if with_sample_buf {
             let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("samples"),
@@ -297,13 +310,69 @@ pub fn load_c3d_shape(
 
 pub type VisualModel = m3d::Model<Arc<Mesh>, Arc<Shape>>;
 
+// Placement of a slot mesh relative to the model, see `Batcher::add_model_posed`.
+pub fn slot_transform(base: &Transform, slot: &m3d::Slot<Arc<Mesh>>, mesh: &Mesh) -> Transform {
+    use cgmath::{Rotation3 as _, Transform as _};
+
+    let mut local = Transform {
+        disp: cgmath::vec3(slot.pos[0] as f32, slot.pos[1] as f32, slot.pos[2] as f32),
+        rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(slot.angle as f32)),
+        scale: slot.scale / base.scale,
+    };
+    local.disp -= local.transform_vector(cgmath::Vector3::from(mesh.offset));
+    base.concat(&local)
+}
+
+// World-space AABB of the body, the wheels, and the slots.
+// The wheels are taken in their rest pose.
+pub fn world_bounds(
+    model: &VisualModel,
+    transform: &Transform,
+) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+    use cgmath::{One as _, Transform as _};
+
+    let mut min = cgmath::Point3::new(f32::MAX, f32::MAX, f32::MAX);
+    let mut max = cgmath::Point3::new(f32::MIN, f32::MIN, f32::MIN);
+    let mut add = |bbox: &BoundingBox, t: &Transform| {
+        for i in 0..8 {
+            let corner = cgmath::Point3::new(
+                if i & 1 != 0 { bbox.max[0] } else { bbox.min[0] },
+                if i & 2 != 0 { bbox.max[1] } else { bbox.min[1] },
+                if i & 4 != 0 { bbox.max[2] } else { bbox.min[2] },
+            );
+            let p = t.transform_point(corner);
+            min = cgmath::Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = cgmath::Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+    };
+
+    add(&model.body.bbox, transform);
+    for w in model.wheels.iter() {
+        if let Some(ref mesh) = w.mesh {
+            let local = Transform {
+                disp: mesh.offset.into(),
+                rot: cgmath::Quaternion::one(),
+                scale: 1.0,
+            };
+            add(&mesh.bbox, &transform.concat(&local));
+        }
+    }
+    for s in model.slots.iter() {
+        if let Some(ref mesh) = s.mesh {
+            add(&mesh.bbox, &slot_transform(transform, s, mesh));
+        }
+    }
+
+    (min, max)
+}
+
 pub fn load_m3d(
     file: File,
     device: &wgpu::Device,
     object: &ObjectContext,
     shape_sampling: u8,
 ) -> VisualModel {
-    let raw = m3d::FullModel::load(file);
+    let raw = m3d::FullModel::load(file).unwrap();
 
     let model = VisualModel {
         body: load_c3d(raw.body, device),
@@ -0,0 +1,142 @@
+use crate::render::{global::Context as GlobalContext, RenderFormats, Shaders};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt as _;
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Locals {
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+}
+unsafe impl Pod for Locals {}
+unsafe impl Zeroable for Locals {}
+
+// Vertical gradient behind the scene, drawn with a full-screen triangle
+// at the far depth, so that anything else covers it.
+pub struct Background {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    formats: RenderFormats,
+    sample_count: u32,
+}
+
+impl Background {
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let shaders = Shaders::new("background", &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("background"),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[formats.color.into()],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: formats.depth,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+
+    pub(super) fn new(
+        zenith: [f32; 4],
+        horizon: [f32; 4],
+        global: &GlobalContext,
+        device: &wgpu::Device,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Background"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("background"),
+            bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("background-locals"),
+            contents: bytemuck::bytes_of(&Locals { zenith, horizon }),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Background"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buf.as_entire_binding(),
+            }],
+        });
+
+        Background {
+            pipeline: Self::create_pipeline(
+                &pipeline_layout,
+                global.formats(),
+                global.sample_count,
+                device,
+            ),
+            pipeline_layout,
+            uniform_buf,
+            bind_group,
+            formats: global.formats(),
+            sample_count: global.sample_count,
+        }
+    }
+
+    pub(super) fn set_colors(&self, zenith: [f32; 4], horizon: [f32; 4], queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buf,
+            0,
+            bytemuck::bytes_of(&Locals { zenith, horizon }),
+        );
+    }
+
+    pub(super) fn reload(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            &self.pipeline_layout,
+            self.formats,
+            self.sample_count,
+            device,
+        );
+    }
+
+    // Expects the globals to be bound at set 0.
+    pub(super) fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
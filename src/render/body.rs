@@ -1,6 +1,7 @@
 use crate::{
     config::{car::CarPhysics, common::Common, settings},
     freelist::{self, FreeList},
+    level::Level,
     model::VisualModel,
     render::{collision::GpuRange, GpuTransform, Shaders},
     space::Transform,
@@ -17,7 +18,10 @@ use std::{
 };
 
 const WORK_GROUP_WIDTH: u32 = 32;
-const MAX_WHEELS: usize = 4;
+// Passed to the shaders as `MAX_WHEELS`, see `Shaders`.
+pub const MAX_WHEELS: usize = 8;
+pub const COLLISION_LAYER_TERRAIN: u32 = 1;
+pub const COLLISION_LAYER_BODY: u32 = 2;
 
 pub type GpuControl = [f32; 4];
 
@@ -30,7 +34,7 @@ unsafe impl Pod for GpuPush {}
 unsafe impl Zeroable for GpuPush {}
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 struct Physics {
     scale: [f32; 4],
     mobility_ship: [f32; 4],
@@ -40,7 +44,7 @@ unsafe impl Pod for Physics {}
 unsafe impl Zeroable for Physics {}
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 struct Model {
     jacobi0: [f32; 4],
     jacobi1: [f32; 4],
@@ -50,7 +54,7 @@ unsafe impl Pod for Model {}
 unsafe impl Zeroable for Model {}
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Data {
     control: GpuControl,
     engine: [f32; 4],
@@ -62,6 +66,9 @@ pub struct Data {
     model: Model,
     physics: Physics,
     wheels: [[f32; 4]; MAX_WHEELS],
+    // Kept out of the range restored by `GpuStore::reset`,
+    // so that resetting a body doesn't change these.
+    flags: [u32; 4], // X = collision mask, Y = active
 }
 unsafe impl Pod for Data {}
 unsafe impl Zeroable for Data {}
@@ -86,6 +93,7 @@ impl Data {
             speed: [0.0; 4],
         },
         wheels: [[0.0; 4]; MAX_WHEELS],
+        flags: [0; 4],
     };
 }
 
@@ -114,6 +122,7 @@ struct DragConstants {
 #[derive(Clone, Copy, Debug)]
 struct Constants {
     nature: [f32; 4],
+    gravity: [f32; 4],
     global_speed: [f32; 4],
     global_mobility: [f32; 4],
     car_rudder: [f32; 4],
@@ -128,12 +137,155 @@ struct Constants {
 unsafe impl Pod for Constants {}
 unsafe impl Zeroable for Constants {}
 
+impl Constants {
+    fn new(common: &Common, gravity: cgmath::Vector3<f32>) -> Self {
+        Constants {
+            nature: [
+                common.nature.time_delta0,
+                common.nature.density,
+                common.nature.gravity,
+                0.0,
+            ],
+            gravity: [gravity.x, gravity.y, gravity.z, 0.0],
+            global_speed: [
+                common.global.speed_factor,
+                common.global.water_speed_factor,
+                common.global.air_speed_factor,
+                common.global.underground_speed_factor,
+            ],
+            global_mobility: [common.global.mobility_factor, 0.0, 0.0, 0.0],
+            car_rudder: [
+                common.car.rudder_step,
+                common.car.rudder_max,
+                common.car.rudder_k_decr,
+                0.0,
+            ],
+            car_traction: [common.car.traction_incr, common.car.traction_decr, 0.0, 0.0],
+            impulse_elastic: [
+                common.impulse.elastic_restriction,
+                common.impulse.elastic_time_scale_factor,
+                0.0,
+                0.0,
+            ],
+            impulse_factors: [
+                common.impulse.factors[0],
+                common.impulse.factors[1],
+                0.0,
+                0.0,
+            ],
+            impulse: [
+                common.impulse.rolling_scale,
+                common.impulse.normal_threshold,
+                common.impulse.k_wheel,
+                common.impulse.k_friction,
+            ],
+            drag: DragConstants {
+                free: common.drag.free.to_array(),
+                speed: common.drag.speed.to_array(),
+                spring: common.drag.spring.to_array(),
+                abs_min: common.drag.abs_min.to_array(),
+                abs_stop: common.drag.abs_stop.to_array(),
+                coll: common.drag.coll.to_array(),
+                other: [common.drag.wheel_speed, common.drag.z],
+                _pad: [0.0; 2],
+            },
+            contact_elastic: [
+                common.contact.k_elastic_wheel,
+                common.contact.k_elastic_spring,
+                common.contact.k_elastic_xy,
+                common.contact.k_elastic_db_coll,
+            ],
+            force: [common.force.k_distance_to_force, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
 pub type GpuBody = freelist::Id<Data>;
 
+#[derive(Debug)]
+pub enum AllocError {
+    Capacity { requested: usize, max: usize },
+    TooManyWheels { count: usize, max: usize },
+}
+
+// Packs the wheel positions and steering for the shaders.
+pub fn pack_wheels<M>(wheels: &[m3d::Wheel<M>]) -> Result<[[f32; 4]; MAX_WHEELS], AllocError> {
+    if wheels.len() > MAX_WHEELS {
+        return Err(AllocError::TooManyWheels {
+            count: wheels.len(),
+            max: MAX_WHEELS,
+        });
+    }
+    let mut packed = [[0.0; 4]; MAX_WHEELS];
+    for (wo, wi) in packed.iter_mut().zip(wheels.iter()) {
+        //TODO: take X bounds like the original did?
+        wo[0] = wi.pos[0];
+        wo[1] = wi.pos[1];
+        wo[2] = wi.pos[2];
+        wo[3] = if wi.steer != 0 { 1.0 } else { -1.0 };
+    }
+    Ok(packed)
+}
+
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    pub dt: f32,
+    accumulator: f32,
+    paused: bool,
+    single_steps: usize,
+}
+
+impl FixedTimestep {
+    pub fn new(dt: f32) -> Self {
+        assert!(dt > 0.0, "Fixed time step has to be positive");
+        FixedTimestep {
+            dt,
+            accumulator: 0.0,
+            paused: false,
+            single_steps: 0,
+        }
+    }
+
+    // While paused, the elapsed time is dropped and only
+    // the steps requested by `step_once` are produced.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn step_once(&mut self) {
+        self.single_steps += 1;
+    }
+
+    pub fn has_pending_steps(&self) -> bool {
+        self.single_steps != 0
+    }
+
+    pub fn advance(&mut self, delta: f32) -> usize {
+        let single_steps = mem::replace(&mut self.single_steps, 0);
+        if self.paused {
+            return single_steps;
+        }
+        self.accumulator += delta;
+        let count = (self.accumulator / self.dt).floor() as usize;
+        self.accumulator -= count as f32 * self.dt;
+        count + single_steps
+    }
+
+    pub fn remainder(&self) -> f32 {
+        self.accumulator
+    }
+}
+
 struct Pipelines {
     step: wgpu::ComputePipeline,
     gather: wgpu::ComputePipeline,
     push: wgpu::ComputePipeline,
+    #[cfg(feature = "body_collision")]
+    collide: wgpu::ComputePipeline,
 }
 
 impl Pipelines {
@@ -186,6 +338,21 @@ impl Pipelines {
                     entry_point: "main",
                 },
             }),
+            #[cfg(feature = "body_collision")]
+            collide: device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("body-collide"),
+                layout: Some(layout_step),
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &Shaders::new_compute(
+                        "physics/body_collide",
+                        [WORK_GROUP_WIDTH, 1, 1],
+                        &[],
+                        device,
+                    )
+                    .unwrap(),
+                    entry_point: "main",
+                },
+            }),
         }
     }
 }
@@ -246,21 +413,123 @@ impl GpuStoreInit {
 enum Update {
     InitData { index: usize },
     SetControl { index: usize },
+    SetActive { active: bool },
+    SetCollisionMask { index: usize },
+    Reset { index: usize },
 }
 
+// Part of `Data` from `engine` to `collision` inclusive,
+// restored by `GpuStore::reset`.
+#[repr(C)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct GpuReset {
+    engine: [f32; 4],
+    pos_scale: [f32; 4],
+    orientation: [f32; 4],
+    linear: [f32; 4],
+    angular: [f32; 4],
+    collision: [f32; 4],
+}
+unsafe impl Pod for GpuReset {}
+unsafe impl Zeroable for GpuReset {}
+
 struct GpuResult {
     buffer: wgpu::Buffer,
     count: usize,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuMotion {
+    pos_scale: [f32; 4],
+    orientation: [f32; 4],
+    linear: [f32; 4],
+    angular: [f32; 4],
+}
+
 pub struct GpuStoreMirror {
     transforms: Vec<Transform>,
+    velocities: Vec<cgmath::Vector3<f32>>,
+    angular_velocities: Vec<cgmath::Vector3<f32>>,
 }
 
 impl GpuStoreMirror {
     pub fn get(&self, body: &GpuBody) -> Option<&Transform> {
         self.transforms.get(body.index())
     }
+
+    // Linear and angular velocities in world space.
+    pub fn get_velocity(
+        &self,
+        body: &GpuBody,
+    ) -> Option<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)> {
+        let linear = self.velocities.get(body.index())?;
+        let angular = self.angular_velocities.get(body.index())?;
+        Some((*linear, *angular))
+    }
+
+    // Transforms and linear velocities of all the slots, see `get_velocity`.
+    pub fn motions(&self) -> impl Iterator<Item = (&Transform, cgmath::Vector3<f32>)> {
+        self.transforms.iter().zip(self.velocities.iter().cloned())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SimSnapshot {
+    free_list: FreeList<Data>,
+    data: Vec<Data>,
+    spawns: Vec<GpuReset>,
+}
+
+// Snapshot recorded by `GpuStore::snapshot`, which is
+// available after the command encoder is submitted.
+pub struct PendingSnapshot {
+    buffer: Option<wgpu::Buffer>,
+    free_list: FreeList<Data>,
+    spawns: Vec<GpuReset>,
+}
+
+impl PendingSnapshot {
+    pub fn wait(self, device: &wgpu::Device) -> SimSnapshot {
+        let data = match self.buffer {
+            Some(buffer) => {
+                let future = buffer.slice(..).map_async(wgpu::MapMode::Read);
+                device.poll(wgpu::Maintain::Wait);
+                futures::executor::block_on(future).unwrap();
+                let data = {
+                    let mapping = buffer.slice(..).get_mapped_range();
+                    bytemuck::cast_slice::<u8, Data>(&mapping).to_vec()
+                };
+                buffer.unmap();
+                data
+            }
+            None => Vec::new(),
+        };
+        SimSnapshot {
+            free_list: self.free_list,
+            data,
+            spawns: self.spawns,
+        }
+    }
+}
+
+impl SimSnapshot {
+    pub fn body_count(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn transform(&self, body: &GpuBody) -> Option<Transform> {
+        self.data.get(body.index()).map(|d| Transform {
+            disp: cgmath::vec3(d.pos_scale[0], d.pos_scale[1], d.pos_scale[2]),
+            rot: cgmath::Quaternion::new(
+                d.orientation[3],
+                d.orientation[0],
+                d.orientation[1],
+                d.orientation[2],
+            ),
+            scale: d.pos_scale[3],
+        })
+    }
 }
 
 pub struct GpuStore {
@@ -272,7 +541,9 @@ pub struct GpuStore {
     buf_uniforms: wgpu::Buffer,
     buf_ranges: wgpu::Buffer,
     buf_pushes: wgpu::Buffer,
+    buf_constants: wgpu::Buffer,
     capacity: usize,
+    gravity_override: Option<cgmath::Vector3<f32>>,
     bind_group: wgpu::BindGroup,
     bind_group_gather: wgpu::BindGroup,
     bind_group_push: wgpu::BindGroup,
@@ -280,6 +551,9 @@ pub struct GpuStore {
     updates: Vec<(usize, Update)>,
     update_data: Vec<Data>,
     update_control: Vec<GpuControl>,
+    update_masks: Vec<u32>,
+    update_resets: Vec<GpuReset>,
+    spawns: Vec<GpuReset>,
     pending_pushes: Vec<GpuPush>,
     gpu_result: Option<GpuResult>,
     cpu_mirror: Arc<Mutex<GpuStoreMirror>>,
@@ -420,67 +694,12 @@ impl GpuStore {
         };
         let buf_pushes = device.create_buffer(&desc_pushes);
 
-        let constants = Constants {
-            nature: [
-                common.nature.time_delta0,
-                common.nature.density,
-                common.nature.gravity,
-                0.0,
-            ],
-            global_speed: [
-                common.global.speed_factor,
-                common.global.water_speed_factor,
-                common.global.air_speed_factor,
-                common.global.underground_speed_factor,
-            ],
-            global_mobility: [common.global.mobility_factor, 0.0, 0.0, 0.0],
-            car_rudder: [
-                common.car.rudder_step,
-                common.car.rudder_max,
-                common.car.rudder_k_decr,
-                0.0,
-            ],
-            car_traction: [common.car.traction_incr, common.car.traction_decr, 0.0, 0.0],
-            impulse_elastic: [
-                common.impulse.elastic_restriction,
-                common.impulse.elastic_time_scale_factor,
-                0.0,
-                0.0,
-            ],
-            impulse_factors: [
-                common.impulse.factors[0],
-                common.impulse.factors[1],
-                0.0,
-                0.0,
-            ],
-            impulse: [
-                common.impulse.rolling_scale,
-                common.impulse.normal_threshold,
-                common.impulse.k_wheel,
-                common.impulse.k_friction,
-            ],
-            drag: DragConstants {
-                free: common.drag.free.to_array(),
-                speed: common.drag.speed.to_array(),
-                spring: common.drag.spring.to_array(),
-                abs_min: common.drag.abs_min.to_array(),
-                abs_stop: common.drag.abs_stop.to_array(),
-                coll: common.drag.coll.to_array(),
-                other: [common.drag.wheel_speed, common.drag.z],
-                _pad: [0.0; 2],
-            },
-            contact_elastic: [
-                common.contact.k_elastic_wheel,
-                common.contact.k_elastic_spring,
-                common.contact.k_elastic_xy,
-                common.contact.k_elastic_db_coll,
-            ],
-            force: [common.force.k_distance_to_force, 0.0, 0.0, 0.0],
-        };
+        let gravity = cgmath::vec3(0.0, 0.0, -common.nature.gravity);
+        let constants = Constants::new(common, gravity);
         let buf_constants = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("body-constants"),
             contents: bytemuck::bytes_of(&constants),
-            usage: wgpu::BufferUsage::UNIFORM,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -533,7 +752,9 @@ impl GpuStore {
             buf_uniforms,
             buf_ranges,
             buf_pushes,
+            buf_constants,
             capacity: init.capacity,
+            gravity_override: None,
             bind_group,
             bind_group_gather,
             bind_group_push,
@@ -541,10 +762,15 @@ impl GpuStore {
             updates: Vec::new(),
             update_data: Vec::new(),
             update_control: Vec::new(),
+            update_masks: Vec::new(),
+            update_resets: Vec::new(),
+            spawns: Vec::new(),
             pending_pushes: Vec::with_capacity(WORK_GROUP_WIDTH as usize),
             gpu_result: None,
             cpu_mirror: Arc::new(Mutex::new(GpuStoreMirror {
                 transforms: Vec::new(),
+                velocities: Vec::new(),
+                angular_velocities: Vec::new(),
             })),
         }
     }
@@ -559,12 +785,70 @@ impl GpuStore {
         self.update_control.push(control);
     }
 
+    pub fn set_collision_mask(&mut self, body: &GpuBody, mask: u32) {
+        self.updates.push((
+            body.index(),
+            Update::SetCollisionMask {
+                index: self.update_masks.len(),
+            },
+        ));
+        self.update_masks.push(mask);
+    }
+
+    // Moves the body back to its transform at `alloc`, with zero velocities.
+    pub fn reset(&mut self, body: &GpuBody) {
+        self.updates.push((
+            body.index(),
+            Update::Reset {
+                index: self.update_resets.len(),
+            },
+        ));
+        self.update_resets.push(self.spawns[body.index()]);
+    }
+
+    pub fn set_active(&mut self, body: &GpuBody, active: bool) {
+        self.updates
+            .push((body.index(), Update::SetActive { active }));
+    }
+
     pub fn add_push(&mut self, body: &GpuBody, vec: cgmath::Vector3<f32>) {
         self.pending_pushes.push(GpuPush {
             dir_id: [vec.x, vec.y, vec.z, body.index() as f32],
         });
     }
 
+    /// Overrides the gravity vector, which otherwise points down
+    /// with the magnitude of `common.nature.gravity`.
+    /// Passing `None` goes back to the default.
+    /// Takes effect on the next `update_constants`.
+    pub fn set_gravity(&mut self, gravity: Option<cgmath::Vector3<f32>>) {
+        self.gravity_override = gravity;
+    }
+
+    pub fn update_constants(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        common: &Common,
+    ) {
+        let gravity = self
+            .gravity_override
+            .unwrap_or_else(|| cgmath::vec3(0.0, 0.0, -common.nature.gravity));
+        let constants = Constants::new(common, gravity);
+        let temp = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("temp-constants"),
+            contents: bytemuck::bytes_of(&constants),
+            usage: wgpu::BufferUsage::COPY_SRC,
+        });
+        encoder.copy_buffer_to_buffer(
+            &temp,
+            0,
+            &self.buf_constants,
+            0,
+            mem::size_of::<Constants>() as wgpu::BufferAddress,
+        );
+    }
+
     pub fn reload(&mut self, device: &wgpu::Device) {
         self.pipelines = Pipelines::new(
             &self.pipeline_layout_step,
@@ -579,9 +863,17 @@ impl GpuStore {
         transform: &Transform,
         model: &VisualModel,
         car_physics: &CarPhysics,
-    ) -> GpuBody {
-        let id = self.free_list.alloc();
-        assert!(id.index() < self.capacity);
+    ) -> Result<GpuBody, AllocError> {
+        let wheels = pack_wheels(&model.wheels)?;
+        let id = match self.free_list.alloc_within(self.capacity) {
+            Some(id) => id,
+            None => {
+                return Err(AllocError::Capacity {
+                    requested: self.free_list.length() + 1,
+                    max: self.capacity,
+                })
+            }
+        };
 
         let matrix = cgmath::Matrix3::from(model.body.physics.jacobi)
             .invert()
@@ -589,14 +881,6 @@ impl GpuStore {
         let ji: &[f32; 9] = matrix.as_ref();
 
         let gt = GpuTransform::new(transform);
-        let mut wheels = [[0.0; 4]; MAX_WHEELS];
-        for (wo, wi) in wheels.iter_mut().zip(model.wheels.iter()) {
-            //TODO: take X bounds like the original did?
-            wo[0] = wi.pos[0];
-            wo[1] = wi.pos[1];
-            wo[2] = wi.pos[2];
-            wo[3] = if wi.steer != 0 { 1.0 } else { -1.0 };
-        }
         let data = Data {
             control: [0.0, 0.0, 1.0, 0.0],
             engine: [0.0; 4],
@@ -631,8 +915,23 @@ impl GpuStore {
                 ],
             },
             wheels,
+            flags: [!0, 1, 0, 0],
         };
 
+        let spawn = GpuReset {
+            engine: data.engine,
+            pos_scale: data.pos_scale,
+            orientation: data.orientation,
+            linear: data.linear,
+            angular: data.angular,
+            collision: data.collision,
+        };
+        if self.spawns.len() <= id.index() {
+            self.spawns.resize(id.index() + 1, spawn);
+        } else {
+            self.spawns[id.index()] = spawn;
+        }
+
         self.updates.push((
             id.index(),
             Update::InitData {
@@ -640,13 +939,66 @@ impl GpuStore {
             },
         ));
         self.update_data.push(data);
-        id
+        Ok(id)
     }
 
     pub fn free(&mut self, id: GpuBody) {
+        // the slot keeps its data, so it has to stop being simulated and collided with
+        self.updates
+            .push((id.index(), Update::SetActive { active: false }));
         self.free_list.free(id);
     }
 
+    /// Number of live bodies.
+    pub fn len(&self) -> usize {
+        self.free_list.live_count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Lays out `count` bodies on a square grid around `base`, resting on the terrain.
+    // Stops at the capacity, returning the bodies allocated so far.
+    pub fn alloc_grid(
+        &mut self,
+        count: usize,
+        spacing: f32,
+        base: &Transform,
+        level: &Level,
+        model: &VisualModel,
+        car_physics: &CarPhysics,
+    ) -> Vec<GpuBody> {
+        const CENTER_OFFSET: f32 = 5.0;
+        let side = (count as f32).sqrt().ceil() as usize;
+        let half = 0.5 * (side.max(1) - 1) as f32;
+        let mut bodies = Vec::with_capacity(count);
+        for i in 0..count {
+            let x = base.disp.x + ((i % side) as f32 - half) * spacing;
+            let y = base.disp.y + ((i / side) as f32 - half) * spacing;
+            let transform = Transform {
+                disp: cgmath::vec3(x, y, level.sample_height(x, y) + CENTER_OFFSET),
+                ..*base
+            };
+            match self.alloc(&transform, model, car_physics) {
+                Ok(body) => bodies.push(body),
+                Err(e) => {
+                    log::warn!("Spawned {} out of {} bodies: {:?}", bodies.len(), count, e);
+                    break;
+                }
+            }
+        }
+        bodies
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.capacity
+    }
+
     pub fn update_entries(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
         let buf_init_data = if self.update_data.is_empty() {
             None
@@ -670,6 +1022,43 @@ impl GpuStore {
             self.update_control.clear();
             Some(buf)
         };
+        let buf_set_mask = if self.update_masks.is_empty() {
+            None
+        } else {
+            let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("temp-mask"),
+                contents: bytemuck::cast_slice(&self.update_masks),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            self.update_masks.clear();
+            Some(buf)
+        };
+        let buf_reset = if self.update_resets.is_empty() {
+            None
+        } else {
+            let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("temp-reset"),
+                contents: bytemuck::cast_slice(&self.update_resets),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            self.update_resets.clear();
+            Some(buf)
+        };
+
+        let need_active = self
+            .updates
+            .iter()
+            .any(|&(_, ref u)| matches!(*u, Update::SetActive { .. }));
+        let buf_active = if need_active {
+            let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("temp-active"),
+                contents: bytemuck::cast_slice(&[0u32, 1]),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            Some(buf)
+        } else {
+            None
+        };
 
         for (body_id, update) in self.updates.drain(..) {
             let data_size = mem::size_of::<Data>();
@@ -693,6 +1082,39 @@ impl GpuStore {
                         size as wgpu::BufferAddress,
                     );
                 }
+                Update::SetCollisionMask { index } => {
+                    let size = mem::size_of::<u32>();
+                    let offset = data_size - mem::size_of::<[u32; 4]>(); // flags
+                    encoder.copy_buffer_to_buffer(
+                        buf_set_mask.as_ref().unwrap(),
+                        (index * size) as wgpu::BufferAddress,
+                        &self.buf_data,
+                        (body_id * data_size + offset) as wgpu::BufferAddress,
+                        size as wgpu::BufferAddress,
+                    );
+                }
+                Update::Reset { index } => {
+                    let size = mem::size_of::<GpuReset>();
+                    encoder.copy_buffer_to_buffer(
+                        buf_reset.as_ref().unwrap(),
+                        (index * size) as wgpu::BufferAddress,
+                        &self.buf_data,
+                        (body_id * data_size + mem::size_of::<GpuControl>()) as wgpu::BufferAddress,
+                        size as wgpu::BufferAddress,
+                    );
+                }
+                Update::SetActive { active } => {
+                    let size = mem::size_of::<u32>();
+                    // the flag lives in `flags.y`
+                    let offset = data_size - mem::size_of::<[u32; 4]>() + size;
+                    encoder.copy_buffer_to_buffer(
+                        buf_active.as_ref().unwrap(),
+                        (active as usize * size) as wgpu::BufferAddress,
+                        &self.buf_data,
+                        (body_id * data_size + offset) as wgpu::BufferAddress,
+                        size as wgpu::BufferAddress,
+                    );
+                }
             }
         }
     }
@@ -781,6 +1203,11 @@ impl GpuStore {
             pass.set_bind_group(1, &self.bind_group_push, &[]);
             pass.dispatch(1, 1, 1);
         }
+        #[cfg(feature = "body_collision")]
+        {
+            pass.set_pipeline(&self.pipelines.collide);
+            pass.dispatch(num_groups, 1, 1);
+        }
         pass.set_pipeline(&self.pipelines.step);
         pass.dispatch(num_groups, 1, 1);
 
@@ -798,7 +1225,7 @@ impl GpuStore {
         let count = self.free_list.length();
         let buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Gpu Results"),
-            size: (count * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress,
+            size: (count * mem::size_of::<GpuMotion>()) as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
             mapped_at_creation: false,
         });
@@ -809,8 +1236,8 @@ impl GpuStore {
                 &self.buf_data,
                 (i * mem::size_of::<Data>() + offset) as wgpu::BufferAddress,
                 &buffer,
-                (i * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress,
-                mem::size_of::<GpuTransform>() as wgpu::BufferAddress,
+                (i * mem::size_of::<GpuMotion>()) as wgpu::BufferAddress,
+                mem::size_of::<GpuMotion>() as wgpu::BufferAddress,
             );
         }
 
@@ -824,34 +1251,113 @@ impl GpuStore {
         };
 
         let latest = Arc::clone(&self.cpu_mirror);
-        let end = (count * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress;
+        let end = (count * mem::size_of::<GpuMotion>()) as wgpu::BufferAddress;
         let future = buffer
             .slice(..end)
             .map_async(wgpu::MapMode::Read)
             .map(move |_| {
                 let mapping = buffer.slice(..end).get_mapped_range();
-                let data = unsafe {
-                    slice::from_raw_parts(*mapping.as_ptr() as *const GpuTransform, count)
-                };
-
-                let transforms = data.iter().map(|gt| Transform {
-                    disp: cgmath::vec3(gt.pos_scale[0], gt.pos_scale[1], gt.pos_scale[2]),
-                    rot: cgmath::Quaternion::new(
-                        gt.orientation[3],
-                        gt.orientation[0],
-                        gt.orientation[1],
-                        gt.orientation[2],
-                    ),
-                    scale: gt.pos_scale[3],
-                });
+                let data =
+                    unsafe { slice::from_raw_parts(*mapping.as_ptr() as *const GpuMotion, count) };
 
                 let mut storage = latest.lock().unwrap();
                 storage.transforms.clear();
-                storage.transforms.extend(transforms);
+                storage.velocities.clear();
+                storage.angular_velocities.clear();
+                for gm in data {
+                    let rot = cgmath::Quaternion::new(
+                        gm.orientation[3],
+                        gm.orientation[0],
+                        gm.orientation[1],
+                        gm.orientation[2],
+                    );
+                    // the simulation keeps the velocity in the local space
+                    let linear = cgmath::vec3(gm.linear[0], gm.linear[1], gm.linear[2]);
+                    let angular = cgmath::vec3(gm.angular[0], gm.angular[1], gm.angular[2]);
+                    storage.velocities.push(rot * linear);
+                    storage.angular_velocities.push(rot * angular);
+                    storage.transforms.push(Transform {
+                        disp: cgmath::vec3(gm.pos_scale[0], gm.pos_scale[1], gm.pos_scale[2]),
+                        rot,
+                        scale: gm.pos_scale[3],
+                    });
+                }
             });
         spawner.spawn_local_obj(Box::new(future).into()).unwrap();
     }
 
+    // Records the copy of all the body data, after applying the pending updates.
+    pub fn snapshot(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> PendingSnapshot {
+        self.update_entries(device, encoder);
+        let count = self.free_list.length();
+        let buffer = if count == 0 {
+            None
+        } else {
+            let size = (count * mem::size_of::<Data>()) as wgpu::BufferAddress;
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Snapshot"),
+                size,
+                usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+                mapped_at_creation: false,
+            });
+            encoder.copy_buffer_to_buffer(&self.buf_data, 0, &buffer, 0, size);
+            Some(buffer)
+        };
+
+        PendingSnapshot {
+            buffer,
+            free_list: self.free_list.clone(),
+            spawns: self.spawns.clone(),
+        }
+    }
+
+    pub fn restore(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        snapshot: &SimSnapshot,
+    ) {
+        assert_eq!(snapshot.free_list.length(), snapshot.data.len());
+        assert!(snapshot.data.len() <= self.capacity);
+
+        self.updates.clear();
+        self.update_data.clear();
+        self.update_control.clear();
+        self.update_masks.clear();
+        self.update_resets.clear();
+        self.pending_pushes.clear();
+        self.gpu_result = None;
+        // deactivate the slots past the snapshot, which would otherwise
+        // keep being stepped and collided with
+        let old_count = self.free_list.length();
+        for index in snapshot.data.len()..old_count {
+            self.updates
+                .push((index, Update::SetActive { active: false }));
+        }
+        self.free_list = snapshot.free_list.clone();
+        self.spawns = snapshot.spawns.clone();
+
+        if !snapshot.data.is_empty() {
+            let temp = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("temp-restore"),
+                contents: bytemuck::cast_slice(&snapshot.data),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            encoder.copy_buffer_to_buffer(
+                &temp,
+                0,
+                &self.buf_data,
+                0,
+                (snapshot.data.len() * mem::size_of::<Data>()) as wgpu::BufferAddress,
+            );
+        }
+        self.update_entries(device, encoder);
+    }
+
     pub fn cpu_mirror(&self) -> MutexGuard<GpuStoreMirror> {
         self.cpu_mirror.lock().unwrap()
     }
@@ -0,0 +1,142 @@
+use futures::{executor::LocalSpawner, task::LocalSpawn as _, FutureExt};
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+pub struct Capture {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    extent: wgpu::Extent3d,
+}
+
+pub struct PendingCapture {
+    buffer: wgpu::Buffer,
+    extent: wgpu::Extent3d,
+    bytes_per_row: u32,
+}
+
+impl Capture {
+    pub fn new(device: &wgpu::Device, extent: wgpu::Extent3d, format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Capture {
+            texture,
+            view,
+            extent,
+        }
+    }
+
+    // Records the copy into a readable buffer. The result has to be
+    // consumed after the encoder is submitted.
+    pub fn read(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+    ) -> PendingCapture {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (self.extent.width * 4 + align - 1) / align * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture"),
+            size: (bytes_per_row * self.extent.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row,
+                    rows_per_image: 0,
+                },
+            },
+            self.extent,
+        );
+        PendingCapture {
+            buffer,
+            extent: self.extent,
+            bytes_per_row,
+        }
+    }
+}
+
+pub fn write_png(path: &Path, extent: wgpu::Extent3d, rgba: &[u8]) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, extent.width, extent.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(rgba)
+        .unwrap();
+}
+
+impl PendingCapture {
+    // Strips the row padding, optionally converting from BGRA to RGBA.
+    fn unpad(&self, to_rgba: bool) -> Vec<u8> {
+        let mapping = self.buffer.slice(..).get_mapped_range();
+        let mut data = Vec::with_capacity((self.extent.width * self.extent.height * 4) as usize);
+        for row in mapping.chunks(self.bytes_per_row as usize) {
+            let row = &row[..self.extent.width as usize * 4];
+            if to_rgba {
+                for bgra in row.chunks(4) {
+                    data.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+                }
+            } else {
+                data.extend_from_slice(row);
+            }
+        }
+        data
+    }
+
+    fn to_rgba(&self) -> Vec<u8> {
+        self.unpad(true)
+    }
+
+    pub fn save(self, path: PathBuf, spawner: &LocalSpawner) {
+        let future = self
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read)
+            .map(move |_| {
+                write_png(&path, self.extent, &self.to_rgba());
+                info!("Saved capture to {:?}", path);
+            });
+        spawner.spawn_local_obj(Box::new(future).into()).unwrap();
+    }
+
+    fn map_blocking(&self, device: &wgpu::Device) {
+        let future = self.buffer.slice(..).map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(future).unwrap();
+    }
+
+    // Blocks until the copy is done, returns RGBA pixels.
+    pub fn wait(self, device: &wgpu::Device) -> Vec<u8> {
+        self.map_blocking(device);
+        self.to_rgba()
+    }
+
+    // Blocks until the copy is done, returns the pixels in the target order.
+    pub fn wait_raw(self, device: &wgpu::Device) -> Vec<u8> {
+        self.map_blocking(device);
+        self.unpad(false)
+    }
+}
@@ -371,7 +371,7 @@ impl GpuCollider {
         });
         pass.set_pipeline(&self.pipeline);
         pass.set_bind_group(0, &self.bind_group, &[]);
-        pass.set_bind_group(1, &terrain.bind_group, &[]);
+        pass.set_bind_group(1, &terrain.collision_bind_group, &[]);
 
         self.epoch.0 += 1;
         for r in self.ranges.iter_mut() {
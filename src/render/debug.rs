@@ -2,9 +2,10 @@ use crate::{
     config::settings,
     model,
     render::{
+        body::GpuStoreMirror,
         global::Context as GlobalContext,
-        object::{Context as ObjectContext, Instance as ObjectInstance, InstanceDesc},
-        Shaders, ShapeVertexDesc, COLOR_FORMAT, DEPTH_FORMAT,
+        object::{Context as ObjectContext, InstanceDesc},
+        Shaders,
     },
 };
 
@@ -19,6 +20,8 @@ const BLEND_BEHIND: wgpu::BlendDescriptor = wgpu::BlendDescriptor {
     dst_factor: wgpu::BlendFactor::OneMinusBlendColor,
     operation: wgpu::BlendOperation::Add,
 };
+// length of the velocity line per unit of speed
+const VELOCITY_SCALE: f32 = 0.5;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 enum Visibility {
@@ -74,6 +77,10 @@ impl LineBuffer {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.vertices.clear();
         self.colors.clear();
@@ -90,12 +97,109 @@ impl LineBuffer {
         self.colors.push(color);
         self.colors.push(color);
     }
+
+    // `right` and `up` are the extents of a single font grid cell
+    pub fn add_text(
+        &mut self,
+        text: &str,
+        origin: [f32; 3],
+        right: [f32; 3],
+        up: [f32; 3],
+        color: u32,
+    ) {
+        let point = |x: f32, y: f32| {
+            [
+                origin[0] + x * right[0] + y * up[0],
+                origin[1] + x * right[1] + y * up[1],
+                origin[2] + x * right[2] + y * up[2],
+            ]
+        };
+        let (mut cx, mut cy) = (0.0, 0.0);
+        for c in text.chars() {
+            if c == '\n' {
+                cx = 0.0;
+                cy -= GLYPH_LINE_HEIGHT;
+                continue;
+            }
+            for stroke in glyph(c.to_ascii_uppercase()) {
+                for pair in stroke.windows(2) {
+                    let (x0, y0) = pair[0];
+                    let (x1, y1) = pair[1];
+                    self.add(
+                        point(cx + x0 as f32, cy + y0 as f32),
+                        point(cx + x1 as f32, cy + y1 as f32),
+                        color,
+                    );
+                }
+            }
+            cx += GLYPH_ADVANCE;
+        }
+    }
+}
+
+const GLYPH_ADVANCE: f32 = 6.0;
+const GLYPH_LINE_HEIGHT: f32 = 12.0;
+
+// Stroke font on a 4x8 grid, with the origin at the bottom left.
+fn glyph(c: char) -> &'static [&'static [(u8, u8)]] {
+    match c {
+        'A' => &[&[(0, 0), (0, 6), (2, 8), (4, 6), (4, 0)], &[(0, 4), (4, 4)]],
+        'B' => &[
+            &[(0, 0), (0, 8), (3, 8), (4, 7), (4, 5), (3, 4), (0, 4)],
+            &[(3, 4), (4, 3), (4, 1), (3, 0), (0, 0)],
+        ],
+        'C' => &[&[(4, 8), (0, 8), (0, 0), (4, 0)]],
+        'D' => &[&[(0, 0), (0, 8), (2, 8), (4, 6), (4, 2), (2, 0), (0, 0)]],
+        'E' => &[&[(4, 8), (0, 8), (0, 0), (4, 0)], &[(0, 4), (3, 4)]],
+        'F' => &[&[(4, 8), (0, 8), (0, 0)], &[(0, 4), (3, 4)]],
+        'G' => &[&[(4, 8), (0, 8), (0, 0), (4, 0), (4, 4), (2, 4)]],
+        'H' => &[&[(0, 0), (0, 8)], &[(4, 0), (4, 8)], &[(0, 4), (4, 4)]],
+        'I' => &[&[(0, 8), (4, 8)], &[(2, 8), (2, 0)], &[(0, 0), (4, 0)]],
+        'J' => &[&[(4, 8), (4, 0), (0, 0), (0, 2)]],
+        'K' => &[&[(0, 0), (0, 8)], &[(4, 8), (0, 4), (4, 0)]],
+        'L' => &[&[(0, 8), (0, 0), (4, 0)]],
+        'M' => &[&[(0, 0), (0, 8), (2, 4), (4, 8), (4, 0)]],
+        'N' => &[&[(0, 0), (0, 8), (4, 0), (4, 8)]],
+        'O' => &[&[(0, 0), (0, 8), (4, 8), (4, 0), (0, 0)]],
+        'P' => &[&[(0, 0), (0, 8), (4, 8), (4, 4), (0, 4)]],
+        'Q' => &[&[(0, 0), (0, 8), (4, 8), (4, 0), (0, 0)], &[(2, 2), (4, 0)]],
+        'R' => &[&[(0, 0), (0, 8), (4, 8), (4, 4), (0, 4), (4, 0)]],
+        'S' | '5' => &[&[(4, 8), (0, 8), (0, 4), (4, 4), (4, 0), (0, 0)]],
+        'T' => &[&[(0, 8), (4, 8)], &[(2, 8), (2, 0)]],
+        'U' => &[&[(0, 8), (0, 0), (4, 0), (4, 8)]],
+        'V' => &[&[(0, 8), (2, 0), (4, 8)]],
+        'W' => &[&[(0, 8), (0, 0), (2, 4), (4, 0), (4, 8)]],
+        'X' => &[&[(0, 0), (4, 8)], &[(0, 8), (4, 0)]],
+        'Y' => &[&[(0, 8), (2, 4), (4, 8)], &[(2, 4), (2, 0)]],
+        'Z' => &[&[(0, 8), (4, 8), (0, 0), (4, 0)]],
+        '0' => &[&[(0, 0), (0, 8), (4, 8), (4, 0), (0, 0), (4, 8)]],
+        '1' => &[&[(1, 7), (2, 8), (2, 0)], &[(0, 0), (4, 0)]],
+        '2' => &[&[(0, 8), (4, 8), (4, 4), (0, 4), (0, 0), (4, 0)]],
+        '3' => &[&[(0, 8), (4, 8), (4, 0), (0, 0)], &[(0, 4), (4, 4)]],
+        '4' => &[&[(0, 8), (0, 4), (4, 4)], &[(4, 8), (4, 0)]],
+        '6' => &[&[(4, 8), (0, 8), (0, 0), (4, 0), (4, 4), (0, 4)]],
+        '7' => &[&[(0, 8), (4, 8), (2, 0)]],
+        '8' => &[&[(0, 0), (0, 8), (4, 8), (4, 0), (0, 0)], &[(0, 4), (4, 4)]],
+        '9' => &[&[(4, 0), (4, 8), (0, 8), (0, 4), (4, 4)]],
+        '.' | ',' => &[&[(2, 0), (2, 1)]],
+        ':' => &[&[(2, 1), (2, 2)], &[(2, 5), (2, 6)]],
+        '-' => &[&[(0, 4), (4, 4)]],
+        '+' => &[&[(0, 4), (4, 4)], &[(2, 2), (2, 6)]],
+        '/' => &[&[(0, 0), (4, 8)]],
+        '_' => &[&[(0, 0), (4, 0)]],
+        _ => &[],
+    }
 }
 
 pub struct Context {
     settings: settings::DebugRender,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
     pipeline_layout: wgpu::PipelineLayout,
+    line_pipeline_layout: wgpu::PipelineLayout,
     pipelines_line: HashMap<Selector, wgpu::RenderPipeline>,
+    pipeline_text: Option<wgpu::RenderPipeline>,
     pipeline_face: Option<wgpu::RenderPipeline>,
     pipeline_edge: Option<wgpu::RenderPipeline>,
     line_color_buf: wgpu::Buffer,
@@ -138,6 +242,11 @@ impl Context {
             ],
             push_constant_ranges: &[],
         });
+        let line_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("debug-line"),
+            bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
 
         let line_color_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("debug-line-color"),
@@ -193,8 +302,13 @@ impl Context {
 
         let mut result = Context {
             settings: settings.clone(),
+            color_format: global.color_format,
+            depth_format: global.depth_format,
+            sample_count: global.sample_count,
             pipeline_layout,
+            line_pipeline_layout,
             pipelines_line: HashMap::new(),
+            pipeline_text: None,
             pipeline_face: None,
             pipeline_edge: None,
             line_color_buf,
@@ -232,7 +346,7 @@ impl Context {
                 rasterization_state: Some(rasterization_state.clone()),
                 primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
                 color_states: &[wgpu::ColorStateDescriptor {
-                    format: COLOR_FORMAT,
+                    format: self.color_format,
                     alpha_blend: wgpu::BlendDescriptor {
                         src_factor: wgpu::BlendFactor::One,
                         dst_factor: wgpu::BlendFactor::One,
@@ -246,19 +360,16 @@ impl Context {
                     write_mask: wgpu::ColorWrite::all(),
                 }],
                 depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                    format: DEPTH_FORMAT,
+                    format: self.depth_format,
                     depth_write_enabled: false,
                     depth_compare: wgpu::CompareFunction::LessEqual,
                     stencil: Default::default(),
                 }),
                 vertex_state: wgpu::VertexStateDescriptor {
                     index_format: wgpu::IndexFormat::Uint16,
-                    vertex_buffers: &[
-                        ShapeVertexDesc::new().buffer_desc(),
-                        InstanceDesc::new().buffer_desc(),
-                    ],
+                    vertex_buffers: &[InstanceDesc::new().buffer_desc()],
                 },
-                sample_count: 1,
+                sample_count: self.sample_count,
                 alpha_to_coverage_enabled: false,
                 sample_mask: !0,
             });
@@ -267,7 +378,7 @@ impl Context {
         }
 
         self.pipelines_line.clear();
-        if self.settings.impulses {
+        if self.settings.impulses || self.settings.velocities || self.settings.grid_spacing > 0.0 {
             let shaders = Shaders::new("debug", &[], device).unwrap();
             for &visibility in &[Visibility::Front, Visibility::Behind] {
                 let (blend, depth_write_enabled, depth_compare) = match visibility {
@@ -278,7 +389,7 @@ impl Context {
                     let name = format!("debug-line-{:?}-{:?}", visibility, color_rate);
                     let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
                         label: Some(&name),
-                        layout: Some(&self.pipeline_layout),
+                        layout: Some(&self.line_pipeline_layout),
                         vertex_stage: wgpu::ProgrammableStageDescriptor {
                             module: &shaders.vs,
                             entry_point: "main",
@@ -288,15 +399,15 @@ impl Context {
                             entry_point: "main",
                         }),
                         rasterization_state: Some(rasterization_state.clone()),
-                        primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                        primitive_topology: wgpu::PrimitiveTopology::LineList,
                         color_states: &[wgpu::ColorStateDescriptor {
-                            format: COLOR_FORMAT,
+                            format: self.color_format,
                             alpha_blend: blend.clone(),
                             color_blend: blend.clone(),
                             write_mask: wgpu::ColorWrite::all(),
                         }],
                         depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                            format: DEPTH_FORMAT,
+                            format: self.depth_format,
                             depth_write_enabled,
                             depth_compare,
                             stencil: Default::default(),
@@ -324,7 +435,7 @@ impl Context {
                                 },
                             ],
                         },
-                        sample_count: 1,
+                        sample_count: self.sample_count,
                         alpha_to_coverage_enabled: false,
                         sample_mask: !0,
                     });
@@ -333,6 +444,63 @@ impl Context {
                 }
             }
         }
+
+        // text is an overlay, so it ignores the depth
+        let shaders = Shaders::new("debug", &[], device).unwrap();
+        self.pipeline_text = Some(
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("debug-text"),
+                layout: Some(&self.line_pipeline_layout),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &shaders.vs,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &shaders.fs,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(rasterization_state),
+                primitive_topology: wgpu::PrimitiveTopology::LineList,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: self.color_format,
+                    alpha_blend: BLEND_FRONT,
+                    color_blend: BLEND_FRONT,
+                    write_mask: wgpu::ColorWrite::all(),
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: self.depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: Default::default(),
+                }),
+                vertex_state: wgpu::VertexStateDescriptor {
+                    index_format: wgpu::IndexFormat::Uint16,
+                    vertex_buffers: &[
+                        wgpu::VertexBufferDescriptor {
+                            stride: mem::size_of::<Position>() as wgpu::BufferAddress,
+                            step_mode: wgpu::InputStepMode::Vertex,
+                            attributes: &[wgpu::VertexAttributeDescriptor {
+                                offset: 0,
+                                format: wgpu::VertexFormat::Float4,
+                                shader_location: 0,
+                            }],
+                        },
+                        wgpu::VertexBufferDescriptor {
+                            stride: mem::size_of::<Color>() as wgpu::BufferAddress,
+                            step_mode: wgpu::InputStepMode::Vertex,
+                            attributes: &[wgpu::VertexAttributeDescriptor {
+                                offset: 0,
+                                format: wgpu::VertexFormat::Uchar4Norm,
+                                shader_location: 1,
+                            }],
+                        },
+                    ],
+                },
+                sample_count: self.sample_count,
+                alpha_to_coverage_enabled: false,
+                sample_mask: !0,
+            }),
+        );
     }
 
     fn draw_liner<'a>(
@@ -354,41 +522,34 @@ impl Context {
         }
     }
 
+    // Draws `count` instances of the shape, expecting the object instances
+    // to be repeated for each polygon.
     pub fn draw_shape<'a>(
         &'a self,
         pass: &mut wgpu::RenderPass<'a>,
         shape: &'a model::Shape,
         instance_buf: &'a wgpu::Buffer,
-        instance_id: usize,
+        count: usize,
     ) {
         if !self.settings.collision_shapes {
             return;
         }
 
-        //TODO: this is broken - both regular rendering and debug one
-        // require instancing now, one has to yield and be refactored.
-        let instance_offset = instance_id * mem::size_of::<ObjectInstance>();
+        let num_instances = (shape.polygons.len() * count) as u32;
         pass.set_bind_group(2, &shape.bind_group, &[]);
-        pass.set_vertex_buffer(0, shape.polygon_buf.slice(..));
-        pass.set_vertex_buffer(
-            1,
-            instance_buf.slice(
-                instance_offset as wgpu::BufferAddress
-                    ..mem::size_of::<ObjectInstance>() as wgpu::BufferAddress,
-            ),
-        );
+        pass.set_vertex_buffer(0, instance_buf.slice(..));
 
         // draw collision polygon faces
         if let Some(ref pipeline) = self.pipeline_face {
             pass.set_pipeline(pipeline);
             pass.set_bind_group(1, &self.bind_group_face, &[]);
-            pass.draw(0..4, 0..shape.polygons.len() as u32);
+            pass.draw(0..4, 0..num_instances);
         }
         // draw collision polygon edges
         if let Some(ref pipeline) = self.pipeline_edge {
             pass.set_pipeline(pipeline);
             pass.set_bind_group(1, &self.bind_group_edge, &[]);
-            pass.draw(0..4, 0..shape.polygons.len() as u32);
+            pass.draw(0..4, 0..num_instances);
         }
 
         // draw sample normals
@@ -404,12 +565,7 @@ impl Context {
         }
     }
 
-    pub fn draw_lines<'a>(
-        &'a mut self,
-        pass: &mut wgpu::RenderPass<'a>,
-        device: &wgpu::Device,
-        linebuf: &LineBuffer,
-    ) {
+    fn upload(&mut self, device: &wgpu::Device, linebuf: &LineBuffer) {
         self.vertex_buf = Some(
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("debug-vertices"),
@@ -425,7 +581,16 @@ impl Context {
             }),
         );
         assert_eq!(linebuf.vertices.len(), linebuf.colors.len());
+    }
 
+    pub fn draw_lines<'a>(
+        &'a mut self,
+        pass: &mut wgpu::RenderPass<'a>,
+        device: &wgpu::Device,
+        linebuf: &LineBuffer,
+    ) {
+        self.upload(device, linebuf);
+        pass.set_bind_group(1, &self.bind_group_line, &[]);
         self.draw_liner(
             pass,
             self.vertex_buf.as_ref().unwrap(),
@@ -434,4 +599,51 @@ impl Context {
             linebuf.vertices.len(),
         );
     }
+
+    pub fn draw_velocity<'a>(
+        &'a mut self,
+        pass: &mut wgpu::RenderPass<'a>,
+        device: &wgpu::Device,
+        mirror: &GpuStoreMirror,
+    ) -> usize {
+        if !self.settings.velocities {
+            return 0;
+        }
+        let mut linebuf = LineBuffer::new();
+        for (transform, velocity) in mirror.motions() {
+            let from = transform.disp;
+            let to = from + velocity * VELOCITY_SCALE;
+            linebuf.add(from.into(), to.into(), 0x00FFFFFF);
+        }
+        if linebuf.vertices.is_empty() {
+            return 0;
+        }
+        self.upload(device, &linebuf);
+        pass.set_bind_group(1, &self.bind_group_line, &[]);
+        self.draw_liner(
+            pass,
+            self.vertex_buf.as_ref().unwrap(),
+            self.color_buf.as_ref().unwrap(),
+            wgpu::InputStepMode::Vertex,
+            linebuf.vertices.len(),
+        );
+        linebuf.vertices.len()
+    }
+
+    pub fn draw_text<'a>(
+        &'a mut self,
+        pass: &mut wgpu::RenderPass<'a>,
+        device: &wgpu::Device,
+        linebuf: &LineBuffer,
+    ) {
+        if linebuf.vertices.is_empty() {
+            return;
+        }
+        self.upload(device, linebuf);
+        pass.set_pipeline(self.pipeline_text.as_ref().unwrap());
+        pass.set_bind_group(1, &self.bind_group_line, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buf.as_ref().unwrap().slice(..));
+        pass.set_vertex_buffer(1, self.color_buf.as_ref().unwrap().slice(..));
+        pass.draw(0..linebuf.vertices.len() as u32, 0..1);
+    }
 }
@@ -1,7 +1,32 @@
-use crate::{config::settings, space::Camera};
+use crate::{config::settings, render::RenderFormats, space::Camera};
 use bytemuck::{Pod, Zeroable};
 use std::mem;
 
+// Has to match `MAX_LIGHTS` in "globals.inc.glsl".
+pub const MAX_LIGHTS: usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Light {
+    // W = 0 for a directional light, 1 for a point light
+    pub pos: [f32; 4],
+    pub color: [f32; 4],
+    // X = range of a point light, zero for unlimited
+    pub params: [f32; 4],
+}
+unsafe impl Pod for Light {}
+unsafe impl Zeroable for Light {}
+
+impl From<&settings::Light> for Light {
+    fn from(light: &settings::Light) -> Self {
+        Light {
+            pos: light.pos,
+            color: light.color,
+            params: [0.0; 4],
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Constants {
@@ -9,27 +34,79 @@ pub struct Constants {
     m_vp: [[f32; 4]; 4],
     m_inv_vp: [[f32; 4]; 4],
     m_light_vp: [[f32; 4]; 4],
-    light_pos: [f32; 4],
-    light_color: [f32; 4],
+    time: [f32; 4],
+    fog_color: [f32; 4],
+    fog_params: [f32; 4],
+    light_count: [u32; 4],
+    lights: [Light; MAX_LIGHTS],
 }
 unsafe impl Pod for Constants {}
 unsafe impl Zeroable for Constants {}
 
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+    pub total: f32,
+    pub delta: f32,
+}
+
+impl Time {
+    pub fn advance(&mut self, delta: f32) {
+        self.total += delta;
+        self.delta = delta;
+    }
+}
+
 impl Constants {
-    pub fn new(cam: &Camera, light: &settings::Light, shadow_cam: Option<&Camera>) -> Self {
+    // Mirrors the view across the horizontal plane at `height`,
+    // so that the scene is rendered as seen in the water.
+    pub fn with_reflection(self, cam: &Camera, height: f32) -> Self {
+        use cgmath::SquareMatrix;
+
+        let mx_vp = cam.get_reflected_view_proj(height);
+        let mut camera_pos = cam.loc;
+        camera_pos.z = 2.0 * height - camera_pos.z;
+        Constants {
+            camera_pos: camera_pos.extend(1.0).into(),
+            m_vp: mx_vp.into(),
+            m_inv_vp: mx_vp.invert().unwrap().into(),
+            ..self
+        }
+    }
+
+    // Adds the dynamic lights after the main one, dropping those that don't fit.
+    pub fn with_lights(mut self, lights: &[Light]) -> Self {
+        let start = self.light_count[0] as usize;
+        let count = lights.len().min(MAX_LIGHTS - start);
+        self.lights[start..start + count].copy_from_slice(&lights[..count]);
+        self.light_count[0] += count as u32;
+        self
+    }
+
+    pub fn new(
+        cam: &Camera,
+        light: &settings::Light,
+        shadow_cam: Option<&Camera>,
+        time: &Time,
+        fog: Option<&settings::Fog>,
+    ) -> Self {
         use cgmath::SquareMatrix;
 
         let m_light_vp = shadow_cam
             .map_or_else(cgmath::Matrix4::identity, |sc| sc.get_view_proj())
             .into();
         let mx_vp = cam.get_view_proj();
+        let mut lights = [Light::default(); MAX_LIGHTS];
+        lights[0] = Light::from(light);
         Constants {
             camera_pos: cam.loc.extend(1.0).into(),
             m_vp: mx_vp.into(),
             m_inv_vp: mx_vp.invert().unwrap().into(),
             m_light_vp,
-            light_pos: light.pos,
-            light_color: light.color,
+            time: [time.total, time.delta, 0.0, 0.0],
+            fog_color: fog.map_or([0.0; 4], |f| f.color),
+            fog_params: [fog.map_or(0.0, |f| f.density), 0.0, 0.0, 0.0],
+            light_count: [1, 0, 0, 0],
+            lights,
         }
     }
 }
@@ -39,6 +116,10 @@ pub struct Context {
     pub uniform_buf: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub shadow_bind_group: wgpu::BindGroup,
+    pub color_format: wgpu::TextureFormat,
+    pub depth_format: wgpu::TextureFormat,
+    // samples per pixel of the main pass targets
+    pub sample_count: u32,
 }
 
 impl Context {
@@ -47,6 +128,9 @@ impl Context {
         queue: &wgpu::Queue,
         store_buffer: wgpu::BindingResource,
         shadow_view: Option<&wgpu::TextureView>,
+        reflection_view: Option<&wgpu::TextureView>,
+        formats: RenderFormats,
+        sample_count: u32,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Global"),
@@ -96,6 +180,17 @@ impl Context {
                     ty: wgpu::BindingType::Sampler { comparison: true },
                     count: None,
                 },
+                // reflection texture
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
         let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
@@ -155,6 +250,38 @@ impl Context {
             queue.submit(Some(cmd_encoder.finish()));
             view
         };
+        // transparent, so that nothing gets reflected
+        let dummy_reflection_view = {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("DummyReflection"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: formats.color,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            let mut cmd_encoder = device.create_command_encoder(&Default::default());
+            cmd_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            queue.submit(Some(cmd_encoder.finish()));
+            view
+        };
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Global"),
@@ -182,6 +309,12 @@ impl Context {
                     binding: 4,
                     resource: wgpu::BindingResource::Sampler(&shadow_sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(
+                        reflection_view.unwrap_or(&dummy_reflection_view),
+                    ),
+                },
             ],
         });
         let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -208,6 +341,10 @@ impl Context {
                     binding: 4,
                     resource: wgpu::BindingResource::Sampler(&shadow_sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&dummy_reflection_view),
+                },
             ],
         });
 
@@ -216,6 +353,16 @@ impl Context {
             uniform_buf,
             bind_group,
             shadow_bind_group,
+            color_format: formats.color,
+            depth_format: formats.depth,
+            sample_count,
+        }
+    }
+
+    pub fn formats(&self) -> RenderFormats {
+        RenderFormats {
+            color: self.color_format,
+            depth: self.depth_format,
         }
     }
 }
@@ -1,5 +1,5 @@
 use crate::render::{
-    terrain::{Rect, HEIGHT_FORMAT},
+    terrain::{Rect, HEIGHT_FORMAT, META_FORMAT},
     Shaders,
 };
 use bytemuck::{Pod, Zeroable};
@@ -19,7 +19,32 @@ struct Mip {
     bind_group: wgpu::BindGroup,
 }
 
-pub struct MaxMipper {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reduction {
+    Max,
+    Min,
+    // Most common terrain type, for the meta texture.
+    Mode,
+}
+
+impl Reduction {
+    fn format(self) -> wgpu::TextureFormat {
+        match self {
+            Reduction::Max | Reduction::Min => HEIGHT_FORMAT,
+            Reduction::Mode => META_FORMAT,
+        }
+    }
+
+    fn component_type(self) -> wgpu::TextureComponentType {
+        match self {
+            Reduction::Max | Reduction::Min => wgpu::TextureComponentType::Float,
+            Reduction::Mode => wgpu::TextureComponentType::Uint,
+        }
+    }
+}
+
+pub struct Mipper {
+    reduction: Reduction,
     size: wgpu::Extent3d,
     pipeline_layout: wgpu::PipelineLayout,
     pipeline: wgpu::RenderPipeline,
@@ -27,12 +52,20 @@ pub struct MaxMipper {
     mips: Vec<Mip>,
 }
 
-impl MaxMipper {
+pub type MaxMipper = Mipper;
+
+impl Mipper {
     fn create_pipeline(
+        reduction: Reduction,
         layout: &wgpu::PipelineLayout,
         device: &wgpu::Device,
     ) -> wgpu::RenderPipeline {
-        let shaders = Shaders::new("terrain/mip", &[], device).unwrap();
+        let specialization: &[&str] = match reduction {
+            Reduction::Max => &[],
+            Reduction::Min => &["REDUCE_MIN"],
+            Reduction::Mode => &["REDUCE_MODE"],
+        };
+        let shaders = Shaders::new("terrain/mip", specialization, device).unwrap();
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("mipmap"),
             layout: Some(layout),
@@ -50,7 +83,7 @@ impl MaxMipper {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[HEIGHT_FORMAT.into()],
+            color_states: &[reduction.format().into()],
             depth_stencil_state: None,
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint16,
@@ -74,10 +107,11 @@ impl MaxMipper {
         texture: &wgpu::Texture,
         size: wgpu::Extent3d,
         mip_count: u32,
+        reduction: Reduction,
         device: &wgpu::Device,
     ) -> Self {
         let bg_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("MaxMipper"),
+            label: Some("Mipper"),
             entries: &[
                 // sampler
                 wgpu::BindGroupLayoutEntry {
@@ -92,7 +126,7 @@ impl MaxMipper {
                     visibility: wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::SampledTexture {
                         dimension: wgpu::TextureViewDimension::D2,
-                        component_type: wgpu::TextureComponentType::Float,
+                        component_type: reduction.component_type(),
                         multisampled: false,
                     },
                     count: None,
@@ -104,6 +138,7 @@ impl MaxMipper {
             bind_group_layouts: &[&bg_layout],
             push_constant_ranges: &[],
         });
+        // "mip.glsl" only fetches exact texels, so the sampler state is irrelevant
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
@@ -128,7 +163,7 @@ impl MaxMipper {
             });
 
             let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("MaxMipper"),
+                label: Some("Mipper"),
                 layout: &bg_layout,
                 entries: &[
                     wgpu::BindGroupEntry {
@@ -145,9 +180,10 @@ impl MaxMipper {
             mips.push(Mip { view, bind_group });
         }
 
-        let pipeline = Self::create_pipeline(&pipeline_layout, device);
+        let pipeline = Self::create_pipeline(reduction, &pipeline_layout, device);
 
-        MaxMipper {
+        Mipper {
+            reduction,
             size,
             pipeline_layout,
             pipeline,
@@ -155,29 +191,37 @@ impl MaxMipper {
         }
     }
 
+    fn level_size(&self, level: u32) -> (u32, u32) {
+        (
+            (self.size.width >> level).max(1),
+            (self.size.height >> level).max(1),
+        )
+    }
+
     pub fn update(
         &self,
         rects: &[Rect],
         encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
     ) {
-        let mut vertex_data = Vec::with_capacity(rects.len() * 6);
-        for r in rects.iter() {
-            let v_abs = [
-                (r.x, r.y),
-                (r.x + r.w, r.y),
-                (r.x, r.y + r.h),
-                (r.x, r.y + r.h),
-                (r.x + r.w, r.y),
-                (r.x + r.w, r.y + r.h),
-            ];
-            for &(x, y) in v_abs.iter() {
-                vertex_data.push(Vertex {
-                    _pos: [
-                        x as f32 / self.size.width as f32,
-                        y as f32 / self.size.height as f32,
-                    ],
-                });
+        // Every target level gets its own set of vertices, since the rectangles
+        // have to be scaled and normalized by the actual (clamped) level size.
+        let num_vertices = rects.len() * 6;
+        let mut vertex_data = Vec::with_capacity(num_vertices * (self.mips.len() - 1));
+        for level in 1..self.mips.len() as u32 {
+            let (width, height) = self.level_size(level);
+            let mask = (1u32 << level) - 1;
+            for r in rects.iter() {
+                let x0 = (r.x as u32 >> level).min(width);
+                let y0 = (r.y as u32 >> level).min(height);
+                let x1 = ((r.x as u32 + r.w as u32 + mask) >> level).min(width);
+                let y1 = ((r.y as u32 + r.h as u32 + mask) >> level).min(height);
+                let v_abs = [(x0, y0), (x1, y0), (x0, y1), (x0, y1), (x1, y0), (x1, y1)];
+                for &(x, y) in v_abs.iter() {
+                    vertex_data.push(Vertex {
+                        _pos: [x as f32 / width as f32, y as f32 / height as f32],
+                    });
+                }
             }
         }
         let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -186,13 +230,14 @@ impl MaxMipper {
             usage: wgpu::BufferUsage::VERTEX,
         });
 
+        // keep the contents outside of the rects intact
         for mip in 0..self.mips.len() - 1 {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
                     attachment: &self.mips[mip + 1].view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        load: wgpu::LoadOp::Load,
                         store: true,
                     },
                 }],
@@ -201,11 +246,12 @@ impl MaxMipper {
             pass.set_pipeline(&self.pipeline);
             pass.set_bind_group(0, &self.mips[mip].bind_group, &[]);
             pass.set_vertex_buffer(0, vertex_buf.slice(..));
-            pass.draw(0..rects.len() as u32 * 6, 0..1);
+            let base = (mip * num_vertices) as u32;
+            pass.draw(base..base + num_vertices as u32, 0..1);
         }
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
-        self.pipeline = Self::create_pipeline(&self.pipeline_layout, device);
+        self.pipeline = Self::create_pipeline(self.reduction, &self.pipeline_layout, device);
     }
 }
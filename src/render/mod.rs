@@ -5,7 +5,6 @@ use crate::{
 };
 
 use bytemuck::{Pod, Zeroable};
-use glsl_to_spirv;
 use wgpu::util::DeviceExt as _;
 
 use std::{
@@ -15,20 +14,86 @@ use std::{
     mem,
     path::PathBuf,
     sync::Arc,
+    time::Instant,
 };
 
+mod background;
 pub mod body;
+pub mod capture;
 pub mod collision;
 pub mod debug;
 pub mod global;
 pub mod mipmap;
+pub mod normal;
 pub mod object;
+mod reflection;
 mod shadow;
 pub mod terrain;
+pub mod thumbnail;
+pub mod trajectory;
 
 pub use shadow::FORMAT as SHADOW_FORMAT;
 pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+pub const COLOR_FORMAT_SRGB: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+const SHADER_CACHE_DIR: &str = "target/shader-cache";
+
+pub fn color_format(srgb: bool) -> wgpu::TextureFormat {
+    if srgb {
+        COLOR_FORMAT_SRGB
+    } else {
+        COLOR_FORMAT
+    }
+}
+
+pub fn is_srgb(format: wgpu::TextureFormat) -> bool {
+    match format {
+        wgpu::TextureFormat::Bgra8UnormSrgb | wgpu::TextureFormat::Rgba8UnormSrgb => true,
+        _ => false,
+    }
+}
+
+// Formats of the main pass targets, chosen by the application at start-up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderFormats {
+    pub color: wgpu::TextureFormat,
+    pub depth: wgpu::TextureFormat,
+}
+
+impl Default for RenderFormats {
+    fn default() -> Self {
+        RenderFormats {
+            color: COLOR_FORMAT,
+            depth: DEPTH_FORMAT,
+        }
+    }
+}
+
+impl RenderFormats {
+    pub fn new(srgb: bool) -> Self {
+        RenderFormats {
+            color: color_format(srgb),
+            ..Self::default()
+        }
+    }
+}
+
+// Converts an sRGB-encoded color (as written in the settings) into linear space.
+pub fn srgb_to_linear(color: [f32; 4]) -> [f32; 4] {
+    let convert = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [
+        convert(color[0]),
+        convert(color[1]),
+        convert(color[2]),
+        color[3],
+    ]
+}
 
 pub struct GpuTransform {
     pub pos_scale: [f32; 4],
@@ -58,6 +123,7 @@ pub struct SurfaceData {
 
 pub type ShapeVertex = [f32; 4];
 
+// Also read as 7 words per polygon by "debug_shape.glsl".
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct ShapePolygon {
@@ -103,6 +169,58 @@ impl Shaders {
         panic!("\nUnable to compile '{}': {}", name, msg);
     }
 
+    // Stable FNV-1a hash, unlike `DefaultHasher`, which may change between Rust versions.
+    fn hash_source(source: &str, kind: shaderc::ShaderKind) -> u64 {
+        let kind_name = format!("{:?}", kind);
+        source
+            .bytes()
+            .chain(kind_name.bytes())
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    fn is_valid_spirv(spv: &[u8]) -> bool {
+        const MAGIC: u32 = 0x0723_0203;
+        const HEADER_SIZE: usize = 5 * 4;
+        spv.len() >= HEADER_SIZE
+            && spv.len() % 4 == 0
+            && u32::from_ne_bytes([spv[0], spv[1], spv[2], spv[3]]) == MAGIC
+    }
+
+    // The reported line numbers refer to the generated source, as printed by `fail`.
+    // The results are cached on disk, keyed by the hash of the generated source,
+    // which includes the specialization defines, so any change to the includes
+    // or the specialization invalidates them.
+    fn compile(name: &str, source: &str, kind: shaderc::ShaderKind) -> Vec<u8> {
+        let cache_path = PathBuf::from(SHADER_CACHE_DIR)
+            .join(format!("{:016x}.spv", Self::hash_source(source, kind)));
+        match std::fs::read(&cache_path) {
+            Ok(ref spv) if !Self::is_valid_spirv(spv) => {
+                warn!("Ignoring invalid cached shader {:?}", cache_path);
+            }
+            Ok(spv) => return spv,
+            Err(_) => {}
+        }
+
+        let mut compiler = shaderc::Compiler::new().expect("Unable to create shaderc compiler");
+        let spv = match compiler.compile_into_spirv(source, kind, name, "main", None) {
+            Ok(artifact) => artifact.as_binary_u8().to_vec(),
+            Err(e) => Self::fail(name, source, &e.to_string()),
+        };
+        // write into a temporary file first, so that a concurrent
+        // reader never sees a partially written one
+        let temp_path = cache_path.with_extension(format!("spv.{}.tmp", std::process::id()));
+        if let Err(e) = std::fs::create_dir_all(SHADER_CACHE_DIR)
+            .and_then(|_| std::fs::write(&temp_path, &spv))
+            .and_then(|_| std::fs::rename(&temp_path, &cache_path))
+        {
+            warn!("Unable to cache shader '{}': {}", name, e);
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        spv
+    }
+
     pub fn new(
         name: &str,
         specialization: &[&str],
@@ -116,6 +234,8 @@ impl Shaders {
 
         let mut buf_vs = b"#version 450\n#define SHADER_VS\n".to_vec();
         let mut buf_fs = b"#version 450\n#define SHADER_FS\n".to_vec();
+        write!(buf_vs, "#define MAX_WHEELS {}\n", body::MAX_WHEELS)?;
+        write!(buf_fs, "#define MAX_WHEELS {}\n", body::MAX_WHEELS)?;
 
         let mut code = String::new();
         BufReader::new(File::open(&path)?).read_to_string(&mut code)?;
@@ -165,19 +285,8 @@ impl Shaders {
         debug!("vs:\n{}", str_vs);
         debug!("fs:\n{}", str_fs);
 
-        let (mut spv_vs, mut spv_fs) = (Vec::new(), Vec::new());
-        match glsl_to_spirv::compile(&str_vs, glsl_to_spirv::ShaderType::Vertex) {
-            Ok(mut file) => file.read_to_end(&mut spv_vs).unwrap(),
-            Err(ref e) => {
-                Self::fail(name, &str_vs, e);
-            }
-        };
-        match glsl_to_spirv::compile(&str_fs, glsl_to_spirv::ShaderType::Fragment) {
-            Ok(mut file) => file.read_to_end(&mut spv_fs).unwrap(),
-            Err(ref e) => {
-                Self::fail(name, &str_fs, e);
-            }
-        };
+        let spv_vs = Self::compile(name, &str_vs, shaderc::ShaderKind::Vertex);
+        let spv_fs = Self::compile(name, &str_fs, shaderc::ShaderKind::Fragment);
 
         Ok(Shaders {
             vs: device.create_shader_module(wgpu::util::make_spirv(&spv_vs)),
@@ -204,6 +313,7 @@ impl Shaders {
             group_size[0], group_size[1], group_size[2]
         )?;
         write!(buf, "#define SHADER_CS\n")?;
+        write!(buf, "#define MAX_WHEELS {}\n", body::MAX_WHEELS)?;
 
         let mut code = String::new();
         BufReader::new(File::open(&path)?).read_to_string(&mut code)?;
@@ -240,13 +350,7 @@ impl Shaders {
         let str_cs = String::from_utf8_lossy(&buf);
         debug!("cs:\n{}", str_cs);
 
-        let mut spv = Vec::new();
-        match glsl_to_spirv::compile(&str_cs, glsl_to_spirv::ShaderType::Compute) {
-            Ok(mut file) => file.read_to_end(&mut spv).unwrap(),
-            Err(ref e) => {
-                Self::fail(name, &str_cs, e);
-            }
-        };
+        let spv = Self::compile(name, &str_cs, shaderc::ShaderKind::Compute);
 
         Ok(device.create_shader_module(wgpu::util::make_spirv(&spv)))
     }
@@ -256,8 +360,23 @@ pub struct Palette {
     pub view: wgpu::TextureView,
 }
 
+// The palette entries are sRGB-encoded, so they need to be
+// linearized on sampling if the target encodes them back on write.
+pub fn palette_format(color_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    if is_srgb(color_format) {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    }
+}
+
 impl Palette {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, data: &[[u8; 4]]) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[[u8; 4]],
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
         let extent = wgpu::Extent3d {
             width: 0x100,
             height: 1,
@@ -269,7 +388,7 @@ impl Palette {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D1,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            format: palette_format(color_format),
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
 
@@ -300,35 +419,74 @@ struct InstanceArray {
     mesh: Arc<model::Mesh>,
     // actual hardware buffer for this data
     buffer: Option<wgpu::Buffer>,
+    // wheels and slots are drawn with a depth bias
+    biased: bool,
+    // the selected model gets an outline
+    outlined: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WheelPose {
+    pub spin: cgmath::Rad<f32>,
+    pub steer: cgmath::Rad<f32>,
+}
+
+// Models sharing the collision shape get their debug view in a single instanced draw.
+struct ShapeArray {
+    // repeated for each polygon of the shape, see "debug_shape.glsl"
+    data: Vec<object::Instance>,
+    shape: Arc<model::Shape>,
+    buffer: Option<wgpu::Buffer>,
 }
 
 pub struct Batcher {
-    instances: HashMap<*const model::Mesh, InstanceArray>,
-    debug_shapes: Vec<Arc<model::Shape>>,
-    debug_instances: Vec<object::Instance>,
+    instances: HashMap<(*const model::Mesh, bool), InstanceArray>,
+    debug_shapes: HashMap<*const model::Shape, ShapeArray>,
+    outline: bool,
+    palette: object::PaletteId,
 }
 
 impl Batcher {
     pub fn new() -> Self {
         Batcher {
             instances: HashMap::new(),
-            debug_shapes: Vec::new(),
-            debug_instances: Vec::new(),
+            debug_shapes: HashMap::new(),
+            outline: false,
+            palette: object::PaletteId::SHARED,
         }
     }
 
-    pub fn add_mesh(&mut self, mesh: &Arc<model::Mesh>, instance: object::Instance) {
+    fn add_instance(&mut self, mesh: &Arc<model::Mesh>, instance: object::Instance, biased: bool) {
+        let instance = instance.with_palette(self.palette);
+        let outlined = self.outline;
         self.instances
-            .entry(&**mesh)
+            .entry((&**mesh, outlined))
             .or_insert_with(|| InstanceArray {
                 data: Vec::new(),
                 mesh: Arc::clone(mesh),
                 buffer: None,
+                biased,
+                outlined,
             })
             .data
             .push(instance);
     }
 
+    // Applies to the models added after this call.
+    pub fn set_palette(&mut self, palette: object::PaletteId) {
+        self.palette = palette;
+    }
+
+    pub fn add_mesh(&mut self, mesh: &Arc<model::Mesh>, instance: object::Instance) {
+        self.add_instance(mesh, instance, false);
+    }
+
+    // Attachments touching the body are pulled towards the camera,
+    // so that they don't z-fight with it.
+    pub fn add_attachment(&mut self, mesh: &Arc<model::Mesh>, instance: object::Instance) {
+        self.add_instance(mesh, instance, true);
+    }
+
     pub fn add_model(
         &mut self,
         model: &model::VisualModel,
@@ -336,6 +494,47 @@ impl Batcher {
         debug_shape_scale: Option<f32>,
         gpu_body: &body::GpuBody,
         color: object::BodyColor,
+    ) {
+        self.add_model_posed(
+            model,
+            base_transform,
+            debug_shape_scale,
+            gpu_body,
+            color,
+            &WheelPose::default(),
+        )
+    }
+
+    // Same as `add_model_posed`, but the model is also drawn with an outline.
+    pub fn add_model_outlined(
+        &mut self,
+        model: &model::VisualModel,
+        base_transform: &Transform,
+        debug_shape_scale: Option<f32>,
+        gpu_body: &body::GpuBody,
+        color: object::BodyColor,
+        wheel_pose: &WheelPose,
+    ) {
+        self.outline = true;
+        self.add_model_posed(
+            model,
+            base_transform,
+            debug_shape_scale,
+            gpu_body,
+            color,
+            wheel_pose,
+        );
+        self.outline = false;
+    }
+
+    pub fn add_model_posed(
+        &mut self,
+        model: &model::VisualModel,
+        base_transform: &Transform,
+        debug_shape_scale: Option<f32>,
+        gpu_body: &body::GpuBody,
+        color: object::BodyColor,
+        wheel_pose: &WheelPose,
     ) {
         use cgmath::{One as _, Rotation3 as _, Transform as _};
 
@@ -345,24 +544,33 @@ impl Batcher {
             object::Instance::new(base_transform, 0.0, gpu_body, color),
         );
         if let Some(shape_scale) = debug_shape_scale {
-            self.debug_shapes.push(Arc::clone(&model.shape));
-            self.debug_instances.push(object::Instance::new(
-                base_transform,
-                shape_scale,
-                gpu_body,
-                color,
-            ));
+            let instance = object::Instance::new(base_transform, shape_scale, gpu_body, color);
+            self.debug_shapes
+                .entry(&*model.shape)
+                .or_insert_with(|| ShapeArray {
+                    data: Vec::new(),
+                    shape: Arc::clone(&model.shape),
+                    buffer: None,
+                })
+                .data
+                .extend(std::iter::repeat(instance).take(model.shape.polygons.len()));
         }
 
         // wheels
         for w in model.wheels.iter() {
             if let Some(ref mesh) = w.mesh {
+                let rot = if w.steer != 0 {
+                    cgmath::Quaternion::from_angle_z(wheel_pose.steer)
+                        * cgmath::Quaternion::from_angle_x(wheel_pose.spin)
+                } else {
+                    cgmath::Quaternion::one()
+                };
                 let transform = base_transform.concat(&Transform {
                     disp: mesh.offset.into(),
-                    rot: cgmath::Quaternion::one(),
+                    rot,
                     scale: 1.0,
                 });
-                self.add_mesh(
+                self.add_attachment(
                     mesh,
                     object::Instance::new(&transform, 0.0, gpu_body, color),
                 );
@@ -372,14 +580,8 @@ impl Batcher {
         // slots
         for s in model.slots.iter() {
             if let Some(ref mesh) = s.mesh {
-                let mut local = Transform {
-                    disp: cgmath::vec3(s.pos[0] as f32, s.pos[1] as f32, s.pos[2] as f32),
-                    rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(s.angle as f32)),
-                    scale: s.scale / base_transform.scale,
-                };
-                local.disp -= local.transform_vector(cgmath::Vector3::from(mesh.offset));
-                let transform = base_transform.concat(&local);
-                self.add_mesh(
+                let transform = model::slot_transform(base_transform, s, mesh);
+                self.add_attachment(
                     mesh,
                     object::Instance::new(&transform, 0.0, gpu_body, color),
                 );
@@ -387,6 +589,37 @@ impl Batcher {
         }
     }
 
+    // Debris pieces of the same kind share the mesh, so they end up
+    // in a single instanced draw call.
+    pub fn add_debrie(
+        &mut self,
+        mesh: &Arc<model::Mesh>,
+        transform: &Transform,
+        gpu_body: &body::GpuBody,
+        color: object::BodyColor,
+    ) {
+        self.add_mesh(mesh, object::Instance::new(transform, 0.0, gpu_body, color));
+    }
+
+    pub fn draw_call_count(&self) -> usize {
+        self.instances
+            .values()
+            .filter(|array| !array.data.is_empty())
+            .count()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instances.values().map(|array| array.data.len()).sum()
+    }
+
+    // Vertices processed by the indexed draws, including the repeated ones.
+    pub fn vertex_count(&self) -> usize {
+        self.instances
+            .values()
+            .map(|array| array.mesh.num_indices * array.data.len())
+            .sum()
+    }
+
     pub fn prepare(&mut self, device: &wgpu::Device) {
         for array in self.instances.values_mut() {
             if !array.data.is_empty() {
@@ -399,19 +632,73 @@ impl Batcher {
                 );
             }
         }
+        for array in self.debug_shapes.values_mut() {
+            if !array.data.is_empty() {
+                array.buffer = Some(
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("debug-instance"),
+                        contents: bytemuck::cast_slice(&array.data),
+                        usage: wgpu::BufferUsage::VERTEX,
+                    }),
+                );
+            }
+        }
     }
 
-    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
-        for array in self.instances.values() {
-            if array.data.is_empty() {
-                continue;
+    pub fn draw_debug_shapes<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        debug: &'a debug::Context,
+    ) {
+        for array in self.debug_shapes.values() {
+            if let Some(ref buffer) = array.buffer {
+                let count = array.data.len() / array.shape.polygons.len();
+                debug.draw_shape(pass, &array.shape, buffer, count);
+            }
+        }
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a PipelineSet,
+        kind: PipelineKind,
+    ) {
+        let (pipeline, pipeline_biased) = match kind {
+            PipelineKind::Main => (&pipelines.main, &pipelines.main_biased),
+            PipelineKind::Shadow => (&pipelines.shadow, &pipelines.shadow),
+        };
+        if kind == PipelineKind::Main {
+            pass.set_pipeline(&pipelines.outline);
+            for array in self.instances.values() {
+                if array.data.is_empty() || !array.outlined {
+                    continue;
+                }
+                pass.set_index_buffer(array.mesh.index_buf.slice(..));
+                pass.set_vertex_buffer(0, array.mesh.vertex_buf.slice(..));
+                pass.set_vertex_buffer(1, array.buffer.as_ref().unwrap().slice(..));
+                pass.draw_indexed(
+                    0..array.mesh.num_indices as u32,
+                    0,
+                    0..array.data.len() as u32,
+                );
+            }
+        }
+        for &(biased, pipeline) in &[(false, pipeline), (true, pipeline_biased)] {
+            pass.set_pipeline(pipeline);
+            for array in self.instances.values() {
+                if array.data.is_empty() || array.biased != biased {
+                    continue;
+                }
+                pass.set_index_buffer(array.mesh.index_buf.slice(..));
+                pass.set_vertex_buffer(0, array.mesh.vertex_buf.slice(..));
+                pass.set_vertex_buffer(1, array.buffer.as_ref().unwrap().slice(..));
+                pass.draw_indexed(
+                    0..array.mesh.num_indices as u32,
+                    0,
+                    0..array.data.len() as u32,
+                );
             }
-            pass.set_vertex_buffer(0, array.mesh.vertex_buf.slice(..));
-            pass.set_vertex_buffer(1, array.buffer.as_ref().unwrap().slice(..));
-            pass.draw(
-                0..array.mesh.num_vertices as u32,
-                0..array.data.len() as u32,
-            );
         }
     }
 
@@ -420,16 +707,22 @@ impl Batcher {
             array.data.clear();
             array.buffer = None;
         }
-        self.debug_shapes.clear();
-        self.debug_instances.clear();
+        for array in self.debug_shapes.values_mut() {
+            array.data.clear();
+            array.buffer = None;
+        }
+        self.palette = object::PaletteId::SHARED;
     }
 }
 
 pub struct PipelineSet {
     main: wgpu::RenderPipeline,
     shadow: wgpu::RenderPipeline,
+    main_biased: wgpu::RenderPipeline,
+    outline: wgpu::RenderPipeline,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PipelineKind {
     Main,
     Shadow,
@@ -444,18 +737,152 @@ impl PipelineSet {
     }
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct PipelineKey {
+    pub shader: &'static str,
+    pub specialization: &'static [&'static str],
+    pub color_format: Option<wgpu::TextureFormat>,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub topology: wgpu::PrimitiveTopology,
+    pub sample_count: u32,
+}
+
+// Shares pipelines between contexts with equivalent layouts,
+// so that switching the settings back and forth is instant.
+// Has to be cleared when the shaders are reloaded.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn get_or_create(
+        &mut self,
+        key: PipelineKey,
+        create: impl FnOnce() -> wgpu::RenderPipeline,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let pipeline = self
+            .pipelines
+            .entry(key)
+            .or_insert_with(|| Arc::new(create()));
+        Arc::clone(pipeline)
+    }
+
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+    }
+}
+
+// Work submitted by the last `Render::draw_world`, across all passes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub object_draws: usize,
+    pub terrain_draws: usize,
+    pub debug_draws: usize,
+    pub instances: usize,
+    pub vertices: usize,
+}
+
+impl RenderStats {
+    fn add_batch(&mut self, batcher: &Batcher) {
+        self.object_draws += batcher.draw_call_count();
+        self.instances += batcher.instance_count();
+        self.vertices += batcher.vertex_count();
+    }
+}
+
+// GPU time spent in the passes, in seconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuTimings {
+    pub shadow: f32,
+    pub terrain: f32,
+    pub objects: f32,
+    pub physics: f32,
+}
+
+// Render target that is only drawn into, possibly with several samples per pixel.
+fn create_target_view(
+    label: &str,
+    extent: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    device: &wgpu::Device,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: extent,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+// Main pass targets, used instead of the screen ones with multisampling.
+struct Multisampled {
+    color: wgpu::TextureView,
+    depth: wgpu::TextureView,
+}
+
+impl Multisampled {
+    fn new(
+        global: &global::Context,
+        extent: wgpu::Extent3d,
+        device: &wgpu::Device,
+    ) -> Option<Self> {
+        if global.sample_count <= 1 {
+            return None;
+        }
+        Some(Multisampled {
+            color: create_target_view(
+                "Multisampled color",
+                extent,
+                global.color_format,
+                global.sample_count,
+                device,
+            ),
+            depth: create_target_view(
+                "Multisampled depth",
+                extent,
+                global.depth_format,
+                global.sample_count,
+                device,
+            ),
+        })
+    }
+}
+
 pub struct Render {
     global: global::Context,
     pub object: object::Context,
     pub terrain: terrain::Context,
     pub debug: debug::Context,
     pub shadow: Option<shadow::Shadow>,
+    reflection: Option<reflection::Reflection>,
+    background: Option<background::Background>,
+    multisampled: Option<Multisampled>,
     pub light_config: settings::Light,
+    // skip drawing the objects outside of the camera frustum
+    pub frustum_culling: bool,
+    // draw the water surface over the terrain
+    pub water: bool,
+    // point lights on top of the main light, reset by `set_lights`
+    dynamic_lights: Vec<global::Light>,
     pub fog_config: settings::Fog,
+    terrain_config: settings::Terrain,
+    depth_prepass: bool,
+    pipeline_cache: PipelineCache,
     screen_size: wgpu::Extent3d,
+    time: global::Time,
+    last_frame: Instant,
+    stats: RenderStats,
 }
 
 impl Render {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -464,6 +891,7 @@ impl Render {
         settings: &settings::Render,
         screen_size: wgpu::Extent3d,
         store_buffer: wgpu::BindingResource,
+        formats: RenderFormats,
     ) -> Self {
         let shadow = if settings.light.shadow.size != 0 {
             Some(shadow::Shadow::new(&settings.light, device))
@@ -471,14 +899,31 @@ impl Render {
             None
         };
 
+        let sample_count = settings.msaa_samples.max(1);
+        let reflection = if settings.reflection_size != 0 {
+            Some(reflection::Reflection::new(
+                settings.reflection_size,
+                level,
+                formats,
+                sample_count,
+                device,
+            ))
+        } else {
+            None
+        };
+
         let global = global::Context::new(
             device,
             queue,
             store_buffer,
             shadow.as_ref().map(|shadow| &shadow.view),
+            reflection.as_ref().map(|reflection| &reflection.color_view),
+            formats,
+            sample_count,
         );
         let object = object::Context::new(device, queue, object_palette, &global);
-        let terrain = terrain::Context::new(
+        let mut pipeline_cache = PipelineCache::default();
+        let mut terrain = terrain::Context::new(
             device,
             queue,
             level,
@@ -486,8 +931,15 @@ impl Render {
             &settings.terrain,
             &settings.light.shadow.terrain,
             screen_size,
+            settings.depth_prepass,
+            &mut pipeline_cache,
         );
+        terrain.set_height_scale(settings.terrain_height_scale, queue);
         let debug = debug::Context::new(device, &settings.debug, &global, &object);
+        let fog_config = Self::linear_fog(settings, is_srgb(formats.color));
+        let background = Self::background_colors(settings, is_srgb(formats.color))
+            .map(|(zenith, horizon)| background::Background::new(zenith, horizon, &global, device));
+        let multisampled = Multisampled::new(&global, screen_size, device);
 
         Render {
             global,
@@ -495,12 +947,128 @@ impl Render {
             terrain,
             debug,
             shadow,
+            reflection,
+            background,
+            multisampled,
             light_config: settings.light.clone(),
-            fog_config: settings.fog.clone(),
+            frustum_culling: settings.frustum_culling,
+            water: settings.water,
+            dynamic_lights: Vec::new(),
+            fog_config,
+            terrain_config: settings.terrain.clone(),
+            depth_prepass: settings.depth_prepass,
+            pipeline_cache,
             screen_size,
+            time: global::Time::default(),
+            last_frame: Instant::now(),
+            stats: RenderStats::default(),
         }
     }
 
+    // Fog color is also used for clearing, so it has to be linear
+    // when rendering into an sRGB target.
+    fn linear_fog(settings: &settings::Render, srgb: bool) -> settings::Fog {
+        let mut fog = settings.fog.clone();
+        if srgb {
+            fog.color = srgb_to_linear(fog.color);
+        }
+        fog
+    }
+
+    fn background_colors(settings: &settings::Render, srgb: bool) -> Option<([f32; 4], [f32; 4])> {
+        match settings.background {
+            settings::Background::Fog => None,
+            settings::Background::Gradient { zenith, horizon } if srgb => {
+                Some((srgb_to_linear(zenith), srgb_to_linear(horizon)))
+            }
+            settings::Background::Gradient { zenith, horizon } => Some((zenith, horizon)),
+        }
+    }
+
+    pub fn apply_settings(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        level: &level::Level,
+        settings: &settings::Render,
+    ) {
+        info!("Applying render settings");
+        self.light_config = settings.light.clone();
+        self.frustum_culling = settings.frustum_culling;
+        self.water = settings.water;
+        let srgb = is_srgb(self.global.color_format);
+        self.fog_config = Self::linear_fog(settings, srgb);
+        if settings.srgb != srgb {
+            warn!("Color target change requires a restart");
+        }
+        if settings.msaa_samples.max(1) != self.global.sample_count {
+            warn!("Multisampling change requires a restart");
+        }
+
+        match self.shadow {
+            Some(ref mut shadow) if shadow.size == settings.light.shadow.size => {
+                shadow.set_light(&settings.light);
+            }
+            None if settings.light.shadow.size == 0 => {}
+            _ => warn!("Shadow size change requires a restart"),
+        }
+        match (
+            Self::background_colors(settings, srgb),
+            self.background.as_ref(),
+        ) {
+            (Some((zenith, horizon)), Some(background)) => {
+                background.set_colors(zenith, horizon, queue)
+            }
+            (Some((zenith, horizon)), None) => {
+                self.background = Some(background::Background::new(
+                    zenith,
+                    horizon,
+                    &self.global,
+                    device,
+                ));
+            }
+            (None, _) => self.background = None,
+        }
+        let reflection_size = self.reflection.as_ref().map_or(0, |r| r.size);
+        if reflection_size != settings.reflection_size {
+            warn!("Reflection size change requires a restart");
+        }
+
+        if self.terrain_config != settings.terrain || self.depth_prepass != settings.depth_prepass {
+            info!("Re-creating the terrain");
+            self.terrain = terrain::Context::new(
+                device,
+                queue,
+                level,
+                &self.global,
+                &settings.terrain,
+                &settings.light.shadow.terrain,
+                self.screen_size,
+                settings.depth_prepass,
+                &mut self.pipeline_cache,
+            );
+            self.terrain_config = settings.terrain.clone();
+            self.depth_prepass = settings.depth_prepass;
+        }
+        self.terrain
+            .set_height_scale(settings.terrain_height_scale, queue);
+    }
+
+    // Sets the dynamic lights for the following frames, up to `global::MAX_LIGHTS - 1`.
+    pub fn set_lights(&mut self, lights: &[global::Light]) {
+        let max = global::MAX_LIGHTS - 1;
+        if lights.len() > max {
+            warn!(
+                "Only {} out of {} dynamic lights are used",
+                max,
+                lights.len()
+            );
+        }
+        self.dynamic_lights.clear();
+        self.dynamic_lights
+            .extend_from_slice(&lights[..lights.len().min(max)]);
+    }
+
     pub fn draw_world(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
@@ -508,7 +1076,13 @@ impl Render {
         cam: &Camera,
         targets: ScreenTargets,
         device: &wgpu::Device,
+        mirror: Option<&body::GpuStoreMirror>,
     ) {
+        let now = Instant::now();
+        self.time.advance((now - self.last_frame).as_secs_f32());
+        self.last_frame = now;
+        self.stats = RenderStats::default();
+
         batcher.prepare(device);
         //TODO: common routine for draw passes
         //TODO: use `write_buffer`
@@ -516,7 +1090,8 @@ impl Render {
         if let Some(ref mut shadow) = self.shadow {
             shadow.update_view(cam);
 
-            let constants = global::Constants::new(&shadow.cam, &self.light_config, None);
+            let constants =
+                global::Constants::new(&shadow.cam, &self.light_config, None, &self.time, None);
             let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("temp-global-shadow"),
                 contents: bytemuck::bytes_of(&constants),
@@ -557,11 +1132,66 @@ impl Render {
 
             pass.set_bind_group(0, &self.global.shadow_bind_group, &[]);
             self.terrain.draw_shadow(&mut pass);
+            self.stats.terrain_draws += 1;
 
             // draw vehicle models
-            pass.set_pipeline(&self.object.pipelines.shadow);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
-            batcher.draw(&mut pass);
+            batcher.draw(&mut pass, &self.object.pipelines, PipelineKind::Shadow);
+            self.stats.add_batch(batcher);
+        }
+        // reflection pass, only the objects are there, since
+        // the terrain reflection is already ray-traced
+        if let Some(ref reflection) = self.reflection {
+            let constants = global::Constants::new(
+                cam,
+                &self.light_config,
+                None,
+                &self.time,
+                Some(&self.fog_config),
+            )
+            .with_lights(&self.dynamic_lights)
+            .with_reflection(cam, reflection.water_level(cam.loc.y));
+            let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("temp-global-reflection"),
+                contents: bytemuck::bytes_of(&constants),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            encoder.copy_buffer_to_buffer(
+                &global_staging,
+                0,
+                &self.global.uniform_buf,
+                0,
+                mem::size_of::<global::Constants>() as wgpu::BufferAddress,
+            );
+
+            let (attachment, resolve_target) = match reflection.multisampled_view {
+                Some(ref view) => (view, Some(&reflection.color_view)),
+                None => (&reflection.color_view, None),
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: &reflection.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            // this bind group doesn't have the reflection texture bound
+            pass.set_bind_group(0, &self.global.shadow_bind_group, &[]);
+            pass.set_bind_group(1, &self.object.bind_group, &[]);
+            batcher.draw(&mut pass, &self.object.pipelines, PipelineKind::Main);
+            self.stats.add_batch(batcher);
         }
         // main pass
         {
@@ -569,7 +1199,10 @@ impl Render {
                 cam,
                 &self.light_config,
                 self.shadow.as_ref().map(|shadow| &shadow.cam),
-            );
+                &self.time,
+                Some(&self.fog_config),
+            )
+            .with_lights(&self.dynamic_lights);
             let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("temp-global"),
                 contents: bytemuck::bytes_of(&constants),
@@ -592,10 +1225,36 @@ impl Render {
                 self.screen_size,
             );
 
+            let (color, resolve_target, depth) = match self.multisampled {
+                Some(ref ms) => (&ms.color, Some(targets.color), &ms.depth),
+                None => (targets.color, None, targets.depth),
+            };
+            // the terrain depth goes first, so that only the visible fragments are shaded
+            let depth_load = if self.terrain.has_depth_prepass() {
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(
+                        wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                            attachment: depth,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        },
+                    ),
+                });
+                pass.set_bind_group(0, &self.global.bind_group, &[]);
+                self.terrain.draw_depth(&mut pass);
+                self.stats.terrain_draws += 1;
+                wgpu::LoadOp::Load
+            } else {
+                wgpu::LoadOp::Clear(1.0)
+            };
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: targets.color,
-                    resolve_target: None,
+                    attachment: color,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear({
                             let c = self.fog_config.color;
@@ -610,9 +1269,9 @@ impl Render {
                     },
                 }],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: targets.depth,
+                    attachment: depth,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: depth_load,
                         store: true,
                     }),
                     stencil_ops: None,
@@ -620,23 +1279,93 @@ impl Render {
             });
 
             pass.set_bind_group(0, &self.global.bind_group, &[]);
+            if let Some(ref background) = self.background {
+                background.draw(&mut pass);
+            }
             self.terrain.draw(&mut pass);
+            self.stats.terrain_draws += 1;
 
             // draw vehicle models
-            pass.set_pipeline(&self.object.pipelines.main);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
-            batcher.draw(&mut pass);
+            batcher.draw(&mut pass, &self.object.pipelines, PipelineKind::Main);
+            self.stats.add_batch(batcher);
+
+            if self.water {
+                self.terrain.draw_water(&mut pass);
+                self.stats.terrain_draws += 1;
+            }
+
+            if let Some(mirror) = mirror {
+                let count = self.debug.draw_velocity(&mut pass, device, mirror);
+                if count != 0 {
+                    self.stats.debug_draws += 1;
+                    self.stats.vertices += count;
+                }
+            }
         }
     }
 
+    // Draws the world into an offscreen target of the given size,
+    // returning its pixels in the color target order, i.e. BGRA by default.
+    pub fn capture(
+        &mut self,
+        batcher: &mut Batcher,
+        cam: &Camera,
+        extent: wgpu::Extent3d,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<u8> {
+        let screen_size = self.screen_size;
+        if extent != screen_size {
+            self.resize(extent, device);
+        }
+
+        let target = capture::Capture::new(device, extent, self.global.color_format);
+        let depth =
+            create_target_view("Capture depth", extent, self.global.depth_format, 1, device);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture"),
+        });
+        let targets = ScreenTargets {
+            extent,
+            color: &target.view,
+            depth: &depth,
+        };
+        self.draw_world(&mut encoder, batcher, cam, targets, device, None);
+        let pending = target.read(&mut encoder, device);
+        queue.submit(Some(encoder.finish()));
+
+        if extent != screen_size {
+            self.resize(screen_size, device);
+        }
+        pending.wait_raw(device)
+    }
+
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    //TODO: record timestamp queries around the passes once wgpu supports
+    // query sets. Until then report zeros, the same as for adapters
+    // without timestamp support.
+    pub fn gpu_timings(&self) -> GpuTimings {
+        GpuTimings::default()
+    }
+
     pub fn reload(&mut self, device: &wgpu::Device) {
         info!("Reloading shaders");
         self.object.reload(device);
-        self.terrain.reload(device);
+        self.debug.reload(device);
+        if let Some(ref mut background) = self.background {
+            background.reload(device);
+        }
+        self.pipeline_cache.clear();
+        self.terrain.reload(device, &mut self.pipeline_cache);
     }
 
     pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
         self.terrain.resize(extent, device);
+        self.multisampled = Multisampled::new(&self.global, extent, device);
         self.screen_size = extent;
     }
 
@@ -1,7 +1,7 @@
 use crate::{
     render::{
-        body::GpuBody, global::Context as GlobalContext, GpuTransform, Palette, PipelineSet,
-        Shaders, COLOR_FORMAT, DEPTH_FORMAT, SHADOW_FORMAT,
+        body::GpuBody, global::Context as GlobalContext, palette_format, GpuTransform, PipelineSet,
+        RenderFormats, Shaders, SHADOW_FORMAT,
     },
     space::Transform,
 };
@@ -10,6 +10,8 @@ use m3d::NUM_COLOR_IDS;
 
 use std::{mem, slice};
 
+pub const MAX_PALETTES: u32 = 8;
+
 const COLOR_TABLE: [[u8; 2]; NUM_COLOR_IDS as usize] = [
     [0, 0],   // reserved
     [128, 3], // body
@@ -49,6 +51,15 @@ pub enum BodyColor {
     Gray = 10,
 }
 
+// Row of the object palette array. The shared palette is always there,
+// and the rows that were never written fall back to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaletteId(u32);
+
+impl PaletteId {
+    pub const SHARED: Self = PaletteId(0);
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Vertex {
@@ -66,6 +77,7 @@ pub struct Instance {
     orientation: [f32; 4],
     shape_scale: f32,
     body_and_color_id: [u32; 2],
+    palette_id: u32,
 }
 unsafe impl Pod for Instance {}
 unsafe impl Zeroable for Instance {}
@@ -78,18 +90,32 @@ impl Instance {
             orientation: gt.orientation,
             shape_scale: shape_scale,
             body_and_color_id: [body.index() as u32, color as u32],
+            palette_id: PaletteId::SHARED.0,
+        }
+    }
+
+    pub fn with_palette(self, palette: PaletteId) -> Self {
+        Instance {
+            palette_id: palette.0,
+            ..self
         }
     }
 }
 
 pub struct InstanceDesc {
-    attributes: [wgpu::VertexAttributeDescriptor; 4],
+    attributes: [wgpu::VertexAttributeDescriptor; 5],
 }
 
 impl InstanceDesc {
     pub fn new() -> Self {
         InstanceDesc {
-            attributes: wgpu::vertex_attr_array![3 => Float4, 4 => Float4, 5 => Float, 6 => Uint2],
+            attributes: wgpu::vertex_attr_array![
+                3 => Float4,
+                4 => Float4,
+                5 => Float,
+                6 => Uint2,
+                7 => Uint
+            ],
         }
     }
 
@@ -106,11 +132,20 @@ pub struct Context {
     pub bind_group: wgpu::BindGroup,
     pub shape_bind_group_layout: wgpu::BindGroupLayout,
     pub pipeline_layout: wgpu::PipelineLayout,
+    formats: RenderFormats,
+    sample_count: u32,
     pub pipelines: PipelineSet,
+    palette_texture: wgpu::Texture,
+    palette_count: u32,
 }
 
 impl Context {
-    fn create_pipelines(layout: &wgpu::PipelineLayout, device: &wgpu::Device) -> PipelineSet {
+    fn create_pipelines(
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> PipelineSet {
         let vertex_descriptor = wgpu::VertexBufferDescriptor {
             stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::InputStepMode::Vertex,
@@ -119,28 +154,69 @@ impl Context {
         let instance_desc = InstanceDesc::new();
 
         let main_shaders = Shaders::new("object", &["COLOR"], device).unwrap();
-        let main = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("object"),
+        let create_main = |label, depth_bias, depth_bias_slope_scale| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &main_shaders.vs,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &main_shaders.fs,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    // original was not drawn with rasterizer, used no culling
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias,
+                    depth_bias_slope_scale,
+                    ..Default::default()
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+                color_states: &[formats.color.into()],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: formats.depth,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilStateDescriptor::default(),
+                }),
+                vertex_state: wgpu::VertexStateDescriptor {
+                    index_format: wgpu::IndexFormat::Uint16,
+                    vertex_buffers: &[vertex_descriptor.clone(), instance_desc.buffer_desc()],
+                },
+                sample_count,
+                alpha_to_coverage_enabled: false,
+                sample_mask: !0,
+            })
+        };
+        let main = create_main("object", 0, 0.0);
+        let main_biased = create_main("object-biased", -2, -2.0);
+
+        // drawn behind the selected model, without writing depth
+        let outline_shaders = Shaders::new("object", &["OUTLINE"], device).unwrap();
+        let outline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("object-outline"),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &main_shaders.vs,
+                module: &outline_shaders.vs,
                 entry_point: "main",
             },
             fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &main_shaders.fs,
+                module: &outline_shaders.fs,
                 entry_point: "main",
             }),
             rasterization_state: Some(wgpu::RasterizationStateDescriptor {
                 front_face: wgpu::FrontFace::Ccw,
-                // original was not drawn with rasterizer, used no culling
                 cull_mode: wgpu::CullMode::None,
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[COLOR_FORMAT.into()],
+            color_states: &[formats.color.into()],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
-                depth_write_enabled: true,
+                format: formats.depth,
+                depth_write_enabled: false,
                 depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil: wgpu::StencilStateDescriptor::default(),
             }),
@@ -148,7 +224,7 @@ impl Context {
                 index_format: wgpu::IndexFormat::Uint16,
                 vertex_buffers: &[vertex_descriptor.clone(), instance_desc.buffer_desc()],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         });
@@ -190,7 +266,12 @@ impl Context {
             sample_mask: !0,
         });
 
-        PipelineSet { main, shadow }
+        PipelineSet {
+            main,
+            shadow,
+            main_biased,
+            outline,
+        }
     }
 
     fn create_color_table(
@@ -267,7 +348,7 @@ impl Context {
                     binding: 1,
                     visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::SampledTexture {
-                        dimension: wgpu::TextureViewDimension::D1,
+                        dimension: wgpu::TextureViewDimension::D2,
                         component_type: wgpu::TextureComponentType::Float,
                         multisampled: false,
                     },
@@ -297,10 +378,37 @@ impl Context {
                         },
                         count: None,
                     },
+                    // polygons, for the instanced debug view
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::VERTEX,
+                        ty: wgpu::BindingType::StorageBuffer {
+                            dynamic: false,
+                            readonly: true,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        let palette = Palette::new(device, queue, palette_data);
+        let palette_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Object palettes"),
+            size: wgpu::Extent3d {
+                width: 0x100,
+                height: MAX_PALETTES,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: palette_format(global.color_format),
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+        for row in 0..MAX_PALETTES {
+            Self::write_palette(&palette_texture, row, palette_data, queue);
+        }
+        let palette_view = palette_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let (color_table_view, color_table_sampler) = Self::create_color_table(device, queue);
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Object"),
@@ -312,7 +420,7 @@ impl Context {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&palette.view),
+                    resource: wgpu::BindingResource::TextureView(&palette_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
@@ -325,17 +433,68 @@ impl Context {
             bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
             push_constant_ranges: &[],
         });
-        let pipelines = Self::create_pipelines(&pipeline_layout, device);
+        let pipelines = Self::create_pipelines(
+            &pipeline_layout,
+            global.formats(),
+            global.sample_count,
+            device,
+        );
 
         Context {
             bind_group,
             shape_bind_group_layout,
             pipeline_layout,
+            formats: global.formats(),
+            sample_count: global.sample_count,
             pipelines,
+            palette_texture,
+            palette_count: 1,
+        }
+    }
+
+    fn write_palette(texture: &wgpu::Texture, row: u32, data: &[[u8; 4]], queue: &wgpu::Queue) {
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: row, z: 0 },
+            },
+            bytemuck::cast_slice(data),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 0x100 * 4,
+                rows_per_image: 0,
+            },
+            wgpu::Extent3d {
+                width: 0x100,
+                height: 1,
+                depth: 1,
+            },
+        );
+    }
+
+    // Returns `None` if all of the `MAX_PALETTES` slots are taken.
+    pub fn add_palette(&mut self, data: &[[u8; 4]], queue: &wgpu::Queue) -> Option<PaletteId> {
+        if self.palette_count == MAX_PALETTES {
+            return None;
         }
+        let id = PaletteId(self.palette_count);
+        Self::write_palette(&self.palette_texture, id.0, data, queue);
+        self.palette_count += 1;
+        Some(id)
+    }
+
+    pub fn set_palette(&mut self, id: PaletteId, data: &[[u8; 4]], queue: &wgpu::Queue) {
+        assert!(id.0 < self.palette_count);
+        Self::write_palette(&self.palette_texture, id.0, data, queue);
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
-        self.pipelines = Self::create_pipelines(&self.pipeline_layout, device);
+        self.pipelines = Self::create_pipelines(
+            &self.pipeline_layout,
+            self.formats,
+            self.sample_count,
+            device,
+        );
     }
 }
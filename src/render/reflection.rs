@@ -0,0 +1,79 @@
+use crate::{
+    level::Level,
+    render::{create_target_view, RenderFormats},
+};
+
+// Planar reflection of the objects in the water. The target has a fixed size,
+// since it's sampled with the screen coordinates normalized.
+pub struct Reflection {
+    pub(super) color_view: wgpu::TextureView,
+    // rendered into and resolved to `color_view` with multisampling
+    pub(super) multisampled_view: Option<wgpu::TextureView>,
+    pub(super) depth_view: wgpu::TextureView,
+    pub(super) size: u32,
+    // water height per flood section
+    flood_heights: Vec<f32>,
+    flood_section_power: usize,
+}
+
+impl Reflection {
+    pub(super) fn new(
+        size: u32,
+        level: &Level,
+        formats: RenderFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: 1,
+        };
+        let color = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Reflection"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: formats.color,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        });
+        let depth_view = create_target_view(
+            "Reflection depth",
+            extent,
+            formats.depth,
+            sample_count,
+            device,
+        );
+        let multisampled_view = if sample_count > 1 {
+            Some(create_target_view(
+                "Reflection multisampled",
+                extent,
+                formats.color,
+                sample_count,
+                device,
+            ))
+        } else {
+            None
+        };
+
+        let section_size = 1 << level.flood_section_power;
+        Reflection {
+            color_view: color.create_view(&wgpu::TextureViewDescriptor::default()),
+            multisampled_view,
+            depth_view,
+            size,
+            flood_heights: (0..level.flood_map.len())
+                .map(|i| level.flood_level_at((i * section_size) as f32))
+                .collect(),
+            flood_section_power: level.flood_section_power,
+        }
+    }
+
+    // The plane is taken at the water level under the camera.
+    pub(super) fn water_level(&self, y: f32) -> f32 {
+        let count = self.flood_heights.len() << self.flood_section_power;
+        let row = (y.floor() as i64).rem_euclid(count as i64) as usize;
+        self.flood_heights[row >> self.flood_section_power]
+    }
+}
@@ -32,25 +32,37 @@ impl Shadow {
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
         });
 
-        let dir = cgmath::Vector4::from(light.pos).truncate();
-        let up = if dir.x == 0.0 && dir.y == 0.0 {
-            cgmath::Vector3::unit_y()
-        } else {
-            cgmath::Vector3::unit_z()
-        };
+        let (dir, rot) = Self::orient(light);
 
         Shadow {
             view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
             cam: Camera {
                 loc: cgmath::Zero::zero(),
-                rot: cgmath::Quaternion::look_at(dir, up),
+                rot,
                 proj: Projection::ortho(1, 1, 0.0..1.0),
+                jitter: (0.0, 0.0),
             },
             size,
             dir,
         }
     }
 
+    fn orient(light: &settings::Light) -> (cgmath::Vector3<f32>, cgmath::Quaternion<f32>) {
+        let dir = cgmath::Vector4::from(light.pos).truncate();
+        let up = if dir.x == 0.0 && dir.y == 0.0 {
+            cgmath::Vector3::unit_y()
+        } else {
+            cgmath::Vector3::unit_z()
+        };
+        (dir, cgmath::Quaternion::look_at(dir, up))
+    }
+
+    pub(super) fn set_light(&mut self, light: &settings::Light) {
+        let (dir, rot) = Self::orient(light);
+        self.dir = dir;
+        self.cam.rot = rot;
+    }
+
     fn get_local_point(&self, world_pt: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
         let diff = world_pt.to_vec() - self.cam.loc;
         let right = self.cam.rot * cgmath::Vector3::unit_x();
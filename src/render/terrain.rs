@@ -2,8 +2,10 @@ use crate::{
     config::settings,
     level,
     render::{
-        global::Context as GlobalContext, mipmap::MaxMipper, Palette, PipelineKind, Shaders,
-        COLOR_FORMAT, DEPTH_FORMAT, SHADOW_FORMAT,
+        global::Context as GlobalContext,
+        mipmap::{Mipper, Reduction},
+        normal::NormalMap,
+        Palette, PipelineCache, PipelineKey, PipelineKind, RenderFormats, Shaders, SHADOW_FORMAT,
     },
     space::Camera,
 };
@@ -11,10 +13,18 @@ use crate::{
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt as _;
 
-use std::{mem, ops::Range};
+use std::{mem, ops::Range, slice, sync::Arc};
 
+#[cfg(not(feature = "altitude16"))]
 pub const HEIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+#[cfg(feature = "altitude16")]
+pub const HEIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+pub const META_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Uint;
+const HEIGHT_TEXEL_SIZE: u32 = if cfg!(feature = "altitude16") { 4 } else { 1 };
+const DEFAULT_RAY_STEPS: u32 = 8;
+const SHADOW_RAY_STEPS: u32 = 8;
 const SCATTER_GROUP_SIZE: [u32; 3] = [16, 16, 1];
+const MESH_PATCH_SIZE: u16 = 64;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -24,6 +34,14 @@ struct Vertex {
 unsafe impl Pod for Vertex {}
 unsafe impl Zeroable for Vertex {}
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MeshVertex {
+    _pos: [u16; 2],
+}
+unsafe impl Pod for MeshVertex {}
+unsafe impl Zeroable for MeshVertex {}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct SurfaceConstants {
@@ -52,7 +70,7 @@ struct ScatterConstants {
     sample_x: Range<f32>,
 }
 
-fn compute_scatter_constants(cam: &Camera) -> ScatterConstants {
+fn compute_scatter_constants(cam: &Camera, max_height: f32) -> ScatterConstants {
     use cgmath::{prelude::*, Point2, Point3, Vector2, Vector3};
 
     let cam_origin = Point2::new(cam.loc.x, cam.loc.y);
@@ -66,12 +84,12 @@ fn compute_scatter_constants(cam: &Camera) -> ScatterConstants {
         }
     };
 
-    fn intersect(base: &Vector3<f32>, target: Point3<f32>, height: u32) -> Point2<f32> {
+    fn intersect(base: &Vector3<f32>, target: Point3<f32>, height: f32) -> Point2<f32> {
         let dir = target.to_vec() - *base;
         let t = if dir.z == 0.0 {
             0.0
         } else {
-            (height as f32 - base.z) / dir.z
+            (height - base.z) / dir.z
         };
         let end = base + dir * t.max(0.0);
         Point2::new(end.x, end.y)
@@ -80,7 +98,7 @@ fn compute_scatter_constants(cam: &Camera) -> ScatterConstants {
     let mx_invp = cam.get_view_proj().invert().unwrap();
     let y_center = {
         let center = mx_invp.transform_point(Point3::new(0.0, 0.0, 0.0));
-        let center_base = intersect(&cam.loc, center, 0);
+        let center_base = intersect(&cam.loc, center, 0.0);
         (center_base - cam_origin).dot(cam_dir)
     };
     let mut y_range = y_center..y_center;
@@ -96,8 +114,8 @@ fn compute_scatter_constants(cam: &Camera) -> ScatterConstants {
 
     for &lp in &local_positions {
         let wp = mx_invp.transform_point(lp);
-        let pa = intersect(&cam.loc, wp, 0);
-        let pb = intersect(&cam.loc, wp, level::HEIGHT_SCALE);
+        let pa = intersect(&cam.loc, wp, 0.0);
+        let pb = intersect(&cam.loc, wp, max_height);
         for p in &[pa, pb] {
             let dir = *p - cam_origin;
             let y = dir.dot(cam_dir);
@@ -125,7 +143,7 @@ struct Geometry {
 }
 
 impl Geometry {
-    fn new(vertices: &[Vertex], indices: &[u16], device: &wgpu::Device) -> Self {
+    fn new<V: Pod>(vertices: &[V], indices: &[u16], device: &wgpu::Device) -> Self {
         Geometry {
             vertex_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("terrain-vertex"),
@@ -142,13 +160,236 @@ impl Geometry {
     }
 }
 
+// Conventional triangle mesh of the terrain surface. A single patch of
+// `MESH_PATCH_SIZE` tiles is instanced over the visible area, and the vertex
+// shader lifts it by sampling the height map, so the mesh doesn't need to be
+// rebuilt on `update_region`. Only the upper level of dual-level tiles is drawn.
+//
+// With the depth pre-pass, the depth is laid out by a pipeline without
+// the fragment stage first, and the main pipeline only shades the fragments
+// that end up visible, testing for the equal depth.
+pub struct MeshContext {
+    pipeline: Arc<wgpu::RenderPipeline>,
+    depth_pipeline: Option<Arc<wgpu::RenderPipeline>>,
+    geo: Geometry,
+    patch_count: u32,
+}
+
+impl MeshContext {
+    fn key(
+        color_format: Option<wgpu::TextureFormat>,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_prepass: bool,
+    ) -> PipelineKey {
+        PipelineKey {
+            shader: "terrain/mesh",
+            specialization: if depth_prepass && color_format.is_some() {
+                &["EARLY_DEPTH"]
+            } else {
+                &[]
+            },
+            color_format,
+            depth_format: Some(depth_format),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count,
+        }
+    }
+
+    // Without the color format, only the depth is written.
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        color_format: Option<wgpu::TextureFormat>,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_prepass: bool,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let key = Self::key(color_format, depth_format, sample_count, depth_prepass);
+        let shaders = Shaders::new(key.shader, key.specialization, device).unwrap();
+        let color_states = color_format.map(wgpu::ColorStateDescriptor::from);
+        let (depth_write_enabled, depth_compare) = match color_format {
+            Some(_) if depth_prepass => (false, wgpu::CompareFunction::Equal),
+            _ => (true, wgpu::CompareFunction::Less),
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(if color_format.is_some() {
+                "terrain-mesh"
+            } else {
+                "terrain-mesh-depth"
+            }),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: color_format.map(|_| wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: color_states.as_ref().map_or(&[][..], slice::from_ref),
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: depth_format,
+                depth_write_enabled,
+                depth_compare,
+                stencil: Default::default(),
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<MeshVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[wgpu::VertexAttributeDescriptor {
+                        offset: 0,
+                        format: wgpu::VertexFormat::Ushort2,
+                        shader_location: 0,
+                    }],
+                }],
+            },
+            sample_count,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+
+    fn pipelines(
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        depth_prepass: bool,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+    ) -> (Arc<wgpu::RenderPipeline>, Option<Arc<wgpu::RenderPipeline>>) {
+        let key = Self::key(
+            Some(formats.color),
+            formats.depth,
+            sample_count,
+            depth_prepass,
+        );
+        let pipeline = cache.get_or_create(key, || {
+            Self::create_pipeline(
+                layout,
+                Some(formats.color),
+                formats.depth,
+                sample_count,
+                depth_prepass,
+                device,
+            )
+        });
+        let depth_pipeline = if depth_prepass {
+            let key = Self::key(None, formats.depth, sample_count, depth_prepass);
+            Some(cache.get_or_create(key, || {
+                Self::create_pipeline(
+                    layout,
+                    None,
+                    formats.depth,
+                    sample_count,
+                    depth_prepass,
+                    device,
+                )
+            }))
+        } else {
+            None
+        };
+        (pipeline, depth_pipeline)
+    }
+
+    fn new(
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        depth_prepass: bool,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+    ) -> Self {
+        let row = MESH_PATCH_SIZE + 1;
+        let mut vertices = Vec::with_capacity(row as usize * row as usize);
+        for y in 0..row {
+            for x in 0..row {
+                vertices.push(MeshVertex { _pos: [x, y] });
+            }
+        }
+        let mut indices =
+            Vec::with_capacity(MESH_PATCH_SIZE as usize * MESH_PATCH_SIZE as usize * 6);
+        for y in 0..MESH_PATCH_SIZE {
+            for x in 0..MESH_PATCH_SIZE {
+                let i0 = y * row + x;
+                let i1 = i0 + row;
+                indices.extend_from_slice(&[i0, i0 + 1, i1 + 1, i1 + 1, i1, i0]);
+            }
+        }
+
+        let (pipeline, depth_pipeline) =
+            Self::pipelines(layout, formats, sample_count, depth_prepass, device, cache);
+        MeshContext {
+            pipeline,
+            depth_pipeline,
+            geo: Geometry::new(&vertices, &indices, device),
+            patch_count: 0,
+        }
+    }
+
+    fn reload(
+        &mut self,
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+    ) {
+        let depth_prepass = self.depth_pipeline.is_some();
+        let (pipeline, depth_pipeline) =
+            Self::pipelines(layout, formats, sample_count, depth_prepass, device, cache);
+        self.pipeline = pipeline;
+        self.depth_pipeline = depth_pipeline;
+    }
+
+    // Has to match `generate_patch_origin` in the shader.
+    fn update(&mut self, sc: &ScatterConstants) {
+        let size = MESH_PATCH_SIZE as f32;
+        let columns = (sc.sample_x.end / size).ceil() - (sc.sample_x.start / size).floor();
+        let rows = (sc.sample_y.end / size).ceil() - (sc.sample_y.start / size).floor();
+        let count = (columns.max(0.0) * rows.max(0.0)) as u32;
+        const MAX_PATCHES: u32 = 10_000;
+        self.patch_count = if count > MAX_PATCHES {
+            log::error!("Too many terrain patches: {}", count);
+            MAX_PATCHES
+        } else {
+            count
+        };
+    }
+
+    fn draw_with<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+    ) {
+        pass.set_pipeline(pipeline);
+        pass.set_index_buffer(self.geo.index_buf.slice(..));
+        pass.set_vertex_buffer(0, self.geo.vertex_buf.slice(..));
+        pass.draw_indexed(0..self.geo.num_indices, 0, 0..self.patch_count);
+    }
+
+    fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        self.draw_with(pass, &self.pipeline);
+    }
+}
+
 enum Kind {
     Ray {
-        pipeline: wgpu::RenderPipeline,
+        pipeline: Arc<wgpu::RenderPipeline>,
+        params: [u32; 4],
     },
     RayMip {
-        pipeline: wgpu::RenderPipeline,
-        mipper: MaxMipper,
+        pipeline: Arc<wgpu::RenderPipeline>,
+        mipper: Mipper,
+        meta_mipper: Mipper,
         params: [u32; 4],
     },
     /*Tess {
@@ -157,11 +398,11 @@ enum Kind {
         screen_space: bool,
     },*/
     Slice {
-        pipeline: wgpu::RenderPipeline,
+        pipeline: Arc<wgpu::RenderPipeline>,
         geo: Geometry,
     },
     Paint {
-        pipeline: wgpu::RenderPipeline,
+        pipeline: Arc<wgpu::RenderPipeline>,
         geo: Geometry,
         bar_count: u32,
     },
@@ -175,8 +416,29 @@ enum Kind {
         compute_groups: [u32; 3],
         density: [u32; 3],
     },
+    Mesh(MeshContext),
+}
+
+// Packs a block of level altitudes into `HEIGHT_FORMAT` texels.
+fn height_texels(level: &level::Level, xs: Range<i32>, ys: Range<i32>) -> Vec<u8> {
+    let count = (xs.end - xs.start) * (ys.end - ys.start);
+    let mut data = Vec::with_capacity(count as usize * HEIGHT_TEXEL_SIZE as usize);
+    for y in ys {
+        let base = (y * level.size.0) as usize;
+        let row = base + xs.start as usize..base + xs.end as usize;
+        #[cfg(not(feature = "altitude16"))]
+        data.extend_from_slice(&level.height[row]);
+        #[cfg(feature = "altitude16")]
+        for i in row {
+            let max = (255u32 << level::ALTITUDE_FRACTION_BITS) as f32;
+            let value = level.altitude(i) as f32 / max;
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+    data
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Rect {
     pub x: u16,
     pub y: u16,
@@ -188,17 +450,63 @@ pub struct Context {
     pub surface_uni_buf: wgpu::Buffer,
     pub uniform_buf: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
+    // same resources as `bind_group`, but with the unscaled heights
+    pub collision_bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    height_scale: f32,
     pipeline_layout: wgpu::PipelineLayout,
+    formats: RenderFormats,
+    sample_count: u32,
     raytrace_geo: Geometry,
     kind: Kind,
+    water_pipeline: Arc<wgpu::RenderPipeline>,
+    // one instance per flood section in the sampled rows
+    water_sections: u32,
+    flood_section_size: f32,
     shadow_kind: Kind,
+    height_texture: wgpu::Texture,
+    meta_texture: wgpu::Texture,
+    normal_map: NormalMap,
     dirty_rects: Vec<Rect>,
 }
 
 impl Context {
+    fn ray_pipeline(
+        cache: &mut PipelineCache,
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+        name: &'static str,
+        kind: PipelineKind,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let key = match kind {
+            PipelineKind::Main => PipelineKey {
+                shader: name,
+                specialization: &["COLOR"],
+                color_format: Some(formats.color),
+                depth_format: Some(formats.depth),
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                sample_count,
+            },
+            PipelineKind::Shadow => PipelineKey {
+                shader: name,
+                specialization: &[],
+                color_format: None,
+                depth_format: Some(SHADOW_FORMAT),
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                sample_count: 1,
+            },
+        };
+        cache.get_or_create(key, || {
+            Self::create_ray_pipeline(layout, formats, sample_count, device, name, kind)
+        })
+    }
+
     fn create_ray_pipeline(
         layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
         device: &wgpu::Device,
         name: &str,
         kind: PipelineKind,
@@ -217,14 +525,19 @@ impl Context {
         };
 
         let color_descs = [wgpu::ColorStateDescriptor {
-            format: COLOR_FORMAT,
+            format: formats.color,
             alpha_blend: wgpu::BlendDescriptor::REPLACE,
             color_blend: wgpu::BlendDescriptor::REPLACE,
             write_mask: wgpu::ColorWrite::all(),
         }];
-        let (features, color_states, depth_format) = match kind {
-            PipelineKind::Main => (&["COLOR"][..], &color_descs[..], DEPTH_FORMAT),
-            PipelineKind::Shadow => (&[][..], &[][..], SHADOW_FORMAT),
+        let (features, color_states, depth_format, sample_count) = match kind {
+            PipelineKind::Main => (
+                &["COLOR"][..],
+                &color_descs[..],
+                formats.depth,
+                sample_count,
+            ),
+            PipelineKind::Shadow => (&[][..], &[][..], SHADOW_FORMAT, 1),
         };
 
         let shaders = Shaders::new(name, features, device).unwrap();
@@ -253,14 +566,27 @@ impl Context {
                 stencil: Default::default(),
             }),
             vertex_state: vertex_state.clone(),
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         })
     }
 
+    fn simple_key(shader: &'static str, formats: RenderFormats, sample_count: u32) -> PipelineKey {
+        PipelineKey {
+            shader,
+            specialization: &[],
+            color_format: Some(formats.color),
+            depth_format: Some(formats.depth),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count,
+        }
+    }
+
     fn create_slice_pipeline(
         layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
         device: &wgpu::Device,
     ) -> wgpu::RenderPipeline {
         let shaders = Shaders::new("terrain/slice", &[], device).unwrap();
@@ -282,13 +608,13 @@ impl Context {
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
             color_states: &[wgpu::ColorStateDescriptor {
-                format: COLOR_FORMAT,
+                format: formats.color,
                 alpha_blend: wgpu::BlendDescriptor::REPLACE,
                 color_blend: wgpu::BlendDescriptor::REPLACE,
                 write_mask: wgpu::ColorWrite::all(),
             }],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: formats.depth,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
@@ -305,7 +631,7 @@ impl Context {
                     }],
                 }],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         })
@@ -313,6 +639,8 @@ impl Context {
 
     fn create_paint_pipeline(
         layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
         device: &wgpu::Device,
     ) -> wgpu::RenderPipeline {
         let shaders = Shaders::new("terrain/paint", &[], device).unwrap();
@@ -333,9 +661,9 @@ impl Context {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[COLOR_FORMAT.into()],
+            color_states: &[formats.color.into()],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: formats.depth,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
@@ -344,7 +672,58 @@ impl Context {
                 index_format: wgpu::IndexFormat::Uint16,
                 vertex_buffers: &[],
             },
-            sample_count: 1,
+            sample_count,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+
+    // Translucent, so it's drawn after everything opaque, without writing depth.
+    fn create_water_pipeline(
+        layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let shaders = Shaders::new("terrain/water", &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("terrain-water"),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: formats.color,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::all(),
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: formats.depth,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: Default::default(),
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         })
@@ -352,6 +731,8 @@ impl Context {
 
     fn create_scatter_pipelines(
         layout: &wgpu::PipelineLayout,
+        formats: RenderFormats,
+        sample_count: u32,
         device: &wgpu::Device,
     ) -> (
         wgpu::ComputePipeline,
@@ -397,9 +778,9 @@ impl Context {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
-            color_states: &[COLOR_FORMAT.into()],
+            color_states: &[formats.color.into()],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: formats.depth,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
@@ -408,7 +789,7 @@ impl Context {
                 index_format: wgpu::IndexFormat::Uint16,
                 vertex_buffers: &[],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         });
@@ -446,6 +827,7 @@ impl Context {
         (bind_group, group_count)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -454,6 +836,8 @@ impl Context {
         config: &settings::Terrain,
         shadow_config: &settings::ShadowTerrain,
         screen_extent: wgpu::Extent3d,
+        depth_prepass: bool,
+        cache: &mut PipelineCache,
     ) -> Self {
         let extent = wgpu::Extent3d {
             width: level.size.0 as u32,
@@ -502,11 +886,11 @@ impl Context {
         let meta_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Terrain meta"),
             size: extent,
-            mip_level_count: 1,
+            mip_level_count: terrain_mip_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::R8Uint,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            format: META_FORMAT,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST | terrain_extra_usage,
         });
         let flood_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Terrain flood"),
@@ -533,10 +917,10 @@ impl Context {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            bytemuck::cast_slice(&level.height),
+            &height_texels(level, 0..level.size.0, 0..level.size.1),
             wgpu::TextureDataLayout {
                 offset: 0,
-                bytes_per_row: level.size.0 as u32,
+                bytes_per_row: level.size.0 as u32 * HEIGHT_TEXEL_SIZE,
                 rows_per_image: 0,
             },
             extent,
@@ -584,7 +968,7 @@ impl Context {
             table_extent,
         );
 
-        let palette = Palette::new(device, queue, &level.palette);
+        let palette = Palette::new(device, queue, &level.palette, global.color_format);
 
         let repeat_nearest_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -662,7 +1046,7 @@ impl Context {
                 // flood map
                 wgpu::BindGroupLayoutEntry {
                     binding: 4,
-                    visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                    visibility: wgpu::ShaderStage::all(),
                     ty: wgpu::BindingType::SampledTexture {
                         dimension: wgpu::TextureViewDimension::D1,
                         component_type: wgpu::TextureComponentType::Float,
@@ -702,7 +1086,7 @@ impl Context {
                 // flood sampler
                 wgpu::BindGroupLayoutEntry {
                     binding: 8,
-                    visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                    visibility: wgpu::ShaderStage::all(),
                     ty: wgpu::BindingType::Sampler { comparison: false },
                     count: None,
                 },
@@ -713,19 +1097,43 @@ impl Context {
                     ty: wgpu::BindingType::Sampler { comparison: false },
                     count: None,
                 },
+                // normal map
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
 
+        let normal_map = NormalMap::new(
+            &height_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            extent,
+            device,
+        );
+
+        let surface_constants = SurfaceConstants {
+            _tex_scale: [
+                level.size.0 as f32,
+                level.size.1 as f32,
+                level::HEIGHT_SCALE as f32,
+                0.0,
+            ],
+        };
         let surface_uni_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("surface-uniforms"),
-            contents: bytemuck::bytes_of(&SurfaceConstants {
-                _tex_scale: [
-                    level.size.0 as f32,
-                    level.size.1 as f32,
-                    level::HEIGHT_SCALE as f32,
-                    0.0,
-                ],
-            }),
+            contents: bytemuck::bytes_of(&surface_constants),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        // the collision always sees the original heights
+        let collision_surface_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("collision-surface-uniforms"),
+            contents: bytemuck::bytes_of(&surface_constants),
             usage: wgpu::BufferUsage::UNIFORM,
         });
         let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
@@ -735,60 +1143,68 @@ impl Context {
             mapped_at_creation: false,
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Terrain"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: surface_uni_buf.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: uniform_buf.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(
-                        &height_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::TextureView(
-                        &meta_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureView(
-                        &flood_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::TextureView(
-                        &table_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::TextureView(&palette.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::Sampler(&repeat_nearest_sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::Sampler(&flood_sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 9,
-                    resource: wgpu::BindingResource::Sampler(&table_sampler),
-                },
-            ],
-        });
+        let create_bind_group = |label, surface_buf: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: surface_buf.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: uniform_buf.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(
+                            &height_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(
+                            &meta_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(
+                            &flood_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(
+                            &table_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(&palette.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: wgpu::BindingResource::Sampler(&repeat_nearest_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: wgpu::BindingResource::Sampler(&flood_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 9,
+                        resource: wgpu::BindingResource::Sampler(&table_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 10,
+                        resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                    },
+                ],
+            })
+        };
+        let bind_group = create_bind_group("Terrain", &surface_uni_buf);
+        let collision_bind_group = create_bind_group("Terrain collision", &collision_surface_buf);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("terrain"),
@@ -812,15 +1228,27 @@ impl Context {
             device,
         );
 
+        let formats = global.formats();
+        let sample_count = global.sample_count;
         let kind = match *config {
-            settings::Terrain::RayTraced => {
-                let pipeline = Self::create_ray_pipeline(
+            settings::Terrain::RayTraced | settings::Terrain::RayTracedSteps { .. } => {
+                let max_steps = match *config {
+                    settings::Terrain::RayTracedSteps { max_steps } => max_steps,
+                    _ => DEFAULT_RAY_STEPS,
+                };
+                let pipeline = Self::ray_pipeline(
+                    cache,
                     &pipeline_layout,
+                    formats,
+                    sample_count,
                     device,
                     "terrain/ray",
                     PipelineKind::Main,
                 );
-                Kind::Ray { pipeline }
+                Kind::Ray {
+                    pipeline,
+                    params: [max_steps, 0, 0, 0],
+                }
             }
             settings::Terrain::RayMipTraced {
                 mip_count,
@@ -828,17 +1256,25 @@ impl Context {
                 max_steps,
                 debug,
             } => {
-                let pipeline = Self::create_ray_pipeline(
+                let pipeline = Self::ray_pipeline(
+                    cache,
                     &pipeline_layout,
+                    formats,
+                    sample_count,
                     device,
                     "terrain/ray_mip",
                     PipelineKind::Main,
                 );
-                let mipper = MaxMipper::new(&height_texture, extent, mip_count, device);
+                let mipper =
+                    Mipper::new(&height_texture, extent, mip_count, Reduction::Max, device);
+
+                let meta_mipper =
+                    Mipper::new(&meta_texture, extent, mip_count, Reduction::Mode, device);
 
                 Kind::RayMip {
                     pipeline,
                     mipper,
+                    meta_mipper,
                     params: [
                         mip_count - 1,
                         max_jumps,
@@ -866,7 +1302,10 @@ impl Context {
                     device,
                 );
 
-                let pipeline = Self::create_slice_pipeline(&pipeline_layout, device);
+                let pipeline = cache.get_or_create(
+                    Self::simple_key("terrain/slice", formats, sample_count),
+                    || Self::create_slice_pipeline(&pipeline_layout, formats, sample_count, device),
+                );
 
                 Kind::Slice { pipeline, geo }
             }
@@ -883,7 +1322,10 @@ impl Context {
                     device,
                 );
 
-                let pipeline = Self::create_paint_pipeline(&pipeline_layout, device);
+                let pipeline = cache.get_or_create(
+                    Self::simple_key("terrain/paint", formats, sample_count),
+                    || Self::create_paint_pipeline(&pipeline_layout, formats, sample_count, device),
+                );
 
                 Kind::Paint {
                     pipeline,
@@ -922,7 +1364,12 @@ impl Context {
                     });
 
                 let (scatter_pipeline, clear_pipeline, copy_pipeline) =
-                    Self::create_scatter_pipelines(&local_pipeline_layout, device);
+                    Self::create_scatter_pipelines(
+                        &local_pipeline_layout,
+                        formats,
+                        sample_count,
+                        device,
+                    );
                 let (local_bg, compute_groups) =
                     Self::create_scatter_resources(screen_extent, &local_bg_layout, device);
                 Kind::Scatter {
@@ -936,17 +1383,42 @@ impl Context {
                     density,
                 }
             }
+            settings::Terrain::Meshed => Kind::Mesh(MeshContext::new(
+                &pipeline_layout,
+                formats,
+                sample_count,
+                depth_prepass,
+                device,
+                cache,
+            )),
         };
+        if depth_prepass {
+            match kind {
+                Kind::Mesh(_) => {}
+                _ => log::warn!("Depth pre-pass is only supported by the meshed terrain"),
+            }
+        }
+
+        let water_pipeline = cache.get_or_create(
+            Self::simple_key("terrain/water", formats, sample_count),
+            || Self::create_water_pipeline(&pipeline_layout, formats, sample_count, device),
+        );
 
         let shadow_kind = match *shadow_config {
             settings::ShadowTerrain::RayTraced => {
-                let pipeline = Self::create_ray_pipeline(
+                let pipeline = Self::ray_pipeline(
+                    cache,
                     &pipeline_layout,
+                    formats,
+                    sample_count,
                     device,
                     "terrain/ray",
                     PipelineKind::Shadow,
                 );
-                Kind::Ray { pipeline }
+                Kind::Ray {
+                    pipeline,
+                    params: [SHADOW_RAY_STEPS, 0, 0, 0],
+                }
             }
         };
 
@@ -954,11 +1426,21 @@ impl Context {
             surface_uni_buf,
             uniform_buf,
             bind_group,
+            collision_bind_group,
+            height_scale: 1.0,
             bind_group_layout,
             pipeline_layout,
+            formats,
+            sample_count,
             raytrace_geo,
             kind,
+            water_pipeline,
+            water_sections: 0,
+            flood_section_size: (1 << level.flood_section_power) as f32,
             shadow_kind,
+            height_texture,
+            meta_texture,
+            normal_map,
             dirty_rects: vec![Rect {
                 x: 0,
                 y: 0,
@@ -968,13 +1450,71 @@ impl Context {
         }
     }
 
-    pub fn reload(&mut self, device: &wgpu::Device) {
+    // Re-uploads the height and meta of the given tiles after `level` got
+    // edited. The mips are rebuilt for these rects at the next `prepare`.
+    pub fn update_region(&mut self, rects: &[Rect], level: &level::Level, queue: &wgpu::Queue) {
+        for r in rects {
+            let x1 = (r.x as i32 + r.w as i32).min(level.size.0);
+            let y1 = (r.y as i32 + r.h as i32).min(level.size.1);
+            if x1 <= r.x as i32 || y1 <= r.y as i32 {
+                continue;
+            }
+            let extent = wgpu::Extent3d {
+                width: (x1 - r.x as i32) as u32,
+                height: (y1 - r.y as i32) as u32,
+                depth: 1,
+            };
+            let mut meta = Vec::with_capacity((extent.width * extent.height) as usize);
+            for y in r.y as i32..y1 {
+                let base = (y * level.size.0) as usize;
+                meta.extend_from_slice(&level.meta[base + r.x as usize..base + x1 as usize]);
+            }
+            let height = height_texels(level, r.x as i32..x1, r.y as i32..y1);
+            for &(texture, ref data, texel_size) in &[
+                (&self.height_texture, height, HEIGHT_TEXEL_SIZE),
+                (&self.meta_texture, meta, 1),
+            ] {
+                queue.write_texture(
+                    wgpu::TextureCopyView {
+                        texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: r.x as u32,
+                            y: r.y as u32,
+                            z: 0,
+                        },
+                    },
+                    data,
+                    wgpu::TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: extent.width * texel_size,
+                        rows_per_image: 0,
+                    },
+                    extent,
+                );
+            }
+            self.dirty_rects.push(Rect {
+                x: r.x,
+                y: r.y,
+                w: extent.width as u16,
+                h: extent.height as u16,
+            });
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, cache: &mut PipelineCache) {
+        self.normal_map.reload(device);
+        let formats = self.formats;
+        let sample_count = self.sample_count;
         match self.kind {
             Kind::Ray {
                 ref mut pipeline, ..
             } => {
-                *pipeline = Self::create_ray_pipeline(
+                *pipeline = Self::ray_pipeline(
+                    cache,
                     &self.pipeline_layout,
+                    formats,
+                    sample_count,
                     device,
                     "terrain/ray",
                     PipelineKind::Main,
@@ -983,15 +1523,20 @@ impl Context {
             Kind::RayMip {
                 ref mut pipeline,
                 ref mut mipper,
+                ref mut meta_mipper,
                 ..
             } => {
-                *pipeline = Self::create_ray_pipeline(
+                *pipeline = Self::ray_pipeline(
+                    cache,
                     &self.pipeline_layout,
+                    formats,
+                    sample_count,
                     device,
                     "terrain/ray_mip",
                     PipelineKind::Main,
                 );
                 mipper.reload(device);
+                meta_mipper.reload(device);
             }
             /*
             Terrain::Tess { ref mut low, ref mut high, screen_space } => {
@@ -1002,12 +1547,20 @@ impl Context {
             Kind::Slice {
                 ref mut pipeline, ..
             } => {
-                *pipeline = Self::create_slice_pipeline(&self.pipeline_layout, device);
+                let layout = &self.pipeline_layout;
+                *pipeline = cache.get_or_create(
+                    Self::simple_key("terrain/slice", formats, sample_count),
+                    || Self::create_slice_pipeline(layout, formats, sample_count, device),
+                );
             }
             Kind::Paint {
                 ref mut pipeline, ..
             } => {
-                *pipeline = Self::create_paint_pipeline(&self.pipeline_layout, device);
+                let layout = &self.pipeline_layout;
+                *pipeline = cache.get_or_create(
+                    Self::simple_key("terrain/paint", formats, sample_count),
+                    || Self::create_paint_pipeline(layout, formats, sample_count, device),
+                );
             }
             Kind::Scatter {
                 ref pipeline_layout,
@@ -1017,19 +1570,25 @@ impl Context {
                 ..
             } => {
                 let (scatter, clear, copy) =
-                    Self::create_scatter_pipelines(pipeline_layout, device);
+                    Self::create_scatter_pipelines(pipeline_layout, formats, sample_count, device);
                 *scatter_pipeline = scatter;
                 *clear_pipeline = clear;
                 *copy_pipeline = copy;
             }
+            Kind::Mesh(ref mut mesh) => {
+                mesh.reload(&self.pipeline_layout, formats, sample_count, device, cache);
+            }
         }
 
         match self.shadow_kind {
             Kind::Ray {
                 ref mut pipeline, ..
             } => {
-                *pipeline = Self::create_ray_pipeline(
+                *pipeline = Self::ray_pipeline(
+                    cache,
                     &self.pipeline_layout,
+                    formats,
+                    sample_count,
                     device,
                     "terrain/ray",
                     PipelineKind::Shadow,
@@ -1037,6 +1596,12 @@ impl Context {
             }
             _ => unreachable!(),
         }
+
+        let layout = &self.pipeline_layout;
+        self.water_pipeline = cache.get_or_create(
+            Self::simple_key("terrain/water", formats, sample_count),
+            || Self::create_water_pipeline(layout, formats, sample_count, device),
+        );
     }
 
     pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
@@ -1055,6 +1620,34 @@ impl Context {
         }
     }
 
+    // Forward step count of the ray-traced terrain.
+    pub fn ray_steps(&self) -> Option<u32> {
+        match self.kind {
+            Kind::Ray { params, .. } => Some(params[0]),
+            _ => None,
+        }
+    }
+
+    // Changes the forward step count of the ray-traced terrain,
+    // returns `false` for the other kinds of terrain.
+    pub fn set_ray_steps(&mut self, max_steps: u32) -> bool {
+        match self.kind {
+            Kind::Ray { ref mut params, .. } => {
+                params[0] = max_steps;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Exaggerates the rendered relief, leaving the collision heights intact.
+    pub fn set_height_scale(&mut self, scale: f32, queue: &wgpu::Queue) {
+        self.height_scale = scale;
+        let height = level::HEIGHT_SCALE as f32 * scale;
+        // Z component of `SurfaceConstants::_tex_scale`
+        queue.write_buffer(&self.surface_uni_buf, 8, bytemuck::bytes_of(&height));
+    }
+
     pub fn prepare(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
@@ -1065,19 +1658,27 @@ impl Context {
         screen_size: wgpu::Extent3d,
     ) {
         if !self.dirty_rects.is_empty() {
-            if let Kind::RayMip { ref mipper, .. } = self.kind {
+            self.normal_map.update(&self.dirty_rects, encoder, device);
+            if let Kind::RayMip {
+                ref mipper,
+                ref meta_mipper,
+                ..
+            } = self.kind
+            {
                 mipper.update(&self.dirty_rects, encoder, device);
+                meta_mipper.update(&self.dirty_rects, encoder, device);
             }
             self.dirty_rects.clear();
         }
 
         let params = match self.kind {
-            Kind::RayMip { params, .. } => params,
+            Kind::Ray { params, .. } | Kind::RayMip { params, .. } => params,
+            Kind::Mesh(_) => [MESH_PATCH_SIZE as u32, 0, 0, 0],
             _ => [0; 4],
         };
 
         let sc = if let Kind::Scatter { .. } = self.kind {
-            compute_scatter_constants(cam)
+            compute_scatter_constants(cam, level::HEIGHT_SCALE as f32 * self.height_scale)
         } else {
             use cgmath::EuclideanSpace;
             let bounds = cam.visible_bounds();
@@ -1118,6 +1719,11 @@ impl Context {
             );
         }
 
+        // has to match the section range in "water.glsl"
+        let size = self.flood_section_size;
+        let sections = (sc.sample_y.end / size).ceil() - (sc.sample_y.start / size).floor();
+        self.water_sections = sections.max(0.0) as u32;
+
         match self.kind {
             Kind::Paint {
                 ref mut bar_count, ..
@@ -1133,6 +1739,9 @@ impl Context {
                     count
                 };
             }
+            Kind::Mesh(ref mut mesh) => {
+                mesh.update(&sc);
+            }
             Kind::Scatter {
                 ref clear_pipeline,
                 ref scatter_pipeline,
@@ -1167,7 +1776,7 @@ impl Context {
     ) {
         use cgmath::EuclideanSpace;
         let params = match self.shadow_kind {
-            Kind::RayMip { params, .. } => params,
+            Kind::Ray { params, .. } | Kind::RayMip { params, .. } => params,
             _ => [0; 4],
         };
 
@@ -1212,7 +1821,7 @@ impl Context {
         pass.set_bind_group(1, &self.bind_group, &[]);
         // draw terrain
         match self.kind {
-            Kind::Ray { ref pipeline } | Kind::RayMip { ref pipeline, .. } => {
+            Kind::Ray { ref pipeline, .. } | Kind::RayMip { ref pipeline, .. } => {
                 let geo = &self.raytrace_geo;
                 pass.set_pipeline(pipeline);
                 pass.set_index_buffer(geo.index_buf.slice(..));
@@ -1251,14 +1860,41 @@ impl Context {
                 pass.set_bind_group(2, bind_group, &[]);
                 pass.draw(0..4, 0..1);
             }
+            Kind::Mesh(ref mesh) => {
+                mesh.draw(pass);
+            }
+        }
+    }
+
+    pub fn has_depth_prepass(&self) -> bool {
+        match self.kind {
+            Kind::Mesh(ref mesh) => mesh.depth_pipeline.is_some(),
+            _ => false,
+        }
+    }
+
+    // Writes the terrain depth ahead of `draw`, if the pre-pass is enabled.
+    pub fn draw_depth<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if let Kind::Mesh(ref mesh) = self.kind {
+            if let Some(ref pipeline) = mesh.depth_pipeline {
+                pass.set_bind_group(1, &self.bind_group, &[]);
+                mesh.draw_with(pass, pipeline);
+            }
         }
     }
 
+    // Expects the globals to be bound at set 0, like `draw`.
+    pub fn draw_water<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_pipeline(&self.water_pipeline);
+        pass.draw(0..6, 0..self.water_sections);
+    }
+
     pub fn draw_shadow<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
         pass.set_bind_group(1, &self.bind_group, &[]);
         // draw terrain
         match self.shadow_kind {
-            Kind::Ray { ref pipeline } | Kind::RayMip { ref pipeline, .. } => {
+            Kind::Ray { ref pipeline, .. } | Kind::RayMip { ref pipeline, .. } => {
                 let geo = &self.raytrace_geo;
                 pass.set_pipeline(pipeline);
                 pass.set_index_buffer(geo.index_buf.slice(..));
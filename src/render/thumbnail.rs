@@ -0,0 +1,109 @@
+use crate::{
+    config::settings,
+    model::VisualModel,
+    render::{
+        body::{GpuBody, GpuStoreInit},
+        capture::Capture,
+        global, object, Batcher, PipelineKind, RenderFormats,
+    },
+    space::{Camera, OrbitController, Projection},
+};
+
+// Renders a single model offscreen, framed to fit, and returns RGBA pixels.
+pub fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    model: &VisualModel,
+    palette: &[[u8; 4]],
+    color: object::BodyColor,
+    light: &settings::Light,
+    extent: wgpu::Extent3d,
+) -> Vec<u8> {
+    let store_init = GpuStoreInit::new_dummy(device);
+    let global = global::Context::new(
+        device,
+        queue,
+        store_init.resource(),
+        None,
+        None,
+        RenderFormats::default(),
+        1,
+    );
+    let object = object::Context::new(device, queue, palette, &global);
+
+    let center = cgmath::vec3(0.0, 0.0, 0.0);
+    let mut cam = Camera {
+        loc: center,
+        rot: cgmath::One::one(),
+        proj: Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect: extent.width as f32 / extent.height as f32,
+            near: 1.0,
+            far: 1000.0,
+        }),
+        jitter: (0.0, 0.0),
+    };
+    OrbitController {
+        target: center,
+        distance: 1.0,
+        yaw: cgmath::Rad(0.5),
+        pitch: cgmath::Angle::turn_div_6(),
+    }
+    .apply(&mut cam);
+    cam.frame_bounds(center, model.body.bbox.radius);
+
+    let constants = global::Constants::new(&cam, light, None, &global::Time::default(), None);
+    queue.write_buffer(&global.uniform_buf, 0, bytemuck::bytes_of(&constants));
+
+    let mut batcher = Batcher::new();
+    let transform = cgmath::Decomposed {
+        scale: 1.0,
+        disp: center,
+        rot: cgmath::One::one(),
+    };
+    batcher.add_model(model, &transform, None, &GpuBody::ZERO, color);
+    batcher.prepare(device);
+
+    let capture = Capture::new(device, extent, global.color_format);
+    let depth = device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Thumbnail depth"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: global.depth_format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Thumbnail"),
+    });
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &capture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        pass.set_bind_group(0, &global.bind_group, &[]);
+        pass.set_bind_group(1, &object.bind_group, &[]);
+        batcher.draw(&mut pass, &object.pipelines, PipelineKind::Main);
+    }
+    let pending = capture.read(&mut encoder, device);
+    queue.submit(Some(encoder.finish()));
+    pending.wait(device)
+}
@@ -0,0 +1,99 @@
+use crate::{
+    render::body::{GpuBody, GpuStoreMirror},
+    space::Transform,
+};
+
+use cgmath::VectorSpace as _;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: f32,
+    pub transform: Transform,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Trajectory {
+    pub samples: Vec<Sample>,
+}
+
+impl Trajectory {
+    pub fn duration(&self) -> f32 {
+        self.samples.last().map_or(0.0, |s| s.time)
+    }
+
+    pub fn sample(&self, time: f32) -> Option<Transform> {
+        let next = self.samples.iter().position(|s| s.time > time);
+        let (a, b) = match next {
+            None => return self.samples.last().map(|s| s.transform),
+            Some(0) => return Some(self.samples[0].transform),
+            Some(i) => (&self.samples[i - 1], &self.samples[i]),
+        };
+        let t = (time - a.time) / (b.time - a.time);
+        Some(Transform {
+            disp: a.transform.disp.lerp(b.transform.disp, t),
+            rot: a.transform.rot.nlerp(b.transform.rot, t),
+            scale: a.transform.scale + (b.transform.scale - a.transform.scale) * t,
+        })
+    }
+}
+
+pub struct TrajectoryRecorder {
+    bodies: Vec<GpuBody>,
+    tracks: Vec<Trajectory>,
+    time: f32,
+}
+
+impl TrajectoryRecorder {
+    pub fn new(bodies: &[GpuBody]) -> Self {
+        TrajectoryRecorder {
+            bodies: bodies.to_vec(),
+            tracks: bodies.iter().map(|_| Trajectory::default()).collect(),
+            time: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, delta: f32, mirror: &GpuStoreMirror) {
+        self.record_with(delta, |body| mirror.get(body).cloned());
+    }
+
+    // Appends a sample for every body known to `get`. The samples are kept
+    // even if the body didn't move, so that the playback rests with it.
+    pub fn record_with<F: Fn(&GpuBody) -> Option<Transform>>(&mut self, delta: f32, get: F) {
+        self.time += delta;
+        for (body, track) in self.bodies.iter().zip(self.tracks.iter_mut()) {
+            // The mirror lags behind the simulation, so it may not have
+            // the body yet, or it may still hold the previous frame.
+            if let Some(transform) = get(body) {
+                track.samples.push(Sample {
+                    time: self.time,
+                    transform,
+                });
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<Trajectory> {
+        self.tracks
+    }
+}
+
+pub struct TrajectoryPlayer {
+    track: Trajectory,
+    time: f32,
+}
+
+impl TrajectoryPlayer {
+    pub fn new(track: Trajectory) -> Self {
+        let time = track.samples.first().map_or(0.0, |s| s.time);
+        TrajectoryPlayer { track, time }
+    }
+
+    pub fn advance(&mut self, delta: f32) -> Option<Transform> {
+        self.time += delta;
+        self.track.sample(self.time)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.time >= self.track.duration()
+    }
+}
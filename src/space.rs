@@ -1,8 +1,20 @@
+use crate::level::Level;
+
 use cgmath::{EuclideanSpace as _, InnerSpace as _, Rotation as _, Rotation3 as _, Transform as _};
 use std::ops::Range;
 
 pub type Transform = cgmath::Decomposed<cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
 
+// Thin wrappers around `cgmath::Transform`, returning `None` for a zero scale.
+pub fn inverse(t: &Transform) -> Option<Transform> {
+    t.inverse_transform()
+}
+
+pub fn world_to_local(t: &Transform, point: cgmath::Point3<f32>) -> Option<cgmath::Point3<f32>> {
+    t.inverse_transform().map(|inv| inv.transform_point(point))
+}
+
+#[derive(Serialize, Deserialize)]
 pub enum Projection {
     Ortho {
         p: cgmath::Ortho<f32>,
@@ -48,6 +60,19 @@ impl Projection {
         }
     }
 
+    pub fn set_depth_range(&mut self, depth: Range<f32>) {
+        match *self {
+            Projection::Ortho { ref mut p, .. } => {
+                p.near = depth.start;
+                p.far = depth.end;
+            }
+            Projection::Perspective(ref mut p) => {
+                p.near = depth.start;
+                p.far = depth.end;
+            }
+        }
+    }
+
     pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
         match *self {
             Projection::Ortho { p, .. } => p.into(),
@@ -56,10 +81,38 @@ impl Projection {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Camera {
     pub loc: cgmath::Vector3<f32>,
     pub rot: cgmath::Quaternion<f32>,
     pub proj: Projection,
+    // Sub-pixel offset of the projection, in NDC units.
+    #[serde(skip)]
+    pub jitter: (f32, f32),
+}
+
+// Element of the low-discrepancy Halton sequence, in [0, 1) range.
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// Sub-pixel jitter for the given frame, cycling through `count` samples
+// of the Halton (2, 3) sequence. Returns the offset in NDC units.
+pub fn jitter_offset(frame: u32, count: u32, screen_size: (u32, u32)) -> (f32, f32) {
+    let index = frame % count + 1;
+    let x = halton(index, 2) - 0.5;
+    let y = halton(index, 3) - 0.5;
+    (
+        2.0 * x / screen_size.0 as f32,
+        2.0 * y / screen_size.1 as f32,
+    )
 }
 
 #[derive(Debug)]
@@ -67,6 +120,52 @@ pub struct Follow {
     pub transform: Transform,
     pub speed: f32,
     pub fix_z: bool,
+    // Replaces the exponential approach by `speed` when set.
+    pub spring: Option<Spring>,
+}
+
+// Critically damped spring, which doesn't jitter on the asynchronously
+// updated transforms of the GPU bodies.
+#[derive(Clone, Debug)]
+pub struct Spring {
+    pub stiffness: f32,
+    velocity: Option<cgmath::Vector3<f32>>,
+}
+
+impl Spring {
+    pub fn new(stiffness: f32) -> Self {
+        Spring {
+            stiffness,
+            velocity: None,
+        }
+    }
+
+    // Jumps straight to the goal on the next update.
+    pub fn reset(&mut self) {
+        self.velocity = None;
+    }
+
+    fn update(
+        &mut self,
+        current: cgmath::Vector3<f32>,
+        goal: cgmath::Vector3<f32>,
+        dt: f32,
+    ) -> cgmath::Vector3<f32> {
+        match self.velocity {
+            Some(ref mut velocity) => {
+                let omega = self.stiffness;
+                let k = (-omega * dt).exp();
+                let change = current - goal;
+                let temp = (*velocity + change * omega) * dt;
+                *velocity = (*velocity - temp * omega) * k;
+                goal + (change + temp) * k
+            }
+            None => {
+                self.velocity = Some(cgmath::vec3(0.0, 0.0, 0.0));
+                goal
+            }
+        }
+    }
 }
 
 pub struct Direction {
@@ -74,6 +173,256 @@ pub struct Direction {
     pub height: f32,
 }
 
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn from_camera(cam: &Camera) -> Self {
+        let mx = cam.get_view_proj();
+        let row = |i: usize| cgmath::vec4(mx.x[i], mx.y[i], mx.z[i], mx.w[i]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        // clip space depth is in [0, 1] range, so the near plane is just Z
+        let raw = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2];
+        let mut planes = [cgmath::Vector4::new(0.0, 0.0, 0.0, 0.0); 6];
+        for (plane, p) in planes.iter_mut().zip(raw.iter()) {
+            *plane = *p / p.truncate().magnitude();
+        }
+        Frustum { planes }
+    }
+
+    pub fn contains_sphere(&self, center: cgmath::Vector3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.truncate().dot(center) + p.w >= -radius)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    pub target: cgmath::Vector3<f32>,
+    pub distance: f32,
+    pub yaw: cgmath::Rad<f32>,
+    pub pitch: cgmath::Rad<f32>,
+}
+
+impl OrbitController {
+    const MAX_PITCH: cgmath::Rad<f32> = cgmath::Rad(1.5);
+    const MIN_DISTANCE: f32 = 0.1;
+
+    pub fn rotate(&mut self, dx: cgmath::Rad<f32>, dy: cgmath::Rad<f32>) {
+        self.yaw += dx;
+        self.pitch = cgmath::Rad(
+            (self.pitch + dy)
+                .0
+                .max(-Self::MAX_PITCH.0)
+                .min(Self::MAX_PITCH.0),
+        );
+    }
+
+    pub fn zoom(&mut self, amount: f32) {
+        self.distance = (self.distance * (-amount).exp()).max(Self::MIN_DISTANCE);
+    }
+
+    // Advances the yaw at a constant rate, keeping the pitch and distance.
+    pub fn auto_orbit(&mut self, dt: f32, speed: cgmath::Rad<f32>) {
+        use cgmath::Angle;
+        self.yaw = (self.yaw + speed * dt).normalize();
+    }
+
+    // Time of a full revolution of `auto_orbit` at the given speed.
+    pub fn period(speed: cgmath::Rad<f32>) -> f32 {
+        use cgmath::Angle;
+        cgmath::Rad::full_turn().0 / speed.0.abs()
+    }
+
+    pub fn apply(&self, cam: &mut Camera) {
+        use cgmath::Angle;
+        // the camera looks along -Z, so tilting it by a quarter turn
+        // makes it look horizontally along +Y
+        let tilt = cgmath::Rad::turn_div_4() - self.pitch;
+        cam.rot =
+            cgmath::Quaternion::from_angle_z(self.yaw) * cgmath::Quaternion::from_angle_x(tilt);
+        cam.loc = self.target - cam.dir() * self.distance;
+    }
+}
+
+// Unconstrained camera, moving along its local axes.
+#[derive(Clone, Debug)]
+pub struct FlyController {
+    pub yaw: cgmath::Rad<f32>,
+    pub pitch: cgmath::Rad<f32>,
+    pub speed: f32,
+    pub boost: f32,
+    // X = right, Y = forward, Z = up, in the [-1, 1] range
+    pub movement: cgmath::Vector3<f32>,
+    pub boosted: bool,
+}
+
+impl FlyController {
+    const MAX_PITCH: cgmath::Rad<f32> = cgmath::Rad(1.5);
+
+    // Starts looking at the same direction as the camera.
+    pub fn new(cam: &Camera, speed: f32) -> Self {
+        let dir = cam.dir();
+        FlyController {
+            yaw: cgmath::Rad((-dir.x).atan2(dir.y)),
+            pitch: cgmath::Rad((-dir.z).max(-1.0).min(1.0).asin()),
+            speed,
+            boost: 4.0,
+            movement: cgmath::vec3(0.0, 0.0, 0.0),
+            boosted: false,
+        }
+    }
+
+    pub fn rotate(&mut self, dx: cgmath::Rad<f32>, dy: cgmath::Rad<f32>) {
+        self.yaw += dx;
+        self.pitch = cgmath::Rad(
+            (self.pitch + dy)
+                .0
+                .max(-Self::MAX_PITCH.0)
+                .min(Self::MAX_PITCH.0),
+        );
+    }
+
+    pub fn update(&self, cam: &mut Camera, delta: f32) {
+        use cgmath::Angle;
+        // same orientation convention as `OrbitController`
+        let tilt = cgmath::Rad::turn_div_4() - self.pitch;
+        cam.rot =
+            cgmath::Quaternion::from_angle_z(self.yaw) * cgmath::Quaternion::from_angle_x(tilt);
+
+        let speed = if self.boosted {
+            self.speed * self.boost
+        } else {
+            self.speed
+        };
+        let offset = cam.rot * cgmath::Vector3::unit_x() * self.movement.x
+            + cam.dir() * self.movement.y
+            + cam.rot * cgmath::Vector3::unit_y() * self.movement.z;
+        cam.loc += offset * speed * delta;
+    }
+}
+
+// Input state for `CameraController`, gathered by the application.
+#[derive(Clone, Debug)]
+pub struct CameraInput {
+    // X = right, Y = forward, Z = up, in the [-1, 1] range
+    pub movement: cgmath::Vector3<f32>,
+    // mouse drag since the last update
+    pub look: (cgmath::Rad<f32>, cgmath::Rad<f32>),
+    // scroll since the last update, only used for orbiting
+    pub zoom: f32,
+    pub boosted: bool,
+}
+
+impl Default for CameraInput {
+    fn default() -> Self {
+        CameraInput {
+            movement: cgmath::vec3(0.0, 0.0, 0.0),
+            look: (cgmath::Rad(0.0), cgmath::Rad(0.0)),
+            zoom: 0.0,
+            boosted: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CameraMode {
+    Orbit(OrbitController),
+    Fly(FlyController),
+}
+
+// Camera driven by either orbiting around a target, or flying freely.
+// In the orbit mode, the movement input rotates around the target.
+pub struct CameraController {
+    pub cam: Camera,
+    pub mode: CameraMode,
+    // radians per second of the orbit rotation
+    pub orbit_speed: f32,
+    pub fly_speed: f32,
+    last_distance: f32,
+}
+
+impl CameraController {
+    // Starts orbiting around the target, keeping the camera location.
+    pub fn new(cam: Camera, target: cgmath::Vector3<f32>) -> Self {
+        let offset = target - cam.loc;
+        let distance = offset.magnitude();
+        let dir = if distance > 0.0 {
+            offset / distance
+        } else {
+            cam.dir()
+        };
+        let orbit = OrbitController {
+            target,
+            distance,
+            yaw: cgmath::Rad((-dir.x).atan2(dir.y)),
+            pitch: cgmath::Rad((-dir.z).max(-1.0).min(1.0).asin()),
+        };
+        let mut controller = CameraController {
+            cam,
+            mode: CameraMode::Orbit(orbit.clone()),
+            orbit_speed: 2.0,
+            fly_speed: 20.0,
+            last_distance: distance,
+        };
+        orbit.apply(&mut controller.cam);
+        controller
+    }
+
+    pub fn is_flying(&self) -> bool {
+        match self.mode {
+            CameraMode::Orbit(_) => false,
+            CameraMode::Fly(_) => true,
+        }
+    }
+
+    // Switches between the modes without moving the camera.
+    // The new orbit target is placed in front of the camera,
+    // at the distance the orbit had before flying.
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::Orbit(ref orbit) => {
+                self.last_distance = orbit.distance;
+                CameraMode::Fly(FlyController::new(&self.cam, self.fly_speed))
+            }
+            CameraMode::Fly(ref fly) => CameraMode::Orbit(OrbitController {
+                target: self.cam.loc + self.cam.dir() * self.last_distance,
+                distance: self.last_distance,
+                yaw: fly.yaw,
+                pitch: fly.pitch,
+            }),
+        };
+    }
+
+    pub fn update(&mut self, dt: f32, input: &CameraInput) -> Transform {
+        match self.mode {
+            CameraMode::Orbit(ref mut orbit) => {
+                let speed = cgmath::Rad(self.orbit_speed * dt);
+                orbit.rotate(
+                    input.look.0 + speed * input.movement.x,
+                    input.look.1 - speed * input.movement.y,
+                );
+                orbit.zoom(input.zoom);
+                orbit.apply(&mut self.cam);
+            }
+            CameraMode::Fly(ref mut fly) => {
+                fly.rotate(input.look.0, input.look.1);
+                fly.movement = input.movement;
+                fly.boosted = input.boosted;
+                fly.speed = self.fly_speed;
+                fly.update(&mut self.cam, dt);
+            }
+        }
+        Transform {
+            disp: self.cam.loc,
+            rot: self.cam.rot,
+            scale: 1.0,
+        }
+    }
+}
+
 impl Camera {
     pub fn dir(&self) -> cgmath::Vector3<f32> {
         self.rot * -cgmath::Vector3::unit_z()
@@ -93,9 +442,20 @@ impl Camera {
         proj.y.z = 0.5 * (proj.y.z + proj.y.w);
         proj.z.z = 0.5 * (proj.z.z + proj.z.w);
         proj.w.z = 0.5 * (proj.w.z + proj.w.w);
+        // shift the clip space, so that the offset is constant after the W division
+        for column in [&mut proj.x, &mut proj.y, &mut proj.z, &mut proj.w].iter_mut() {
+            column.x += self.jitter.0 * column.w;
+            column.y += self.jitter.1 * column.w;
+        }
         proj
     }
 
+    // The offset is in NDC units, see `jitter_offset` for converting
+    // the sub-pixel samples. It has to be undone by the resolve pass.
+    pub fn set_jitter(&mut self, offset: (f32, f32)) {
+        self.jitter = offset;
+    }
+
     fn view_transform(&self) -> Transform {
         cgmath::Decomposed {
             scale: 1.0,
@@ -110,6 +470,20 @@ impl Camera {
         self.get_proj_matrix() * view_mx
     }
 
+    // View-projection of the scene mirrored across the plane Z = `height`.
+    pub fn get_reflected_view_proj(&self, height: f32) -> cgmath::Matrix4<f32> {
+        let mut mirror = cgmath::Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0);
+        mirror.w.z = 2.0 * height;
+        self.get_view_proj() * mirror
+    }
+
+    pub fn cast_ray(&self, ndc: (f32, f32)) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        let inv_vp = self.get_view_proj().inverse_transform().unwrap();
+        let near = cgmath::Point3::from_homogeneous(inv_vp * cgmath::vec4(ndc.0, ndc.1, 0.0, 1.0));
+        let far = cgmath::Point3::from_homogeneous(inv_vp * cgmath::vec4(ndc.0, ndc.1, 1.0, 1.0));
+        (near, (far - near).normalize())
+    }
+
     fn intersect_ray_height(&self, dir: cgmath::Vector3<f32>, height: f32) -> cgmath::Point3<f32> {
         let t_raw = (height - self.loc.z) / dir.z;
         let range = self.depth_range();
@@ -170,7 +544,7 @@ impl Camera {
         ]
     }
 
-    pub fn follow(&mut self, target: &Transform, dt: f32, follow: &Follow) {
+    pub fn follow(&mut self, target: &Transform, dt: f32, follow: &mut Follow) {
         let new_target = if follow.fix_z {
             let z_axis = target.rot * cgmath::Vector3::unit_z();
             let adjust_quat = cgmath::Quaternion::from_arc(z_axis, cgmath::Vector3::unit_z(), None);
@@ -184,9 +558,13 @@ impl Camera {
         };
 
         let result = new_target.concat(&follow.transform);
-        let k = (dt * -follow.speed).exp();
-
-        self.loc = result.disp * (1.0 - k) + self.loc * k;
+        self.loc = match follow.spring {
+            Some(ref mut spring) => spring.update(self.loc, result.disp, dt),
+            None => {
+                let k = (dt * -follow.speed).exp();
+                result.disp * (1.0 - k) + self.loc * k
+            }
+        };
         self.rot = cgmath::Quaternion::look_at(
             (self.loc - target.disp).normalize(),
             cgmath::Vector3::unit_z(),
@@ -194,6 +572,47 @@ impl Camera {
         .invert();
     }
 
+    // Also fits the depth range to the radius, leaving room to zoom in and out.
+    pub fn frame_bounds(&mut self, center: cgmath::Vector3<f32>, radius: f32) {
+        use cgmath::Angle as _;
+        const MARGIN: f32 = 1.2;
+        const NEAR_FRACTION: f32 = 0.01;
+        const FAR_SCALE: f32 = 10.0;
+        let extent = radius * MARGIN;
+        let near = extent * NEAR_FRACTION;
+        let distance = match self.proj {
+            Projection::Perspective(ref pf) => {
+                let half_y = pf.fovy / 2.0;
+                let half_x = cgmath::Rad((half_y.tan() * pf.aspect).atan());
+                let half_min = if half_x < half_y { half_x } else { half_y };
+                extent / half_min.sin()
+            }
+            Projection::Ortho { ref mut p, .. } => {
+                let aspect = (p.right - p.left) / (p.bottom - p.top).abs();
+                let (half_w, half_h) = if aspect > 1.0 {
+                    (extent * aspect, extent)
+                } else {
+                    (extent, extent / aspect)
+                };
+                p.left = -half_w;
+                p.right = half_w;
+                p.top = half_h * (p.top - p.bottom).signum();
+                p.bottom = -p.top;
+                near + extent
+            }
+        };
+        self.proj
+            .set_depth_range(near..(distance + extent) * FAR_SCALE);
+        self.loc = center - self.dir() * distance;
+    }
+
+    pub fn clamp_to_level(&mut self, level: &Level, min_clearance: f32) {
+        let min_z = level.sample_height(self.loc.x, self.loc.y) + min_clearance;
+        if self.loc.z < min_z {
+            self.loc.z = min_z;
+        }
+    }
+
     pub fn look_by(&mut self, target: &Transform, dir: &Direction) {
         debug_assert!(dir.view.z < 0.0);
         let k = (target.disp.z - self.loc.z) / -dir.view.z;
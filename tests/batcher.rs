@@ -0,0 +1,74 @@
+use vangers::{
+    model::{BoundingBox, Mesh},
+    render::{body::GpuBody, object::BodyColor, Batcher},
+    space::Transform,
+};
+
+use cgmath::One as _;
+use std::sync::Arc;
+
+// Returns `None` on machines without a GPU adapter.
+fn create_device() -> Option<wgpu::Device> {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+    let adapter =
+        futures::executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::Default,
+            compatible_surface: None,
+        }))?;
+    let (device, _queue) = futures::executor::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            shader_validation: true,
+        },
+        None,
+    ))
+    .ok()?;
+    Some(device)
+}
+
+fn dummy_mesh(device: &wgpu::Device) -> Arc<Mesh> {
+    let buffer = |usage| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4,
+            usage,
+            mapped_at_creation: false,
+        })
+    };
+    Arc::new(Mesh {
+        num_vertices: 4,
+        vertex_buf: buffer(wgpu::BufferUsage::VERTEX),
+        num_indices: 6,
+        index_buf: buffer(wgpu::BufferUsage::INDEX),
+        offset: [0.0; 3],
+        bbox: BoundingBox {
+            min: [0.0; 3],
+            max: [0.0; 3],
+            radius: 0.0,
+        },
+        physics: m3d::Physics {
+            volume: 0.0,
+            rcm: [0.0; 3],
+            jacobi: [[0.0; 3]; 3],
+        },
+    })
+}
+
+#[test]
+fn debris_draw_calls() {
+    let device = match create_device() {
+        Some(device) => device,
+        None => return,
+    };
+    let meshes = (0..4).map(|_| dummy_mesh(&device)).collect::<Vec<_>>();
+
+    let mut batcher = Batcher::new();
+    for i in 0..200 {
+        let mesh = &meshes[i % meshes.len()];
+        batcher.add_debrie(mesh, &Transform::one(), &GpuBody::ZERO, BodyColor::Dummy);
+    }
+
+    assert_eq!(batcher.draw_call_count(), meshes.len());
+    assert_eq!(batcher.instance_count(), 200);
+}
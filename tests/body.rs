@@ -0,0 +1,70 @@
+use vangers::render::body::{pack_wheels, AllocError, GpuBody, SimSnapshot, MAX_WHEELS};
+
+fn wheels(count: usize) -> Vec<m3d::Wheel<()>> {
+    (0..count)
+        .map(|i| m3d::Wheel {
+            mesh: None,
+            steer: (i % 2) as u32,
+            pos: [i as f32, 1.0, 2.0],
+            width: 10,
+            radius: 5,
+            bound_index: 0,
+        })
+        .collect()
+}
+
+#[test]
+fn pack_wheels_steering() {
+    let packed = pack_wheels(&wheels(6)).unwrap();
+    assert_eq!(packed[0], [0.0, 1.0, 2.0, -1.0]);
+    assert_eq!(packed[5], [5.0, 1.0, 2.0, 1.0]);
+    for unused in packed[6..].iter() {
+        assert_eq!(unused[3], 0.0);
+    }
+}
+
+#[test]
+fn pack_wheels_rejects_excess() {
+    match pack_wheels(&wheels(MAX_WHEELS + 1)) {
+        Err(AllocError::TooManyWheels { count, max }) => {
+            assert_eq!((count, max), (MAX_WHEELS + 1, MAX_WHEELS));
+        }
+        other => panic!(
+            "Extra wheels should be rejected, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
+
+// A single body at (1, 2, 3), written the way `ron` serializes it.
+fn snapshot_text() -> String {
+    let zero = "(0.0, 0.0, 0.0, 0.0)";
+    let wheels = [zero; MAX_WHEELS].join(", ");
+    let data = format!(
+        "(control: {z}, engine: {z}, pos_scale: (1.0, 2.0, 3.0, 1.0), \
+         orientation: (0.0, 0.0, 0.0, 1.0), linear: {z}, angular: {z}, collision: {z}, \
+         model: (jacobi0: {z}, jacobi1: {z}, jacobi2: {z}), \
+         physics: (scale: {z}, mobility_ship: {z}, speed: {z}), \
+         wheels: ({w}), flags: (0, 1, 0, 0))",
+        z = zero,
+        w = wheels,
+    );
+    format!(
+        "(free_list: (epochs: [1], free: []), data: [{}], spawns: [])",
+        data
+    )
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let snapshot: SimSnapshot = ron::de::from_str(&snapshot_text()).unwrap();
+    let text = ron::ser::to_string(&snapshot).unwrap();
+    let back: SimSnapshot = ron::de::from_str(&text).unwrap();
+
+    assert_eq!(back.body_count(), 1);
+    let transform = back.transform(&GpuBody::ZERO).unwrap();
+    assert_eq!(transform.disp, cgmath::vec3(1.0, 2.0, 3.0));
+    assert_eq!(transform.scale, 1.0);
+    let original = snapshot.transform(&GpuBody::ZERO).unwrap();
+    assert_eq!(transform.rot, original.rot);
+}
@@ -0,0 +1,53 @@
+use m3d::{DrawTriangle, Geometry, Vertex};
+
+fn subdivided_plane(size: u16) -> Geometry<DrawTriangle> {
+    let index = |x: u16, y: u16| y * (size + 1) + x;
+    let vertex = |pos| Vertex { pos, normal: 0 };
+    let mut positions = Vec::new();
+    let mut polygons = Vec::new();
+    for y in 0..=size {
+        for x in 0..=size {
+            positions.push([x as i8 * 10, y as i8 * 10, 0]);
+        }
+    }
+    for y in 0..size {
+        for x in 0..size {
+            let (a, b) = (index(x, y), index(x + 1, y));
+            let (c, d) = (index(x + 1, y + 1), index(x, y + 1));
+            for &[i, j, k] in &[[a, b, c], [a, c, d]] {
+                polygons.push(DrawTriangle {
+                    vertices: [vertex(i), vertex(j), vertex(k)],
+                    flat_normal: [0, 0, 124],
+                    material: [1, 0],
+                });
+            }
+        }
+    }
+    Geometry {
+        positions,
+        normals: vec![[0, 0, 124]],
+        polygons,
+    }
+}
+
+#[test]
+fn decimate_plane_half() {
+    let plane = subdivided_plane(8);
+    let result = plane.decimate(0.5);
+
+    let expected = plane.polygons.len() / 2;
+    assert!(
+        result.polygons.len() <= expected && result.polygons.len() + 4 >= expected,
+        "got {} triangles out of {}",
+        result.polygons.len(),
+        plane.polygons.len()
+    );
+    assert!(result.positions.len() < plane.positions.len());
+    for pos in result.positions.iter() {
+        assert_eq!(pos[2], 0);
+    }
+    for polygon in result.polygons.iter() {
+        assert_eq!(polygon.flat_normal, [0, 0, 124]);
+        assert_eq!(polygon.material, [1, 0]);
+    }
+}
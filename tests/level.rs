@@ -0,0 +1,442 @@
+use vangers::level::{
+    decompress_rows, raycast, read_vmc_tables, Level, LevelConfig, LevelData, LoadPhase, Power,
+    TerrainConfig, ALTITUDE_FRACTION_BITS, DOUBLE_LEVEL, HEIGHT_SCALE,
+};
+use vangers::render::terrain::Rect;
+
+use std::path::{Path, PathBuf};
+
+fn raised_level(alt: u8) -> Level {
+    let mut level = Level::new_test();
+    for h in level.height.iter_mut() {
+        *h = alt;
+    }
+    level
+}
+
+// Unique directory for the files of a test, so that parallel runs don't collide.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vangers-test-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Config of a compressed 4x4 level with a blank palette, stored in `dir`.
+fn test_config(dir: &Path) -> LevelConfig {
+    let path_palette = dir.join("level.pal");
+    std::fs::write(&path_palette, vec![0u8; 0x300]).unwrap();
+    LevelConfig {
+        path_palette,
+        path_data: dir.join("level"),
+        is_compressed: true,
+        size: (Power(2), Power(2)),
+        geo: Power(1),
+        section: Power(1),
+        min_square: Power(0),
+        terrains: (0..8)
+            .map(|_| TerrainConfig {
+                shadow_offset: 0,
+                height_shift: 0,
+                colors: 0..1,
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn raycast_straight_down() {
+    let level = raised_level(100);
+    let ground = 100.0 * HEIGHT_SCALE as f32 / 255.0;
+    let origin = cgmath::Point3::new(1.5, 0.5, 200.0);
+    let (x, y, dist) = raycast(&level, origin, -cgmath::Vector3::unit_z(), 1000.0).unwrap();
+    assert_eq!((x, y), (1, 0));
+    assert!((dist - (200.0 - ground)).abs() < 1e-3);
+}
+
+#[test]
+fn raycast_max_distance() {
+    let level = raised_level(100);
+    let origin = cgmath::Point3::new(0.5, 0.5, 200.0);
+    assert_eq!(
+        raycast(&level, origin, -cgmath::Vector3::unit_z(), 50.0),
+        None
+    );
+}
+
+#[test]
+fn raycast_wrapping() {
+    let level = raised_level(0);
+    let origin = cgmath::Point3::new(-2.5, -0.5, 10.0);
+    let dir = cgmath::Vector3::new(-1.0, 0.0, -1.0);
+    let (x, y, dist) = raycast(&level, origin, cgmath::InnerSpace::normalize(dir), 100.0).unwrap();
+    assert_eq!((x, y), (1, 0));
+    assert!((dist - 10.0 * 2f32.sqrt()).abs() < 1e-3);
+}
+
+#[test]
+fn level_raycast() {
+    use cgmath::InnerSpace as _;
+
+    let mut level = raised_level(100);
+    level.meta = vec![3 << 3, 3 << 3];
+    let ground = 100.0 * HEIGHT_SCALE as f32 / 255.0;
+    let origin = cgmath::vec3(0.5, 0.5, ground + 10.0);
+    let dir = cgmath::vec3(1.0, 0.0, -1.0).normalize();
+    let (point, ty) = level.raycast(origin, dir).unwrap();
+    assert!((point - cgmath::vec3(10.5, 0.5, ground)).magnitude() < 1e-3);
+    assert_eq!(ty, 3);
+    assert_eq!(level.raycast(origin, cgmath::Vector3::unit_z()), None);
+    // horizontal rays stop after a lap around the level
+    assert_eq!(level.raycast(origin, cgmath::vec3(1.0, 0.3, 0.0)), None);
+    let above = cgmath::vec3(0.5, 0.5, HEIGHT_SCALE as f32);
+    assert_eq!(level.raycast(above, cgmath::Vector3::unit_x()), None);
+
+    // the low layer is only reachable from below the high one
+    level.height = vec![10, 200];
+    level.meta = vec![DOUBLE_LEVEL | 2 << 3 | 1, DOUBLE_LEVEL | 5 << 3];
+    let down = -cgmath::Vector3::unit_z();
+    let (point, ty) = level.raycast(cgmath::vec3(0.5, 0.5, 150.0), down).unwrap();
+    assert!((point.z - 200.0 * HEIGHT_SCALE as f32 / 255.0).abs() < 1e-3);
+    assert_eq!(ty, 5);
+    let (point, ty) = level.raycast(cgmath::vec3(1.5, 0.5, 8.0), down).unwrap();
+    assert!((point.z - 10.0 * HEIGHT_SCALE as f32 / 255.0).abs() < 1e-3);
+    assert_eq!(ty, 2);
+}
+
+#[test]
+fn vmc_round_trip() {
+    let size = (8, 3);
+    let data = LevelData {
+        height: (0..24).map(|i| (i * 37) as u8).collect(),
+        meta: (0..24).map(|i| (i * 11 + 5) as u8).collect(),
+        size,
+    };
+    let path = std::env::temp_dir().join("vangers-test-round-trip.vmc");
+    data.save_vmc(&path).unwrap();
+
+    let mut vmc = std::fs::File::open(&path).unwrap();
+    let (st_table, sz_table) = read_vmc_tables(&mut vmc, size.1);
+    let (height, meta) = decompress_rows(vmc, &st_table, &sz_table, size);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(height, data.height);
+    assert_eq!(meta, data.meta);
+}
+
+#[test]
+fn sample_normal_flat() {
+    let up = cgmath::Vector3::unit_z();
+    assert_eq!(Level::new_test().sample_normal(0.5, 0.5), up);
+    // wrapped around the edges
+    assert_eq!(raised_level(100).sample_normal(-3.25, 7.5), up);
+}
+
+#[test]
+fn tile_world_round_trip() {
+    let level = raised_level(100);
+    let tile = level.world_to_tile(cgmath::Point3::new(-2.3, 5.7, 0.0));
+    assert_eq!(tile, (1, 0));
+    let pos = level.tile_to_world(tile);
+    assert_eq!(level.world_to_tile(pos), tile);
+    assert!((pos.z - 100.0 * HEIGHT_SCALE as f32 / 255.0).abs() < 1e-3);
+}
+
+#[test]
+fn sample_height_tile_centers() {
+    let mut level = Level::new_test();
+    level.height = vec![40, 80];
+    for &tile in &[(0, 0), (1, 0), (-1, 3)] {
+        let pos = level.tile_to_world(tile);
+        assert!((level.sample_height(pos.x, pos.y) - pos.z).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn dual_texels() {
+    let mut level = Level::new_test();
+    level.height = vec![10, 200];
+    level.meta = vec![DOUBLE_LEVEL, DOUBLE_LEVEL | 1];
+    assert_eq!(level.dual_mask(), vec![true, true]);
+    assert_eq!(&level.export_dual()[..4], &[10, 200, 0, 0xFF]);
+
+    let level = raised_level(100);
+    assert_eq!(level.dual_mask(), vec![false, false]);
+    assert_eq!(&level.export_dual()[..4], &[100, 100, 100, 0xFF]);
+}
+
+#[test]
+fn triangulate_region() {
+    let level = raised_level(255);
+    let mesh = level.triangulate(None);
+    assert_eq!(mesh.positions.len(), 3 * 2);
+    assert_eq!(mesh.colors.len(), mesh.positions.len());
+    assert_eq!(mesh.indices.len(), 2 * 6);
+    assert_eq!(mesh.positions[5], [2.0, 1.0, HEIGHT_SCALE as f32]);
+
+    let rect = Rect {
+        x: 1,
+        y: 0,
+        w: 4,
+        h: 3,
+    };
+    let mesh = level.triangulate(Some(rect));
+    assert_eq!(mesh.positions.len(), 5 * 4);
+    assert_eq!(mesh.indices.len(), 4 * 3 * 6);
+    assert!(mesh
+        .indices
+        .iter()
+        .all(|&i| (i as usize) < mesh.positions.len()));
+}
+
+#[test]
+fn flood_level_wrapping() {
+    let mut level = Level::new_test();
+    assert_eq!(level.flood_level_at(0.5), 0.0);
+    level.flood_map = vec![255];
+    assert_eq!(level.flood_level_at(-3.5), HEIGHT_SCALE as f32);
+}
+
+#[test]
+fn diff_single_tile() {
+    let original = raised_level(10);
+    let mut edited = raised_level(10);
+    edited.height[1] = 20;
+    let diff = original.diff(&edited);
+    assert_eq!(diff.len(), 1);
+    let (coord, ref change) = diff[0];
+    assert_eq!(coord, (1, 0));
+    assert!(change.before.top() < change.after.top());
+}
+
+#[test]
+fn load_keeps_powers() {
+    let dir = test_dir("powers");
+    let mut config = test_config(&dir);
+    config.is_compressed = false;
+    config.section = Power(2);
+    std::fs::write(config.path_data.with_extension("vmp"), vec![0u8; 2 * 4 * 4]).unwrap();
+
+    let level = vangers::level::load(&config);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(level.geo_power, config.geo.as_power() as usize);
+    assert_eq!(
+        level.flood_section_power,
+        config.section.as_power() as usize
+    );
+    assert_eq!(level.flood_map.len(), 1);
+}
+
+#[test]
+fn max_mip_chain() {
+    let mut level = Level::new_test();
+    level.size = (4, 2);
+    level.height = vec![1, 7, 3, 2, 5, 0, 9, 4];
+    level.meta = vec![0; 8];
+    let mips = level.compute_max_mip(4);
+    assert_eq!(mips.len(), 4);
+    assert_eq!(mips[0], level.height);
+    assert_eq!(mips[1], vec![7, 9]);
+    assert_eq!(mips[2], vec![9]);
+    assert_eq!(mips[3], vec![9]);
+}
+
+#[test]
+fn terrain_at_wrapping() {
+    let mut level = Level::new_test();
+    for &(x, y) in &[(0.5, 0.5), (1.9, 0.2), (-3.5, 7.0)] {
+        assert_eq!(level.terrain_at(x, y), 0);
+    }
+    level.meta[1] = 5 << 3;
+    assert_eq!(level.terrain_at(1.5, 0.5), 5);
+    assert_eq!(level.terrain_at(-0.5, -2.5), 5);
+    assert_eq!(level.terrain_at(0.5, 0.5), 0);
+}
+
+#[test]
+fn save_round_trip() {
+    let dir = test_dir("save");
+    let config = test_config(&dir);
+    let mut level = Level::new_test();
+    level.size = (4, 4);
+    level.height = (0..16).map(|i| (i * 13) as u8).collect();
+    level.meta = (0..16).map(|i| (i * 7 + 1) as u8).collect();
+    level.flood_map = vec![3, 200];
+    level.save(&config).unwrap();
+
+    let loaded = vangers::level::load(&config);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(loaded.height, level.height);
+    assert_eq!(loaded.meta, level.meta);
+    assert_eq!(loaded.flood_map, level.flood_map);
+}
+
+#[test]
+fn save_keeps_other_vpr_tables() {
+    let dir = test_dir("save-vpr");
+    let config = test_config(&dir);
+    let mut level = Level::new_test();
+    level.size = (4, 4);
+    level.height = vec![0; 16];
+    level.meta = vec![0; 16];
+    level.flood_map = vec![0, 0];
+    level.save(&config).unwrap();
+
+    // Fill the tables in front of the flood map with a pattern.
+    let path_vpr = config.path_data.with_extension("vpr");
+    let mut vpr = std::fs::read(&path_vpr).unwrap();
+    let flood_offset = vpr.len() - level.flood_map.len() * 4;
+    for (i, b) in vpr[..flood_offset].iter_mut().enumerate() {
+        *b = i as u8 | 1;
+    }
+    std::fs::write(&path_vpr, &vpr).unwrap();
+
+    level.flood_map = vec![5, 7];
+    level.save(&config).unwrap();
+    let saved = std::fs::read(&path_vpr).unwrap();
+    let loaded = vangers::level::load(&config);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(saved.len(), vpr.len());
+    assert_eq!(saved[..flood_offset], vpr[..flood_offset]);
+    assert_eq!(loaded.flood_map, level.flood_map);
+}
+
+#[test]
+fn load_reports_progress() {
+    let dir = test_dir("progress");
+    let config = test_config(&dir);
+    let mut level = Level::new_test();
+    level.size = (4, 4);
+    level.height = vec![0; 16];
+    level.meta = vec![0; 16];
+    level.flood_map = vec![0, 0];
+    level.save(&config).unwrap();
+
+    let mut reports = Vec::new();
+    vangers::level::load_with_progress(&config, &mut |phase, ratio| reports.push((phase, ratio)));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        reports,
+        vec![
+            (LoadPhase::Vmc, 0.0),
+            (LoadPhase::Vmc, 1.0),
+            (LoadPhase::Vpr, 0.0),
+            (LoadPhase::Vpr, 1.0),
+            (LoadPhase::Palette, 0.0),
+            (LoadPhase::Palette, 1.0),
+        ]
+    );
+}
+
+#[test]
+fn png_round_trip() {
+    let dir = test_dir("export");
+    let path = dir.join("level.png");
+    let mut level = Level::new_test();
+    level.size = (8, 1);
+    level.height = vec![30, 90, 42, 42, 60, 60, 17, 200];
+    level.meta = vec![
+        DOUBLE_LEVEL | 2 << 3 | 1,
+        DOUBLE_LEVEL | 5 << 3 | 3 | 0x80,
+        7 << 3 | 0x80,
+        0x4 | 2,
+        // dual-level with equal altitudes and no delta
+        DOUBLE_LEVEL | 1 << 3,
+        DOUBLE_LEVEL | 1 << 3 | 0x4,
+        0x80 | 0x4 | 1,
+        3 << 3,
+    ];
+    #[cfg(feature = "altitude16")]
+    {
+        level.height_fine = vec![0, 255, 1, 128, 0, 0, 77, 3];
+    }
+    level.export_png(&path).unwrap();
+    assert!(level
+        .export_png(&dir.join("missing").join("level.png"))
+        .is_err());
+
+    let mut loaded = Level::new_test();
+    loaded.size = level.size;
+    loaded.height = vec![0; 8];
+    loaded.meta = vec![0; 8];
+    #[cfg(feature = "altitude16")]
+    {
+        loaded.height_fine = vec![0; 8];
+    }
+    loaded.import_png(&path).unwrap();
+    // the size has to match
+    let mut small = Level::new_test();
+    assert!(small.import_png(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(loaded.height, level.height);
+    assert_eq!(loaded.meta, level.meta);
+    #[cfg(feature = "altitude16")]
+    assert_eq!(loaded.height_fine, level.height_fine);
+}
+
+#[test]
+fn sample_height_bilinear() {
+    let mut level = Level::new_test();
+    level.height = vec![40, 80];
+    let scale = HEIGHT_SCALE as f32 / 255.0;
+    assert!((level.sample_height(0.25, 0.0) - 50.0 * scale).abs() < 1e-3);
+    assert!((level.sample_height(-1.0, 0.7) - 60.0 * scale).abs() < 1e-3);
+
+    level.height = vec![10, 200];
+    level.meta = vec![DOUBLE_LEVEL, DOUBLE_LEVEL];
+    assert!((level.sample_height_near(0.5, 0.0, 0.0) - 10.0 * scale).abs() < 1e-3);
+    assert!((level.sample_height_near(0.5, 0.0, 1000.0) - 200.0 * scale).abs() < 1e-3);
+    assert_eq!(
+        level.sample_height(0.5, 0.0),
+        level.sample_height_near(0.5, 0.0, 1000.0)
+    );
+}
+
+#[test]
+fn sample_height_near_dual_border() {
+    let mut level = Level::new_test();
+    level.size = (4, 1);
+    level.height = vec![40, 40, 10, 200];
+    #[cfg(feature = "altitude16")]
+    {
+        level.height_fine = vec![0; 4];
+    }
+    level.meta = vec![0, 0, DOUBLE_LEVEL, DOUBLE_LEVEL];
+    let scale = HEIGHT_SCALE as f32 / 255.0;
+    // the center of a single tile next to a dual one ignores the dual layers
+    assert!((level.sample_height_near(1.5, 0.5, 1000.0) - 40.0 * scale).abs() < 1e-3);
+    assert!((level.sample_height_near(2.5, 0.5, 0.0) - 10.0 * scale).abs() < 1e-3);
+    assert!((level.sample_height_near(2.5, 0.5, 1000.0) - 200.0 * scale).abs() < 1e-3);
+}
+
+#[test]
+fn water_level_sections() {
+    let mut level = Level::new_test();
+    level.size = (2, 4);
+    level.flood_section_power = 1;
+    level.flood_map = vec![10, 30];
+    assert_eq!(level.water_level(1) >> ALTITUDE_FRACTION_BITS, 10);
+    assert_eq!(level.water_level(2) >> ALTITUDE_FRACTION_BITS, 30);
+    assert_eq!(level.water_level(-1) >> ALTITUDE_FRACTION_BITS, 30);
+    assert_eq!(level.water_level(4), level.water_level(0));
+
+    let alt = 20 << ALTITUDE_FRACTION_BITS;
+    assert!(!level.is_underwater((0, 0), alt));
+    assert!(level.is_underwater((1, 3), alt));
+}
+
+#[test]
+fn config_reports_missing_keys() {
+    let path = std::env::temp_dir().join("vangers-test-config.ini");
+    std::fs::write(&path, "[Global Parameters]\nGeoNet Power = 5\n").unwrap();
+    let result = LevelConfig::try_load(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    match result {
+        Err(reason) => assert!(reason.contains("Shadow Offsets"), "{}", reason),
+        Ok(_) => panic!("Loaded an incomplete config"),
+    }
+}
@@ -0,0 +1,137 @@
+use m3d::{
+    Bounds, CollisionQuad, DrawMesh, DrawTriangle, Geometry, IndexedVertex, M3dError, Mesh,
+    Physics, Vertex,
+};
+
+fn quad_mesh() -> DrawMesh {
+    let vertex = |pos, normal| Vertex { pos, normal };
+    Mesh {
+        geometry: Geometry {
+            positions: vec![[-10, -10, 0], [10, -10, 0], [10, 10, 5], [-10, 10, 5]],
+            normals: vec![[0, 0, 124], [0, -30, 120]],
+            polygons: vec![
+                DrawTriangle {
+                    vertices: [vertex(0, 0), vertex(1, 0), vertex(2, 1)],
+                    flat_normal: [0, -15, 123],
+                    material: [1, 0],
+                },
+                DrawTriangle {
+                    vertices: [vertex(0, 0), vertex(2, 1), vertex(3, 1)],
+                    flat_normal: [0, -15, 123],
+                    material: [7, 3],
+                },
+            ],
+        },
+        bounds: Bounds {
+            coord_min: [-10, -10, 0],
+            coord_max: [10, 10, 5],
+        },
+        parent_off: [1, 2, 3],
+        parent_rot: [0, 90, 0],
+        max_radius: 15,
+        physics: Physics {
+            volume: 2.5,
+            rcm: [0.0, 0.5, 1.0],
+            jacobi: [[1.0, 0.0, 0.25], [0.0, 2.0, 0.0], [0.25, 0.0, 3.0]],
+        },
+    }
+}
+
+#[test]
+fn mesh_round_trip() {
+    let mesh = quad_mesh();
+    let mut data = Vec::new();
+    mesh.save(&mut data);
+
+    let mut source = &data[..];
+    let loaded: DrawMesh = Mesh::load(&mut source).unwrap();
+    assert!(source.is_empty());
+
+    let (a, b) = (&mesh.geometry, &loaded.geometry);
+    assert_eq!(a.positions, b.positions);
+    assert_eq!(a.normals, b.normals);
+    assert_eq!(a.polygons.len(), b.polygons.len());
+    for (pa, pb) in a.polygons.iter().zip(b.polygons.iter()) {
+        for (va, vb) in pa.vertices.iter().zip(pb.vertices.iter()) {
+            assert_eq!((va.pos, va.normal), (vb.pos, vb.normal));
+        }
+        assert_eq!(pa.flat_normal, pb.flat_normal);
+        assert_eq!(pa.material, pb.material);
+    }
+    assert_eq!(mesh.bounds.coord_min, loaded.bounds.coord_min);
+    assert_eq!(mesh.bounds.coord_max, loaded.bounds.coord_max);
+    assert_eq!(mesh.parent_off, loaded.parent_off);
+    assert_eq!(mesh.parent_rot, loaded.parent_rot);
+    assert_eq!(mesh.max_radius, loaded.max_radius);
+    assert_eq!(mesh.physics.volume, loaded.physics.volume);
+    assert_eq!(mesh.physics.rcm, loaded.physics.rcm);
+    assert_eq!(mesh.physics.jacobi, loaded.physics.jacobi);
+}
+
+#[test]
+fn quads_as_triangles() {
+    let mesh = quad_mesh().map(|geometry| Geometry {
+        positions: geometry.positions,
+        normals: geometry.normals,
+        polygons: vec![CollisionQuad {
+            vertices: [0, 1, 2, 3],
+            middle: [0, 0, 2],
+            flat_normal: [0, -15, 123],
+        }],
+    });
+    let mut data = Vec::new();
+    mesh.save(&mut data);
+
+    let loaded: DrawMesh = Mesh::load(&mut &data[..]).unwrap();
+    let corners = loaded
+        .geometry
+        .polygons
+        .iter()
+        .map(|p| [p.vertices[0].pos, p.vertices[1].pos, p.vertices[2].pos])
+        .collect::<Vec<_>>();
+    assert_eq!(corners, vec![[0, 1, 2], [0, 2, 3]]);
+    for p in loaded.geometry.polygons.iter() {
+        assert_eq!(p.flat_normal, [0, -15, 123]);
+    }
+}
+
+#[test]
+fn malformed_mesh_errors() {
+    let mut data = Vec::new();
+    quad_mesh().save(&mut data);
+
+    let truncated = &data[..data.len() / 2];
+    match Mesh::<Geometry<DrawTriangle>>::load(&mut &truncated[..]) {
+        Err(M3dError::Io(_)) => {}
+        _ => panic!("Truncated mesh should fail to read"),
+    }
+
+    data[0] = 7;
+    match Mesh::<Geometry<DrawTriangle>>::load(&mut &data[..]) {
+        Err(M3dError::BadMagic { found: 7, .. }) => {}
+        _ => panic!("Unexpected version should be reported"),
+    }
+}
+
+#[test]
+fn compact_shares_vertices() {
+    let mesh = quad_mesh();
+    let (vertices, indices) = mesh.geometry.compact().unwrap();
+    assert_eq!(indices.len(), mesh.geometry.index_count());
+    // the two triangles have different colors, so nothing is shared
+    assert_eq!(vertices.len(), indices.len());
+
+    let mut geometry = mesh.geometry;
+    geometry.polygons[1].material = geometry.polygons[0].material;
+    let (vertices, indices) = geometry.compact().unwrap();
+    assert_eq!(vertices.len(), 4);
+    assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+    assert_eq!(
+        vertices[3],
+        IndexedVertex {
+            pos: 3,
+            normal: 1,
+            color: 1,
+        }
+    );
+}
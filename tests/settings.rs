@@ -3,3 +3,33 @@ fn load_settings() {
     let file = std::fs::File::open("config/settings.template.ron").unwrap();
     ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
 }
+
+#[test]
+fn tuning_partial_profile() {
+    use vangers::config::tuning::{Tuning, TuningError, TUNING_VERSION};
+
+    let source = "(version: 1, common: (drag: (z: Some(2.5))), cars: { \"Mechos\": (scale_size: Some(0.5)) })";
+    let tuning: Tuning = source.parse().unwrap();
+    assert_eq!(tuning.version, TUNING_VERSION);
+    assert_eq!(tuning.common.drag.z, Some(2.5));
+    assert_eq!(tuning.common.drag.free, None);
+    assert_eq!(tuning.cars["Mechos"].scale_size, Some(0.5));
+
+    let reloaded: Tuning = tuning.to_ron().unwrap().parse().unwrap();
+    assert_eq!(reloaded, tuning);
+
+    match "(version: 99)".parse::<Tuning>() {
+        Err(TuningError::Version { found: 99 }) => {}
+        other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn terrain_ray_steps() {
+    use vangers::config::settings::Terrain;
+
+    let terrain: Terrain = ron::de::from_str("RayTraced").unwrap();
+    assert!(terrain == Terrain::RayTraced);
+    let terrain: Terrain = ron::de::from_str("RayTracedSteps (max_steps: 16)").unwrap();
+    assert!(terrain == Terrain::RayTracedSteps { max_steps: 16 });
+}
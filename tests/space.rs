@@ -0,0 +1,242 @@
+use cgmath::{InnerSpace as _, Rotation3 as _, Transform as _};
+use vangers::space::{self, Camera, Frustum, Projection, Transform};
+
+fn perspective_camera() -> Camera {
+    Camera {
+        loc: cgmath::vec3(10.0, -20.0, 30.0),
+        rot: cgmath::Quaternion::from_angle_z(cgmath::Deg(30.0))
+            * cgmath::Quaternion::from_angle_x(cgmath::Deg(60.0)),
+        proj: Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect: 1.5,
+            near: 1.0,
+            far: 100.0,
+        }),
+        jitter: (0.0, 0.0),
+    }
+}
+
+#[test]
+fn cast_ray_center() {
+    let cam = perspective_camera();
+    let (origin, dir) = cam.cast_ray((0.0, 0.0));
+    assert!((dir - cam.dir()).magnitude() < 1e-4);
+    let offset = cgmath::vec3(origin.x, origin.y, origin.z) - cam.loc;
+    assert!(offset.normalize().dot(cam.dir()) > 0.999);
+}
+
+#[test]
+fn frustum_culling() {
+    let cam = perspective_camera();
+    let frustum = Frustum::from_camera(&cam);
+    let ahead = cam.loc + cam.dir() * 50.0;
+    assert!(frustum.contains_sphere(ahead, 1.0));
+    let behind = cam.loc - cam.dir() * 50.0;
+    assert!(!frustum.contains_sphere(behind, 1.0));
+    // straddling the near plane
+    assert!(frustum.contains_sphere(cam.loc, 2.0));
+}
+
+#[test]
+fn transform_inverse() {
+    let t = Transform {
+        disp: cgmath::vec3(1.0, -2.0, 3.0),
+        rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(40.0))
+            * cgmath::Quaternion::from_angle_z(cgmath::Deg(-70.0)),
+        scale: 2.5,
+    };
+    let identity = t.concat(&space::inverse(&t).unwrap());
+    assert!(identity.disp.magnitude() < 1e-5);
+    assert!((identity.rot.s.abs() - 1.0).abs() < 1e-5);
+    assert!((identity.scale - 1.0).abs() < 1e-5);
+
+    let local = cgmath::Point3::new(0.5, 4.0, -1.5);
+    let world = t.transform_point(local);
+    let back = space::world_to_local(&t, world).unwrap();
+    assert!((back - local).magnitude() < 1e-4);
+
+    let flat = Transform { scale: 0.0, ..t };
+    assert!(space::inverse(&flat).is_none());
+}
+
+#[test]
+fn clamp_to_level() {
+    let level = vangers::level::Level::new_test();
+    let mut cam = perspective_camera();
+    cam.loc.z = -5.0;
+    cam.clamp_to_level(&level, 2.0);
+    assert_eq!(cam.loc.z, 2.0);
+    cam.loc.z = 30.0;
+    cam.clamp_to_level(&level, 2.0);
+    assert_eq!(cam.loc.z, 30.0);
+}
+
+#[test]
+fn transform_serialization() {
+    let t = Transform {
+        disp: cgmath::vec3(1.0, -2.0, 3.5),
+        rot: cgmath::Quaternion::from_angle_x(cgmath::Deg(15.0)),
+        scale: 0.75,
+    };
+    let text = ron::ser::to_string(&t).unwrap();
+    let back: Transform = ron::de::from_str(&text).unwrap();
+    assert_eq!(back.disp, t.disp);
+    assert_eq!(back.rot, t.rot);
+    assert_eq!(back.scale, t.scale);
+
+    let cam = perspective_camera();
+    let text = ron::ser::to_string(&cam).unwrap();
+    let back: Camera = ron::de::from_str(&text).unwrap();
+    assert_eq!(back.loc, cam.loc);
+    assert_eq!(back.rot, cam.rot);
+}
+
+#[test]
+fn fly_controller() {
+    let mut cam = perspective_camera();
+    let dir = cam.dir();
+    let mut fly = space::FlyController::new(&cam, 10.0);
+    fly.update(&mut cam, 0.5);
+    assert!((cam.dir() - dir).magnitude() < 1e-4);
+
+    let start = cam.loc;
+    fly.movement.y = 1.0;
+    fly.update(&mut cam, 0.5);
+    assert!((cam.loc - (start + dir * 5.0)).magnitude() < 1e-4);
+
+    fly.boosted = true;
+    fly.update(&mut cam, 0.5);
+    assert!((cam.loc - (start + dir * 25.0)).magnitude() < 1e-3);
+
+    fly.rotate(cgmath::Rad(0.0), cgmath::Rad(10.0));
+    assert!(fly.pitch.0 < 1.51);
+}
+
+#[test]
+fn camera_jitter() {
+    let mut cam = perspective_camera();
+    let original = cam.get_view_proj();
+    cam.set_jitter((0.0, 0.0));
+    assert_eq!(cam.get_view_proj(), original);
+
+    let point = cgmath::Point3::new(15.0, -5.0, 20.0);
+    let ndc = |cam: &Camera| {
+        let clip = cam.get_view_proj() * point.to_homogeneous();
+        cgmath::vec2(clip.x / clip.w, clip.y / clip.w)
+    };
+    let base = ndc(&cam);
+    let offset = space::jitter_offset(0, 8, (800, 600));
+    cam.set_jitter(offset);
+    let shifted = ndc(&cam);
+    assert!((shifted - base - cgmath::vec2(offset.0, offset.1)).magnitude() < 1e-5);
+}
+
+#[test]
+fn halton_sequence() {
+    assert_eq!(space::halton(1, 2), 0.5);
+    assert_eq!(space::halton(2, 2), 0.25);
+    assert_eq!(space::halton(3, 2), 0.75);
+    assert!((space::halton(1, 3) - 1.0 / 3.0).abs() < 1e-6);
+    assert!((space::halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+    // the jitter stays within half a pixel
+    for frame in 0..16 {
+        let (x, y) = space::jitter_offset(frame, 8, (100, 100));
+        assert!(x.abs() <= 0.01 && y.abs() <= 0.01);
+    }
+}
+
+#[test]
+fn auto_orbit_loops() {
+    let mut orbit = space::OrbitController {
+        target: cgmath::vec3(0.0, 0.0, 0.0),
+        distance: 10.0,
+        yaw: cgmath::Rad(0.3),
+        pitch: cgmath::Rad(0.7),
+    };
+    let speed = cgmath::Rad(0.5);
+    let period = space::OrbitController::period(speed);
+    let steps = 100;
+    for _ in 0..steps {
+        orbit.auto_orbit(period / steps as f32, speed);
+    }
+    assert!((orbit.yaw.0 - 0.3).abs() < 1e-3);
+    assert_eq!(orbit.pitch, cgmath::Rad(0.7));
+    assert_eq!(orbit.distance, 10.0);
+}
+
+#[test]
+fn frame_bounds_depth_range() {
+    for &radius in &[2.0f32, 200.0] {
+        let mut cam = perspective_camera();
+        let center = cgmath::vec3(5.0, 5.0, 0.0);
+        cam.frame_bounds(center, radius);
+        let distance = (cam.loc - center).magnitude();
+        let depth = cam.depth_range();
+        assert!(depth.start > 0.0 && depth.start < distance - radius);
+        assert!(depth.end > distance + radius);
+        assert!(depth.end / depth.start < 1.0e4);
+    }
+}
+
+#[test]
+fn camera_controller_modes() {
+    let cam = perspective_camera();
+    let (loc, dir) = (cam.loc, cam.dir());
+    let target = cam.loc + cam.dir() * 20.0;
+    let mut controller = space::CameraController::new(cam, target);
+    // the orbit is derived from the camera, which stays in place
+    assert!((controller.cam.loc - loc).magnitude() < 1e-3);
+    assert!((controller.cam.dir() - dir).magnitude() < 1e-4);
+
+    let input = space::CameraInput {
+        look: (cgmath::Rad(0.0), cgmath::Rad(10.0)),
+        ..space::CameraInput::default()
+    };
+    let transform = controller.update(0.1, &input);
+    assert_eq!(transform.disp, controller.cam.loc);
+    assert!(((controller.cam.loc - target).magnitude() - 20.0).abs() < 1e-3);
+    // the pitch is clamped short of looking straight down
+    assert!(controller.cam.dir().z > -1.0 + 1e-3);
+
+    let before = (controller.cam.loc, controller.cam.dir());
+    controller.toggle_mode();
+    assert!(controller.is_flying());
+    controller.update(0.1, &space::CameraInput::default());
+    assert!((controller.cam.loc - before.0).magnitude() < 1e-3);
+    assert!((controller.cam.dir() - before.1).magnitude() < 1e-4);
+
+    controller.toggle_mode();
+    assert!(!controller.is_flying());
+    controller.update(0.1, &space::CameraInput::default());
+    assert!((controller.cam.loc - before.0).magnitude() < 1e-3);
+}
+
+#[test]
+fn follow_spring() {
+    use cgmath::One as _;
+
+    let mut cam = perspective_camera();
+    let mut follow = space::Follow {
+        transform: Transform {
+            disp: cgmath::vec3(0.0, -10.0, 5.0),
+            rot: cgmath::Quaternion::one(),
+            scale: 1.0,
+        },
+        speed: 0.0,
+        fix_z: false,
+        spring: Some(space::Spring::new(4.0)),
+    };
+    let mut target = Transform::one();
+    let goal = cgmath::vec3(0.0, -10.0, 5.0);
+    // the first update has no previous state to spring from
+    cam.follow(&target, 0.1, &mut follow);
+    assert!((cam.loc - goal).magnitude() < 1e-5);
+
+    target.disp.x = 20.0;
+    cam.follow(&target, 0.1, &mut follow);
+    assert!(cam.loc.x > 0.0 && cam.loc.x < 20.0);
+    for _ in 0..100 {
+        cam.follow(&target, 0.1, &mut follow);
+    }
+    assert!((cam.loc - goal - target.disp).magnitude() < 1e-2);
+}
@@ -0,0 +1,38 @@
+use vangers::render::body::FixedTimestep;
+
+#[test]
+fn fixed_timestep_total() {
+    let dt = 0.05;
+    let deltas = [0.016, 0.033, 0.1, 0.0, 0.251, 0.07, 0.0049, 0.5, 0.013];
+    let mut fixed = FixedTimestep::new(dt);
+    let mut steps = 0;
+    for &delta in deltas.iter() {
+        steps += fixed.advance(delta);
+        assert!(fixed.remainder() >= 0.0 && fixed.remainder() < dt);
+    }
+    let total = deltas.iter().sum::<f32>();
+    let simulated = steps as f32 * dt + fixed.remainder();
+    assert!((total - simulated).abs() < 1e-4);
+}
+
+#[test]
+fn fixed_timestep_paused() {
+    let mut fixed = FixedTimestep::new(0.05);
+    assert_eq!(fixed.advance(0.07), 1);
+    let remainder = fixed.remainder();
+
+    fixed.set_paused(true);
+    for &delta in [0.016, 0.5, 0.2].iter() {
+        assert_eq!(fixed.advance(delta), 0);
+        assert_eq!(fixed.remainder().to_bits(), remainder.to_bits());
+    }
+
+    fixed.step_once();
+    assert!(fixed.has_pending_steps());
+    assert_eq!(fixed.advance(0.3), 1);
+    assert_eq!(fixed.advance(0.3), 0);
+    assert_eq!(fixed.remainder().to_bits(), remainder.to_bits());
+
+    fixed.set_paused(false);
+    assert_eq!(fixed.advance(0.04), 1);
+}
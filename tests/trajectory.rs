@@ -0,0 +1,46 @@
+use vangers::{
+    render::{
+        body::GpuBody,
+        trajectory::{Sample, Trajectory, TrajectoryPlayer, TrajectoryRecorder},
+    },
+    space::Transform,
+};
+
+use cgmath::One as _;
+
+fn at(time: f32, x: f32) -> Sample {
+    Sample {
+        time,
+        transform: Transform {
+            disp: cgmath::vec3(x, 0.0, 0.0),
+            rot: cgmath::Quaternion::one(),
+            scale: 1.0,
+        },
+    }
+}
+
+#[test]
+fn trajectory_playback() {
+    let track = Trajectory {
+        samples: vec![at(1.0, 0.0), at(2.0, 10.0), at(4.0, 20.0)],
+    };
+    let mut player = TrajectoryPlayer::new(track);
+    assert_eq!(player.advance(0.5).unwrap().disp.x, 5.0);
+    assert_eq!(player.advance(1.5).unwrap().disp.x, 15.0);
+    assert!(!player.is_done());
+    assert_eq!(player.advance(5.0).unwrap().disp.x, 20.0);
+    assert!(player.is_done());
+}
+
+#[test]
+fn trajectory_record_rest() {
+    let mut recorder = TrajectoryRecorder::new(&[GpuBody::ZERO]);
+    for &x in &[0.0, 0.0, 0.0, 10.0] {
+        recorder.record_with(1.0, |_| Some(at(0.0, x).transform));
+    }
+    let tracks = recorder.finish();
+    assert_eq!(tracks[0].samples.len(), 4);
+    // the body rests until the last frame
+    assert_eq!(tracks[0].sample(2.5).unwrap().disp.x, 0.0);
+    assert_eq!(tracks[0].sample(3.5).unwrap().disp.x, 5.0);
+}